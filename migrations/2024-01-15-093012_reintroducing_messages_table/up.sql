@@ -0,0 +1,12 @@
+CREATE TABLE messages (
+    message_id SERIAL PRIMARY KEY,
+    chat_room_id INTEGER NOT NULL REFERENCES chat_rooms(chat_room_id) ON DELETE CASCADE,
+    sender_id INTEGER REFERENCES users(user_id) ON DELETE SET NULL,
+    payload BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    parent_message_id INTEGER REFERENCES messages(message_id) ON DELETE SET NULL,
+    edited_at TIMESTAMP,
+    deleted_at TIMESTAMP
+);
+
+CREATE INDEX messages_chat_room_id_message_id_idx ON messages (chat_room_id, message_id);
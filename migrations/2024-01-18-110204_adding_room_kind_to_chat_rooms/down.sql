@@ -0,0 +1,13 @@
+UPDATE chat_rooms
+SET room_description = 'private room'
+WHERE room_kind = 'direct';
+
+DROP INDEX chat_rooms_direct_users_idx;
+
+ALTER TABLE chat_rooms
+DROP CONSTRAINT chat_rooms_direct_users_check,
+DROP COLUMN direct_user_high,
+DROP COLUMN direct_user_low,
+DROP COLUMN room_kind;
+
+DROP TYPE room_kind;
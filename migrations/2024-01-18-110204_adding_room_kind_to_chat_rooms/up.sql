@@ -0,0 +1,45 @@
+CREATE TYPE room_kind AS ENUM ('direct', 'group', 'channel');
+
+ALTER TABLE chat_rooms
+ADD COLUMN room_kind room_kind NOT NULL DEFAULT 'group',
+ADD COLUMN direct_user_low INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
+ADD COLUMN direct_user_high INTEGER REFERENCES users(user_id) ON DELETE CASCADE;
+
+-- every room that was flagged with the "private room" sentinel is a direct room
+UPDATE chat_rooms
+SET room_kind = 'direct'
+WHERE room_description = 'private room';
+
+-- the user pair is taken from the participants, when the same pair has more than one
+-- direct room only the oldest one gets it so the unique index below can be created
+UPDATE chat_rooms
+SET direct_user_low = pairs.user_low,
+    direct_user_high = pairs.user_high,
+    room_name = 'direct-' || pairs.user_low || '-' || pairs.user_high,
+    room_description = ''
+FROM (
+    SELECT DISTINCT ON (user_low, user_high) chat_room_id, user_low, user_high
+    FROM (
+        SELECT chat_room_participants.chat_room_id,
+               MIN(chat_room_participants.user_id) AS user_low,
+               MAX(chat_room_participants.user_id) AS user_high
+        FROM chat_room_participants
+        JOIN chat_rooms ON chat_rooms.chat_room_id = chat_room_participants.chat_room_id
+        WHERE chat_rooms.room_kind = 'direct'
+        GROUP BY chat_room_participants.chat_room_id
+        HAVING COUNT(DISTINCT chat_room_participants.user_id) = 2
+    ) AS direct_rooms
+    ORDER BY user_low, user_high, chat_room_id
+) AS pairs
+WHERE chat_rooms.chat_room_id = pairs.chat_room_id;
+
+ALTER TABLE chat_rooms
+ADD CONSTRAINT chat_rooms_direct_users_check
+CHECK (
+    (room_kind = 'direct' OR (direct_user_low IS NULL AND direct_user_high IS NULL))
+    AND direct_user_low < direct_user_high
+);
+
+CREATE UNIQUE INDEX chat_rooms_direct_users_idx
+ON chat_rooms (direct_user_low, direct_user_high)
+WHERE room_kind = 'direct';
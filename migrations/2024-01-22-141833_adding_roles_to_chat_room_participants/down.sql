@@ -0,0 +1,13 @@
+DROP INDEX chat_room_participants_owner_idx;
+
+ALTER TABLE chat_room_participants
+ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE chat_room_participants
+SET is_admin = TRUE
+WHERE role = 'owner';
+
+ALTER TABLE chat_room_participants
+DROP COLUMN role;
+
+DROP TYPE participant_role;
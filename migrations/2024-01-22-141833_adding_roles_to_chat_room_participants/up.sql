@@ -0,0 +1,19 @@
+CREATE TYPE participant_role AS ENUM ('owner', 'admin', 'moderator', 'member', 'read_only');
+
+ALTER TABLE chat_room_participants
+ADD COLUMN role participant_role NOT NULL DEFAULT 'member';
+
+-- the single admin of a group was its owner
+UPDATE chat_room_participants
+SET role = 'owner'
+FROM chat_rooms
+WHERE chat_rooms.chat_room_id = chat_room_participants.chat_room_id
+  AND chat_rooms.room_kind = 'group'
+  AND chat_room_participants.is_admin = TRUE;
+
+ALTER TABLE chat_room_participants
+DROP COLUMN is_admin;
+
+CREATE UNIQUE INDEX chat_room_participants_owner_idx
+ON chat_room_participants (chat_room_id)
+WHERE role = 'owner';
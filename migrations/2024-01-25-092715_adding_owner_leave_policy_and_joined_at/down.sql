@@ -0,0 +1,7 @@
+ALTER TABLE chat_room_participants
+DROP COLUMN joined_at;
+
+ALTER TABLE chat_rooms
+DROP COLUMN owner_leave_policy;
+
+DROP TYPE owner_leave_policy;
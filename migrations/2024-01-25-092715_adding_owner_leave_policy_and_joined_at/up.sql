@@ -0,0 +1,9 @@
+CREATE TYPE owner_leave_policy AS ENUM ('transfer', 'delete_room');
+
+ALTER TABLE chat_rooms
+ADD COLUMN owner_leave_policy owner_leave_policy NOT NULL DEFAULT 'transfer';
+
+-- the rows that existed before this column all get the migration time, the participant id
+-- breaks the tie when picking the longest standing member
+ALTER TABLE chat_room_participants
+ADD COLUMN joined_at TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,4 @@
+DROP TABLE group_audit_log;
+DROP TABLE join_requests;
+DROP TYPE join_request_status;
+DROP TABLE group_invites;
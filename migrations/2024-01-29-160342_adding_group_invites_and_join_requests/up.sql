@@ -0,0 +1,45 @@
+CREATE TABLE group_invites (
+    invite_id SERIAL PRIMARY KEY,
+    chat_room_id INTEGER NOT NULL REFERENCES chat_rooms(chat_room_id) ON DELETE CASCADE,
+    created_by INTEGER REFERENCES users(user_id) ON DELETE SET NULL,
+    -- only the sha256 of the token is kept, the token itself is shown once on creation
+    token_hash BYTEA NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    max_uses INTEGER CHECK (max_uses > 0),
+    use_count INTEGER NOT NULL DEFAULT 0,
+    requires_approval BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX group_invites_chat_room_id_idx ON group_invites (chat_room_id);
+
+CREATE TYPE join_request_status AS ENUM ('pending', 'approved', 'rejected');
+
+CREATE TABLE join_requests (
+    join_request_id SERIAL PRIMARY KEY,
+    chat_room_id INTEGER NOT NULL REFERENCES chat_rooms(chat_room_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    invite_id INTEGER REFERENCES group_invites(invite_id) ON DELETE SET NULL,
+    status join_request_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    decided_by INTEGER REFERENCES users(user_id) ON DELETE SET NULL,
+    decided_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX join_requests_pending_idx
+ON join_requests (chat_room_id, user_id)
+WHERE status = 'pending';
+
+-- no foreign keys so the trail outlives the rooms and users it mentions
+CREATE TABLE group_audit_log (
+    audit_id SERIAL PRIMARY KEY,
+    chat_room_id INTEGER NOT NULL,
+    actor_id INTEGER,
+    action VARCHAR(64) NOT NULL,
+    target_user_id INTEGER,
+    invite_id INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX group_audit_log_chat_room_id_idx ON group_audit_log (chat_room_id, audit_id);
@@ -0,0 +1,3 @@
+DROP TABLE user_blocks;
+DROP TABLE contact_requests;
+DROP TYPE contact_request_status;
@@ -0,0 +1,32 @@
+CREATE TYPE contact_request_status AS ENUM ('pending', 'accepted', 'declined', 'expired');
+
+CREATE TABLE contact_requests (
+    contact_request_id SERIAL PRIMARY KEY,
+    requester_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    addressee_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    -- used as the pubkey of the p2p chat room created on acceptance
+    chat_room_pubkey BYTEA NOT NULL,
+    status contact_request_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    responded_at TIMESTAMP,
+    chat_room_id INTEGER REFERENCES chat_rooms(chat_room_id) ON DELETE SET NULL,
+    CHECK (requester_id <> addressee_id)
+);
+
+CREATE UNIQUE INDEX contact_requests_pending_idx
+ON contact_requests (requester_id, addressee_id)
+WHERE status = 'pending';
+
+CREATE INDEX contact_requests_addressee_id_idx ON contact_requests (addressee_id);
+
+CREATE TABLE user_blocks (
+    block_id SERIAL PRIMARY KEY,
+    blocker_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    blocked_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX user_blocks_blocked_id_idx ON user_blocks (blocked_id);
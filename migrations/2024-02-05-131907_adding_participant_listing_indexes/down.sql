@@ -0,0 +1,2 @@
+DROP INDEX chat_room_participants_chat_room_id_participant_id_idx;
+DROP INDEX chat_room_participants_user_id_chat_room_id_idx;
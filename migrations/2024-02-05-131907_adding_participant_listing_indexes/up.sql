@@ -0,0 +1,5 @@
+-- the room listings of a user walk the participants by user id, the participant listings by room
+CREATE INDEX chat_room_participants_user_id_chat_room_id_idx
+    ON chat_room_participants (user_id, chat_room_id);
+CREATE INDEX chat_room_participants_chat_room_id_participant_id_idx
+    ON chat_room_participants (chat_room_id, participant_id);
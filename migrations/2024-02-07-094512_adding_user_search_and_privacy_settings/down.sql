@@ -0,0 +1,3 @@
+DROP INDEX user_profiles_bio_trgm_idx;
+DROP INDEX users_username_trgm_idx;
+DROP TABLE user_privacy_settings;
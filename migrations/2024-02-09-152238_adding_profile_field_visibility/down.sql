@@ -0,0 +1,7 @@
+ALTER TABLE user_privacy_settings
+    DROP COLUMN avatar_visibility,
+    DROP COLUMN bio_visibility,
+    DROP COLUMN phone_visibility,
+    DROP COLUMN email_visibility;
+
+DROP TYPE profile_visibility;
@@ -0,0 +1,8 @@
+CREATE TYPE profile_visibility AS ENUM ('everyone', 'contacts', 'nobody');
+
+-- contacts are the users sharing a p2p chat room with the owner of the settings
+ALTER TABLE user_privacy_settings
+    ADD COLUMN email_visibility profile_visibility NOT NULL DEFAULT 'nobody',
+    ADD COLUMN phone_visibility profile_visibility NOT NULL DEFAULT 'nobody',
+    ADD COLUMN bio_visibility profile_visibility NOT NULL DEFAULT 'everyone',
+    ADD COLUMN avatar_visibility profile_visibility NOT NULL DEFAULT 'everyone';
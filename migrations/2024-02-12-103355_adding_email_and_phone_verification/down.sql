@@ -0,0 +1,7 @@
+DROP TABLE verification_codes;
+DROP TYPE verification_channel;
+
+ALTER TABLE users
+    DROP COLUMN phone_verified_at,
+    DROP COLUMN email_verified_at,
+    ALTER COLUMN phone_number TYPE VARCHAR(15);
@@ -0,0 +1,23 @@
+-- e.164 numbers are a `+` followed by up to 15 digits
+ALTER TABLE users
+    ALTER COLUMN phone_number TYPE VARCHAR(16),
+    ADD COLUMN email_verified_at TIMESTAMP,
+    ADD COLUMN phone_verified_at TIMESTAMP;
+
+CREATE TYPE verification_channel AS ENUM ('email', 'phone');
+
+CREATE TABLE verification_codes (
+    verification_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    channel verification_channel NOT NULL,
+    -- the email or phone number the code was sent to
+    target VARCHAR(255) NOT NULL,
+    code_hash BYTEA NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    consumed_at TIMESTAMP
+);
+
+CREATE INDEX verification_codes_user_id_channel_idx
+    ON verification_codes (user_id, channel, verification_id);
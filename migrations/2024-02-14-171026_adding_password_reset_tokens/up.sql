@@ -0,0 +1,12 @@
+CREATE TABLE password_reset_tokens (
+    reset_token_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    -- sha256 of the token sent to the user, the token itself is never stored
+    token_hash BYTEA NOT NULL UNIQUE,
+    channel verification_channel NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id, created_at);
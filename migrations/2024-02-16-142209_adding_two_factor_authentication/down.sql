@@ -0,0 +1,4 @@
+ALTER TABLE sessions DROP COLUMN mfa_verified_at;
+DROP TABLE mfa_login_challenges;
+DROP TABLE mfa_recovery_codes;
+DROP TABLE user_mfa;
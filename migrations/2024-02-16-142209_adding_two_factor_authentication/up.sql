@@ -0,0 +1,35 @@
+CREATE TABLE user_mfa (
+    user_id INTEGER PRIMARY KEY REFERENCES users(user_id) ON DELETE CASCADE,
+    -- raw totp secret, the user gets it base32 encoded inside the provisioning uri
+    totp_secret BYTEA NOT NULL,
+    -- NULL until the first code is confirmed, a pending enrollment doesn't protect anything
+    enabled_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- the last accepted totp time step, a code can't be replayed inside its window
+    last_used_step BIGINT,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMP
+);
+
+CREATE TABLE mfa_recovery_codes (
+    recovery_code_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    -- sha256 of the code, the codes are only shown once
+    code_hash BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    used_at TIMESTAMP
+);
+
+CREATE INDEX mfa_recovery_codes_user_id_idx ON mfa_recovery_codes (user_id);
+
+CREATE TABLE mfa_login_challenges (
+    challenge_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    challenge_hash BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    consumed_at TIMESTAMP
+);
+
+-- when the session last passed a second factor, the wallet operations want it recent
+ALTER TABLE sessions ADD COLUMN mfa_verified_at TIMESTAMP;
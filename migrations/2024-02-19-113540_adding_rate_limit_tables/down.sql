@@ -0,0 +1,2 @@
+DROP TABLE rate_limit_quotas;
+DROP TABLE rate_limit_buckets;
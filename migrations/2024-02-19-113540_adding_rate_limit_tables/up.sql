@@ -0,0 +1,13 @@
+-- only used with `rate_limit_backend = "postgres"`, it lets several servers share the limits
+CREATE TABLE rate_limit_buckets (
+    bucket_key VARCHAR(255) PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE rate_limit_quotas (
+    quota_key VARCHAR(255) NOT NULL,
+    quota_day DATE NOT NULL,
+    used INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (quota_key, quota_day)
+);
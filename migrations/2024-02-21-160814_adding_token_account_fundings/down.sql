@@ -0,0 +1,2 @@
+DROP TABLE token_account_fundings;
+DROP TYPE funding_status;
@@ -0,0 +1,24 @@
+CREATE TYPE funding_status AS ENUM ('unfunded', 'submitted', 'confirmed');
+
+-- one row per token account made by /create-token-account, the funding signature is written
+-- before the transaction goes out so a retried request can't fund the account twice
+CREATE TABLE token_account_fundings (
+    funding_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    wallet_address VARCHAR(44) NOT NULL,
+    token_mint_address VARCHAR(44) NOT NULL,
+    token_program_id VARCHAR(44) NOT NULL,
+    token_account_address VARCHAR(44) NOT NULL,
+    -- false when the account was already on chain
+    token_account_created BOOLEAN NOT NULL DEFAULT FALSE,
+    token_account_signature VARCHAR(88),
+    funding_status funding_status NOT NULL DEFAULT 'unfunded',
+    funding_signature VARCHAR(88),
+    -- the blockhash the funding transaction was built on, it can't land once this expires
+    funding_blockhash VARCHAR(44),
+    -- held by the request working on the row
+    locked_until TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (wallet_address, token_mint_address, token_program_id)
+);
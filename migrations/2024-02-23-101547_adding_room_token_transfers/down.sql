@@ -0,0 +1,2 @@
+DROP TABLE room_token_transfers;
+DROP TYPE token_transfer_status;
@@ -0,0 +1,25 @@
+CREATE TYPE token_transfer_status AS ENUM ('pending', 'confirmed', 'failed');
+
+-- spl token transfers between the participants of a chat room. the server only builds the
+-- transaction, the sender signs and submits it and then reports the signature back
+CREATE TABLE room_token_transfers (
+    transfer_id SERIAL PRIMARY KEY,
+    chat_room_id INTEGER NOT NULL REFERENCES chat_rooms(chat_room_id) ON DELETE CASCADE,
+    sender_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    recipient_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    sender_wallet_address VARCHAR(44) NOT NULL,
+    recipient_wallet_address VARCHAR(44) NOT NULL,
+    token_mint_address VARCHAR(44) NOT NULL,
+    token_program_id VARCHAR(44) NOT NULL,
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    decimals SMALLINT NOT NULL,
+    -- the serialized message handed out for signing, the reported signature is checked against it
+    transaction_message BYTEA NOT NULL,
+    status token_transfer_status NOT NULL DEFAULT 'pending',
+    signature VARCHAR(88) UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    confirmed_at TIMESTAMP
+);
+
+CREATE INDEX room_token_transfers_chat_room_id_idx
+    ON room_token_transfers (chat_room_id, transfer_id);
@@ -0,0 +1,158 @@
+use crate::auth_lib::authenticate_access_token;
+use crate::db_models::QUsers;
+use crate::db_pool::{PgPool, PgPooledConnection};
+use crate::mfa_lib::ensure_fresh_mfa;
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+// why a guard rejected the request, read back by the catchers to fill the error message
+pub struct GuardFailure(pub String);
+
+fn fail<S>(request: &Request, status: Status, reason: String) -> request::Outcome<S, String> {
+    request.local_cache(|| GuardFailure(reason.clone()));
+    Outcome::Failure((status, reason))
+}
+
+// the connection a guard already checked out, left here for the next guard and finally the
+// handler's `DbConn` so a request never holds more than one. the guards resolve in the order of
+// the handler arguments, which is why `auth` comes before `conn` everywhere
+struct ParkedConn(Mutex<Option<PgPooledConnection>>);
+
+fn parked_conn<'a>(request: &'a Request) -> &'a ParkedConn {
+    request.local_cache(|| ParkedConn(Mutex::new(None)))
+}
+
+fn park_conn(request: &Request, conn: PgPooledConnection) {
+    *parked_conn(request).0.lock().unwrap() = Some(conn);
+}
+
+// the parked connection if there is one, a new one from the managed `PgPool` otherwise
+fn check_out_conn(request: &Request) -> Result<PgPooledConnection, (Status, String)> {
+    if let Some(res) = parked_conn(request).0.lock().unwrap().take() {
+        return Ok(res);
+    }
+    let pool = match request.guard::<State<PgPool>>() {
+        Outcome::Success(res) => res,
+        _ => {
+            return Err((
+                Status::InternalServerError,
+                "database pool isn't managed".to_owned(),
+            ))
+        }
+    };
+    // `get` waits up to `connection_timeout` for a free connection before giving up
+    match pool.get() {
+        Ok(res) => Ok(res),
+        Err(e) => Err((
+            Status::ServiceUnavailable,
+            format!("no database connection available due to \n {}", e),
+        )),
+    }
+}
+
+// a connection checked out of the managed `PgPool`, it goes back to the pool once the request is done
+pub struct DbConn(pub PgPooledConnection);
+
+impl<'a, 'r> FromRequest<'a, 'r> for DbConn {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match check_out_conn(request) {
+            Ok(res) => Outcome::Success(DbConn(res)),
+            Err((status, reason)) => fail(request, status, reason),
+        }
+    }
+}
+
+impl Deref for DbConn {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for DbConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+// the caller resolved from the `Authorization: Bearer <access token>` header
+pub struct AuthUser {
+    pub user: QUsers,
+    pub session_id: i32,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthUser {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let access_token = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|res| res.strip_prefix("Bearer "))
+        {
+            Some(res) => res.trim(),
+            None => {
+                return fail(
+                    request,
+                    Status::Unauthorized,
+                    "missing bearer access token".to_owned(),
+                )
+            }
+        };
+
+        let mut conn = match check_out_conn(request) {
+            Ok(res) => res,
+            Err((status, reason)) => return fail(request, status, reason),
+        };
+        let res = authenticate_access_token(&mut conn, access_token);
+        park_conn(request, conn);
+        match res {
+            Ok((user, session)) => Outcome::Success(AuthUser {
+                user,
+                session_id: session.session_id,
+            }),
+            Err(e) => fail(request, Status::Unauthorized, format!("{}", e)),
+        }
+    }
+}
+
+// an `AuthUser` whose session passed the second factor in the last `MFA_FRESHNESS_SECS`,
+// for the operations that move or unlink funds
+pub struct FreshMfaUser {
+    pub user: QUsers,
+    pub session_id: i32,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for FreshMfaUser {
+    type Error = String;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let auth = match request.guard::<AuthUser>() {
+            Outcome::Success(res) => res,
+            Outcome::Failure(e) => return Outcome::Failure(e),
+            Outcome::Forward(_) => return Outcome::Forward(()),
+        };
+        // the one `AuthUser` parked
+        let mut conn = match check_out_conn(request) {
+            Ok(res) => res,
+            Err((status, reason)) => return fail(request, status, reason),
+        };
+        let res = ensure_fresh_mfa(&mut conn, auth.user.user_id, auth.session_id);
+        park_conn(request, conn);
+        match res {
+            Ok(_) => Outcome::Success(FreshMfaUser {
+                user: auth.user,
+                session_id: auth.session_id,
+            }),
+            // the envelope message, so a database failure isn't spelled out to the client
+            Err(e) => fail(request, e.status(), e.envelope().error.message),
+        }
+    }
+}
@@ -1,7 +1,15 @@
 // use rocket::data::FromDataSimple;
 use rocket::*;
 
+use crate::db_models::{
+    ChatRoomParticipants, FundingStatus, ParticipantRole, ProfileVisibility, QChatRooms,
+    QContactRequests, QGroupInvites, QJoinRequests, QRoomTokenTransfers, QUserPrivacySettings,
+    UserPrivacySettings, VerificationChannel,
+};
+use crate::errors::ChatuzaError;
+use chrono::{DateTime, NaiveDateTime};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct NewUserIN {
@@ -15,7 +23,6 @@ pub struct NewUserIN {
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct UpdatedUserCreditsIN {
-    pub username_out: String,
     pub username_in: String,
     pub email_in: String,
     pub password_in: String,
@@ -24,15 +31,65 @@ pub struct UpdatedUserCreditsIN {
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct UpdatedUserProfileIN {
-    pub username_in: String,
     pub bio_in: String,
     pub profile_picture_in: String,
 }
 
-#[derive(FromForm, Debug, Serialize)]
-pub struct SinglePostUsername {
+#[derive(FromForm, Debug)]
+pub struct LoginIN {
     pub username_in: String,
+    pub password_in: String,
+}
+
+#[derive(FromForm, Debug)]
+pub struct RefreshTokenIN {
+    pub refresh_token_in: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AuthTokensResponse {
+    pub access_token: String,
+    pub access_token_expires_at: i64,
+    pub refresh_token: String,
+    pub refresh_token_expires_at: i64,
+}
+
+// without 2fa the tokens come back right away (flattened, like before), with it only a
+// challenge token that has to be answered on /login/mfa
+#[derive(Serialize, Debug)]
+pub struct LoginResponse {
+    pub mfa_required: bool,
+    #[serde(flatten)]
+    pub tokens: Option<AuthTokensResponse>,
+    pub mfa_challenge_token: Option<String>,
+    pub mfa_challenge_expires_at: Option<i64>,
+}
+
+// code_in is either a 6 digit totp code or one of the recovery codes
+#[derive(FromForm, Debug)]
+pub struct MfaLoginIN {
+    pub mfa_challenge_token_in: String,
+    pub code_in: String,
+}
+
+#[derive(FromForm, Debug)]
+pub struct MfaCodeIN {
+    pub code_in: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MfaEnrollmentResponse {
+    // base32, for the apps that can't scan the uri
+    pub secret: String,
+    pub provisioning_uri: String,
 }
+
+// the plain codes are only ever returned here
+#[derive(Serialize, Debug)]
+pub struct MfaRecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
 #[derive(FromForm, Debug, Serialize)]
 pub struct FundWalletIn {
     pub wallet_address: String,
@@ -43,14 +100,47 @@ pub struct CreateTokenAccount {
     pub wallet_address: String,
     pub token_mint_address: String,
     pub token_program_id: String,
-    pub lbh: String,
+    // ignored, the server signs with a blockhash it fetched itself. still accepted so the forms
+    // of older clients keep parsing
+    pub lbh: Option<String>,
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateTokenAccountResponse {
+    pub token_account_address: String,
+    // false when the account already existed
+    pub token_account_created: bool,
+    pub token_account_signature: Option<String>,
+    pub funding_status: FundingStatus,
+    pub funding_signature: Option<String>,
+    // the transactions sent by this request, empty when a retry found everything done
     pub signatures: Vec<String>,
 }
 
-// delete user only takes one arg //
+#[derive(FromForm, Debug, Serialize)]
+pub struct RoomTokenTransferIN {
+    pub chat_room_id_in: i32,
+    pub recipient_user_id_in: i32,
+    pub token_mint_address_in: String,
+    pub token_program_id_in: String,
+    // in the mint's base units
+    pub amount_in: u64,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct ConfirmRoomTokenTransferIN {
+    pub transfer_id_in: i32,
+    pub signature_in: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RoomTokenTransferResponse {
+    pub transfer: QRoomTokenTransfers,
+    // the unsigned transaction, bincode serialized then base64, for the sender's wallet to sign
+    // and submit
+    pub transaction: String,
+}
+
+// delete user only takes the authenticated user //
 
 // get user with username takes only one argument //
 
@@ -60,13 +150,11 @@ pub struct CreateTokenAccountResponse {
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct NewP2PChatRoomIN {
-    pub requestor_username_in: String,
     pub acceptor_username_in: String,
     pub chat_room_pubkey_in: String,
 }
 #[derive(FromForm, Debug, Serialize)]
 pub struct DeleteP2PChatRoomIN {
-    pub remover_username_in: String,
     pub contact_username_in: String,
 }
 
@@ -74,7 +162,6 @@ pub struct DeleteP2PChatRoomIN {
 pub struct NewGroupChatRoomIN {
     pub room_name_in: String,
     pub room_description_in: String,
-    pub group_owner_username_in: String,
     pub group_members_in: Vec<String>,
     pub chat_room_pubkey: String,
 }
@@ -82,7 +169,6 @@ pub struct NewGroupChatRoomIN {
 #[derive(FromForm, Debug, Serialize)]
 pub struct DeleteGroupChatRoomIN {
     pub chat_room_name_in: String,
-    pub remover_username_in: String,
 }
 
 #[derive(FromForm, Debug, Serialize)]
@@ -90,28 +176,350 @@ pub struct UpdatedGroupChatRoomInfoIN {
     pub old_chat_room_name_in: String,
     pub room_name_in: String,
     pub room_description_in: String,
-    pub editor_username_in: String,
 }
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct NewGroupChatParticipantIN {
     pub username_in: String,
     pub chat_room_name_in: String,
-    pub adder_username_in: String,
 }
 
 #[derive(FromForm, Debug, Serialize)]
 pub struct GroupChatParticipantToRemoveIN {
     pub chat_room_name_in: String,
     pub username_in: String,
-    pub remover_username_in: String,
+}
+
+// role_in is one of admin, moderator, member or read_only
+#[derive(FromForm, Debug, Serialize)]
+pub struct ChangeParticipantRoleIN {
+    pub chat_room_name_in: String,
+    pub username_in: String,
+    pub role_in: String,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct TransferGroupOwnershipIN {
+    pub chat_room_name_in: String,
+    pub new_owner_username_in: String,
+}
+
+// policy_in is either transfer or delete_room
+#[derive(FromForm, Debug, Serialize)]
+pub struct OwnerLeavePolicyIN {
+    pub chat_room_name_in: String,
+    pub policy_in: String,
+}
+
+// max_uses_in left out means unlimited uses
+#[derive(FromForm, Debug, Serialize)]
+pub struct NewGroupInviteIN {
+    pub chat_room_id_in: i32,
+    pub expires_in_secs_in: i64,
+    pub max_uses_in: Option<i32>,
+    pub requires_approval_in: bool,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct RevokeGroupInviteIN {
+    pub invite_id_in: i32,
+}
+
+#[derive(FromForm, Debug)]
+pub struct RedeemGroupInviteIN {
+    pub invite_token_in: String,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct JoinRequestDecisionIN {
+    pub join_request_id_in: i32,
+}
+
+// the token is only handed out here, the db keeps its hash
+#[derive(Serialize, Debug)]
+pub struct GroupInviteResponse {
+    pub invite: QGroupInvites,
+    pub invite_token: String,
+}
+
+// exactly one of participant and join_request is set, depending on the invite needing an approval
+#[derive(Serialize, Debug)]
+pub struct RedeemInviteResponse {
+    pub participant: Option<ChatRoomParticipants>,
+    pub join_request: Option<QJoinRequests>,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct ContactRequestDecisionIN {
+    pub contact_request_id_in: i32,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct BlockUserIN {
+    pub username_in: String,
+}
+
+// chat_room is only set when the request got accepted
+#[derive(Serialize, Debug)]
+pub struct ContactRequestResponse {
+    pub contact_request: QContactRequests,
+    pub chat_room: Option<QChatRooms>,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct UpdatePrivacySettingsIN {
+    pub discoverable_in: Option<bool>,
+    // everyone, contacts or nobody, the missing fields keep their current value
+    pub email_visibility_in: Option<String>,
+    pub phone_visibility_in: Option<String>,
+    pub bio_visibility_in: Option<String>,
+    pub avatar_visibility_in: Option<String>,
+}
+
+impl UpdatePrivacySettingsIN {
+    // merges the submitted fields into the current settings
+    pub fn apply_to(
+        &self,
+        current: &QUserPrivacySettings,
+    ) -> Result<UserPrivacySettings, ChatuzaError> {
+        let parse = |field: &Option<String>, current_value: ProfileVisibility| match field {
+            Some(res) => match ProfileVisibility::from_str(res.as_str()) {
+                Ok(res) => Ok(res),
+                Err(e) => Err(ChatuzaError::Validation(e)),
+            },
+            None => Ok(current_value),
+        };
+
+        Ok(UserPrivacySettings {
+            user_id: current.user_id,
+            discoverable: self.discoverable_in.unwrap_or(current.discoverable),
+            email_visibility: parse(&self.email_visibility_in, current.email_visibility)?,
+            phone_visibility: parse(&self.phone_visibility_in, current.phone_visibility)?,
+            bio_visibility: parse(&self.bio_visibility_in, current.bio_visibility)?,
+            avatar_visibility: parse(&self.avatar_visibility_in, current.avatar_visibility)?,
+        })
+    }
+}
+
+// what other users get to see, the fields hidden from the viewer by the privacy settings are
+// null and the password never leaves the server
+#[derive(Serialize, Debug)]
+pub struct PublicUserProfile {
+    pub user_id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub phone_number: Option<String>,
+    pub bio: Option<String>,
+    pub profile_picture: Option<String>,
+}
+
+// channel_in is "email" or "phone"
+#[derive(FromForm, Debug, Serialize)]
+pub struct StartVerificationIN {
+    pub channel_in: String,
+}
+
+#[derive(FromForm, Debug)]
+pub struct ConfirmVerificationIN {
+    pub channel_in: String,
+    pub code_in: String,
+}
+
+// identifier_in is a username, an email or a phone number, channel_in defaults to "email"
+#[derive(FromForm, Debug, Serialize)]
+pub struct ForgotPasswordIN {
+    pub identifier_in: String,
+    pub channel_in: Option<String>,
+}
+
+#[derive(FromForm, Debug)]
+pub struct ResetPasswordIN {
+    pub reset_token_in: String,
+    pub new_password_in: String,
+}
+
+// the code itself only goes out through the email or the text message
+#[derive(Serialize, Debug)]
+pub struct VerificationStartedResponse {
+    pub channel: VerificationChannel,
+    pub expires_at: NaiveDateTime,
+    pub attempts_left: i32,
 }
 
 // get functions are getting only one argument
 
+#[derive(FromForm, Debug, Serialize)]
+pub struct NewMessageIN {
+    pub chat_room_id_in: i32,
+    pub payload_in: String,
+    pub parent_message_id_in: Option<i32>,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct EditMessageIN {
+    pub message_id_in: i32,
+    pub payload_in: String,
+}
+
+#[derive(FromForm, Debug, Serialize)]
+pub struct DeleteMessageIN {
+    pub message_id_in: i32,
+}
+
 #[derive(FromForm, Debug, Serialize)]
 pub struct NewWalletIn {
-    pub username_in: String,
     pub wallet_addr_in: String,
     pub wallet_backup_in: String,
 }
+
+// -- pagination -- //
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+// forward walks the ids up from the cursor, backward walks them down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageDirection {
+    Forward,
+    Backward,
+}
+
+impl FromStr for PageDirection {
+    type Err = String;
+
+    fn from_str(direction: &str) -> Result<Self, Self::Err> {
+        match direction {
+            "forward" | "asc" => Ok(PageDirection::Forward),
+            "backward" | "desc" => Ok(PageDirection::Backward),
+            _ => Err(format!("{} isn't a page direction", direction)),
+        }
+    }
+}
+
+// the cursor is the id of the last row of the previous page, none starts from the edge
+#[derive(Debug, Clone, Copy)]
+pub struct PageRequest {
+    pub cursor: Option<i32>,
+    pub limit: i64,
+    pub direction: PageDirection,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest {
+            cursor: None,
+            limit: DEFAULT_PAGE_SIZE,
+            direction: PageDirection::Forward,
+        }
+    }
+}
+
+impl PageRequest {
+    // builds the page from the raw query params, the limit is clamped to 1..=MAX_PAGE_SIZE
+    pub fn new(
+        cursor: Option<i32>,
+        limit: Option<i64>,
+        direction: Option<String>,
+    ) -> Result<Self, ChatuzaError> {
+        let direction = match direction {
+            Some(res) => match PageDirection::from_str(res.as_str()) {
+                Ok(res) => res,
+                Err(e) => return Err(ChatuzaError::Validation(e)),
+            },
+            None => PageDirection::Forward,
+        };
+        Ok(PageRequest {
+            cursor,
+            limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+            direction,
+        })
+    }
+}
+
+// next_cursor is none once the last page got returned
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<i32>,
+}
+
+impl<T> Page<T> {
+    // the queries load one row more than the limit to know if another page follows
+    pub fn from_rows(mut rows: Vec<T>, page: &PageRequest, cursor_of: impl Fn(&T) -> i32) -> Self {
+        let has_more = rows.len() as i64 > page.limit;
+        rows.truncate(page.limit as usize);
+        let next_cursor = match rows.last() {
+            Some(res) if has_more => Some(cursor_of(res)),
+            _ => None,
+        };
+        Page {
+            items: rows,
+            next_cursor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatRoomListFilter {
+    pub name_prefix: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParticipantListFilter {
+    pub role: Option<ParticipantRole>,
+    pub joined_after: Option<NaiveDateTime>,
+}
+
+impl ParticipantListFilter {
+    // joined_after is a unix timestamp in seconds
+    pub fn new(role: Option<String>, joined_after: Option<i64>) -> Result<Self, ChatuzaError> {
+        let role = match role {
+            Some(res) => match ParticipantRole::from_str(res.as_str()) {
+                Ok(res) => Some(res),
+                Err(e) => return Err(ChatuzaError::Validation(e)),
+            },
+            None => None,
+        };
+        let joined_after = match joined_after {
+            Some(res) => match DateTime::from_timestamp(res, 0) {
+                Some(res) => Some(res.naive_utc()),
+                None => {
+                    return Err(ChatuzaError::Validation(format!(
+                        "{} isn't a valid timestamp",
+                        res
+                    )))
+                }
+            },
+            None => None,
+        };
+        Ok(ParticipantListFilter { role, joined_after })
+    }
+}
+
+// -- error envelope -- //
+
+// every failed request answers with `{ "error": { "code", "message", "details" } }`
+#[derive(Debug, Serialize)]
+pub struct ApiErrorEnvelope {
+    pub error: ApiErrorBody,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiErrorBody {
+    pub code: String,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiErrorEnvelope {
+    pub fn new(code: &str, message: String, details: Option<serde_json::Value>) -> Self {
+        ApiErrorEnvelope {
+            error: ApiErrorBody {
+                code: code.to_owned(),
+                message,
+                details,
+            },
+        }
+    }
+}
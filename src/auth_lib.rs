@@ -0,0 +1,397 @@
+use crate::api_models::{AuthTokensResponse, LoginResponse};
+use crate::db_models::{QSessions, QUsers, Sessions};
+use crate::errors::ChatuzaError;
+use crate::mfa_lib::{is_mfa_enabled, start_mfa_login_challenge};
+use crate::schema::sessions;
+use crate::schema::users;
+use crate::schema::users::dsl::*;
+use crate::{get_user_with_user_id, get_user_with_username};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, NaiveDateTime, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+pub use dotenvy::dotenv;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+pub use std::env;
+
+// argon2id parameters used for every new hash, the parameters are stored inside the PHC string
+// so raising them here makes the old hashes to be upgraded on the next successful verification
+const ARGON2_M_COST: u32 = 19_456;
+const ARGON2_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
+
+fn password_hasher() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(ARGON2_M_COST, ARGON2_T_COST, ARGON2_P_COST, None).unwrap(), // panic impossible
+    )
+}
+
+pub fn hash_password(plain_password: &str) -> Result<String, ChatuzaError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match password_hasher().hash_password(plain_password.as_bytes(), &salt) {
+        Ok(res) => Ok(res.to_string()),
+        Err(e) => Err(ChatuzaError::Internal(format!(
+            "couldn't hash the password due to \n {}",
+            e
+        ))),
+    }
+}
+
+// legacy rows were stored as plaintext, they are the ones that can't be parsed as a PHC string
+pub fn is_password_hashed(stored_password: &str) -> bool {
+    PasswordHash::new(stored_password).is_ok()
+}
+
+fn password_needs_rehash(stored_hash: &PasswordHash) -> bool {
+    if stored_hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(stored_hash) {
+        Ok(params) => {
+            params.m_cost() != ARGON2_M_COST
+                || params.t_cost() != ARGON2_T_COST
+                || params.p_cost() != ARGON2_P_COST
+        }
+        Err(_) => true,
+    }
+}
+
+fn store_password_hash(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    plain_password: &str,
+) -> Result<(), ChatuzaError> {
+    let new_hash = hash_password(plain_password)?;
+    match diesel::update(users.filter(users::user_id.eq(_user_id)))
+        .set(password.eq(&new_hash))
+        .execute(_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn verify_user_password(
+    _conn: &mut PgConnection,
+    _username: &str,
+    candidate_password: &str,
+) -> Result<bool, ChatuzaError> {
+    let user_info;
+    match get_user_with_username(_conn, _username) {
+        Ok(res) => user_info = res,
+        Err(e) => return Err(e),
+    }
+
+    if !is_password_hashed(&user_info.password) {
+        // legacy plaintext row, hashing it in place if the candidate matches
+        if user_info.password != candidate_password {
+            return Ok(false);
+        }
+        store_password_hash(_conn, user_info.user_id, candidate_password)?;
+        return Ok(true);
+    }
+
+    let stored_hash = PasswordHash::new(&user_info.password).unwrap(); // panic impossible
+    if password_hasher()
+        .verify_password(candidate_password.as_bytes(), &stored_hash)
+        .is_err()
+    {
+        return Ok(false);
+    }
+
+    if password_needs_rehash(&stored_hash) {
+        store_password_hash(_conn, user_info.user_id, candidate_password)?;
+    }
+    Ok(true)
+}
+
+// -- Sessions / tokens -- //
+
+const ACCESS_TOKEN_TTL_SECS: i64 = 15 * 60;
+const REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+const ACCESS_TOKEN_KIND: &str = "access";
+const REFRESH_TOKEN_KIND: &str = "refresh";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// tokens are `base64url(claims json).base64url(hmac-sha256 of the first part)`
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenClaims {
+    sid: i32,
+    uid: i32,
+    kind: String,
+    exp: i64,
+    // only refresh tokens carry an id, its hash is stored in the session row for rotation
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    jti: String,
+}
+
+fn unauthorized(reason: &str) -> ChatuzaError {
+    ChatuzaError::Unauthorized(reason.to_owned())
+}
+
+fn token_secret() -> Result<Vec<u8>, ChatuzaError> {
+    dotenv().ok();
+    match env::var("AUTH_TOKEN_SECRET") {
+        Ok(res) if res.len() >= 32 => Ok(res.into_bytes()),
+        Ok(_) => Err(ChatuzaError::Internal(
+            "AUTH_TOKEN_SECRET must be at least 32 bytes long".to_owned(),
+        )),
+        Err(_) => Err(ChatuzaError::Internal(
+            "AUTH_TOKEN_SECRET must be set".to_owned(),
+        )),
+    }
+}
+
+fn token_mac(payload: &str) -> Result<HmacSha256, ChatuzaError> {
+    let mut mac = HmacSha256::new_from_slice(&token_secret()?).unwrap(); // panic impossible, hmac takes any key length
+    mac.update(payload.as_bytes());
+    Ok(mac)
+}
+
+fn sign_token(claims: &TokenClaims) -> Result<String, ChatuzaError> {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signature = URL_SAFE_NO_PAD.encode(token_mac(&payload)?.finalize().into_bytes());
+    Ok(format!("{}.{}", payload, signature))
+}
+
+fn decode_token(token: &str, expected_kind: &str) -> Result<TokenClaims, ChatuzaError> {
+    let (payload, signature) = match token.split_once('.') {
+        Some(res) => res,
+        None => return Err(unauthorized("malformed token")),
+    };
+    let signature = match URL_SAFE_NO_PAD.decode(signature) {
+        Ok(res) => res,
+        Err(_) => return Err(unauthorized("malformed token")),
+    };
+    if token_mac(payload)?.verify_slice(&signature).is_err() {
+        return Err(unauthorized("invalid token signature"));
+    }
+
+    let claims: TokenClaims;
+    match URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|res| serde_json::from_slice(&res).ok())
+    {
+        Some(res) => claims = res,
+        None => return Err(unauthorized("malformed token")),
+    }
+    if claims.kind != expected_kind {
+        return Err(unauthorized(
+            format!("expected an {} token", expected_kind).as_str(),
+        ));
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(unauthorized("token expired"));
+    }
+    Ok(claims)
+}
+
+// the user an access token was signed for, only the signature and the expiry are checked so
+// it's cheap enough for every request, e.g. to key the rate limits, but it doesn't authenticate
+pub fn peek_access_token_user_id(access_token: &str) -> Option<i32> {
+    decode_token(access_token, ACCESS_TOKEN_KIND)
+        .ok()
+        .map(|res| res.uid)
+}
+
+// the unix timestamp the access token expires at, with the same checks as above
+pub fn access_token_expires_at(access_token: &str) -> Option<i64> {
+    decode_token(access_token, ACCESS_TOKEN_KIND)
+        .ok()
+        .map(|res| res.exp)
+}
+
+pub fn new_token_id() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_token_id(token_id: &str) -> Vec<u8> {
+    Sha256::digest(token_id.as_bytes()).to_vec()
+}
+
+fn issue_tokens(
+    session: &QSessions,
+    refresh_token_id: String,
+) -> Result<AuthTokensResponse, ChatuzaError> {
+    let access_token_expires_at = Utc::now().timestamp() + ACCESS_TOKEN_TTL_SECS;
+    let refresh_token_expires_at = session.expires_at.and_utc().timestamp();
+    Ok(AuthTokensResponse {
+        access_token: sign_token(&TokenClaims {
+            sid: session.session_id,
+            uid: session.user_id,
+            kind: ACCESS_TOKEN_KIND.to_owned(),
+            exp: access_token_expires_at,
+            jti: String::new(),
+        })?,
+        access_token_expires_at,
+        refresh_token: sign_token(&TokenClaims {
+            sid: session.session_id,
+            uid: session.user_id,
+            kind: REFRESH_TOKEN_KIND.to_owned(),
+            exp: refresh_token_expires_at,
+            jti: refresh_token_id,
+        })?,
+        refresh_token_expires_at,
+    })
+}
+
+// `mfa_verified` is set when the login itself went through the second factor
+pub fn create_session(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    mfa_verified: bool,
+) -> Result<AuthTokensResponse, ChatuzaError> {
+    let refresh_token_id = new_token_id();
+    let now = Utc::now().naive_utc();
+    let expires_at: NaiveDateTime = now + Duration::seconds(REFRESH_TOKEN_TTL_SECS);
+
+    match diesel::insert_into(sessions::table)
+        .values(&Sessions {
+            user_id: _user_id,
+            refresh_token_hash: hash_token_id(&refresh_token_id),
+            expires_at,
+            mfa_verified_at: if mfa_verified { Some(now) } else { None },
+        })
+        .returning(QSessions::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => issue_tokens(&res, refresh_token_id),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn login_user(
+    _conn: &mut PgConnection,
+    _username: &str,
+    candidate_password: &str,
+) -> Result<LoginResponse, ChatuzaError> {
+    // unknown usernames and wrong passwords must look the same to the caller
+    match verify_user_password(_conn, _username, candidate_password) {
+        Ok(true) => {}
+        _ => return Err(unauthorized("invalid username or password")),
+    }
+    let user_info = get_user_with_username(_conn, _username)?;
+    // the password alone doesn't open a session once 2fa is on
+    if is_mfa_enabled(_conn, user_info.user_id)? {
+        return start_mfa_login_challenge(_conn, user_info.user_id);
+    }
+    Ok(LoginResponse {
+        mfa_required: false,
+        tokens: Some(create_session(_conn, user_info.user_id, false)?),
+        mfa_challenge_token: None,
+        mfa_challenge_expires_at: None,
+    })
+}
+
+pub fn get_active_session(
+    _conn: &mut PgConnection,
+    _session_id: i32,
+) -> Result<QSessions, ChatuzaError> {
+    let session_rows: Vec<QSessions> = sessions::table
+        .filter(sessions::session_id.eq(_session_id))
+        .select(QSessions::as_select())
+        .load(_conn)
+        .unwrap_or(vec![]);
+
+    if session_rows.len() != 1 {
+        return Err(unauthorized("session not found"));
+    }
+    let session = session_rows.into_iter().next().unwrap(); // panic impossible
+    if session.revoked_at.is_some() {
+        return Err(unauthorized("session revoked"));
+    }
+    if session.expires_at <= Utc::now().naive_utc() {
+        return Err(unauthorized("session expired"));
+    }
+    Ok(session)
+}
+
+// resolves an access token to its user, the session must still be active
+pub fn authenticate_access_token(
+    _conn: &mut PgConnection,
+    access_token: &str,
+) -> Result<(QUsers, QSessions), ChatuzaError> {
+    let claims = decode_token(access_token, ACCESS_TOKEN_KIND)?;
+    let session = get_active_session(_conn, claims.sid)?;
+    if session.user_id != claims.uid {
+        return Err(unauthorized("token doesn't belong to the session"));
+    }
+    match get_user_with_user_id(_conn, session.user_id) {
+        Ok(res) => Ok((res, session)),
+        Err(_) => Err(unauthorized("session user not found")),
+    }
+}
+
+pub fn refresh_session(
+    _conn: &mut PgConnection,
+    refresh_token: &str,
+) -> Result<AuthTokensResponse, ChatuzaError> {
+    let claims = decode_token(refresh_token, REFRESH_TOKEN_KIND)?;
+    let session = get_active_session(_conn, claims.sid)?;
+
+    if session.refresh_token_hash != hash_token_id(&claims.jti) {
+        // an already rotated refresh token is being replayed, killing the whole session
+        revoke_session(_conn, session.session_id)?;
+        return Err(unauthorized(
+            "refresh token reuse detected, session revoked",
+        ));
+    }
+
+    let refresh_token_id = new_token_id();
+    match diesel::update(sessions::table.filter(sessions::session_id.eq(session.session_id)))
+        .set(sessions::refresh_token_hash.eq(hash_token_id(&refresh_token_id)))
+        .returning(QSessions::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => issue_tokens(&res, refresh_token_id),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn revoke_session(_conn: &mut PgConnection, _session_id: i32) -> Result<bool, ChatuzaError> {
+    match diesel::update(
+        sessions::table.filter(
+            sessions::session_id
+                .eq(_session_id)
+                .and(sessions::revoked_at.is_null()),
+        ),
+    )
+    .set(sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(_conn)
+    {
+        Ok(_) => Ok(true),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn revoke_all_user_sessions(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<usize, ChatuzaError> {
+    match diesel::update(
+        sessions::table.filter(
+            sessions::user_id
+                .eq(_user_id)
+                .and(sessions::revoked_at.is_null()),
+        ),
+    )
+    .set(sessions::revoked_at.eq(Utc::now().naive_utc()))
+    .execute(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
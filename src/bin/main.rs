@@ -2,135 +2,221 @@
 
 extern crate rocket; // imports all of the macros from the rocket crate
 
+use chatuza_db::api_guards::*;
 use chatuza_db::api_models::*;
+use chatuza_db::auth_lib::*;
+use chatuza_db::contact_lib::*;
 use chatuza_db::db_models::*;
+use chatuza_db::db_pool::*;
+use chatuza_db::delivery::*;
+use chatuza_db::errors::*;
+use chatuza_db::invite_lib::*;
+use chatuza_db::message_lib::*;
+use chatuza_db::mfa_lib::*;
+use chatuza_db::permission_lib::*;
+use chatuza_db::privacy_lib::*;
+use chatuza_db::rate_limit::*;
+use chatuza_db::realtime::*;
+use chatuza_db::recovery_lib::*;
+use chatuza_db::room_transfer_lib::*;
+use chatuza_db::search_lib::*;
+use chatuza_db::verification_lib::*;
+use chatuza_db::wallet_config::*;
 use chatuza_db::wallet_lib::*;
 use chatuza_db::*;
+use rocket::http::Status;
 use rocket::request::Form;
 use rocket::request::Request;
+use rocket::response::status;
+use rocket::State;
 use rocket::*;
 use rocket_contrib::json::Json;
+use std::process;
+use std::str::FromStr;
 
+// the users who blocked the caller are answered like missing usernames, the fields hidden
+// from the caller by the privacy settings are null
 #[get("/user-via-username/<username>")]
-fn get_user_via_username(username: String) -> Json<Result<QUsers, String>> {
-    let mut conn = establish_connection();
-    match get_user_with_username(&mut conn, username.as_str()) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+fn get_user_via_username(
+    auth: Option<AuthUser>,
+    mut conn: DbConn,
+    username: String,
+) -> ApiResult<PublicUserProfile> {
+    let viewer_user_id = auth.map(|res| res.user.user_id);
+    match get_public_profile_with_username(&mut conn, username.as_str(), viewer_user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/user-profile-via-username/<username>")]
-fn get_user_profile_via_username(username: String) -> Json<Result<UserProfiles, String>> {
-    let mut conn = establish_connection();
-    match get_user_profile_with_username(&mut conn, &username) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+fn get_user_profile_via_username(
+    auth: Option<AuthUser>,
+    mut conn: DbConn,
+    username: String,
+) -> ApiResult<UserProfiles> {
+    let viewer_user_id = auth.map(|res| res.user.user_id);
+    match get_user_profile_with_username(&mut conn, &username, viewer_user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
+// only finds the users whose email is visible to the caller
 #[get("/user-via-email/<email>")]
-fn get_user_via_email(email: String) -> Json<Result<QUsers, String>> {
-    let mut conn = establish_connection();
-    match get_user_with_email(&mut conn, email.as_str()) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+fn get_user_via_email(
+    auth: Option<AuthUser>,
+    mut conn: DbConn,
+    email: String,
+) -> ApiResult<PublicUserProfile> {
+    let viewer_user_id = auth.map(|res| res.user.user_id);
+    match get_public_profile_with_email(&mut conn, email.as_str(), viewer_user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/user-via-userid/<user_id>")]
-fn get_user_via_user_id(user_id: i32) -> Json<Result<QUsers, String>> {
-    let mut conn = establish_connection();
+fn get_user_via_user_id(
+    auth: Option<AuthUser>,
+    mut conn: DbConn,
+    user_id: i32,
+) -> ApiResult<PublicUserProfile> {
+    let viewer_user_id = auth.map(|res| res.user.user_id);
+    match get_public_profile_with_user_id(&mut conn, user_id, viewer_user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+// `joined_after` is a unix timestamp, `role` one of owner/admin/moderator/member/read_only
+#[get(
+    "/chatroom-participants-by-id/<chatroom_id>?<cursor>&<limit>&<direction>&<role>&<joined_after>"
+)]
+fn get_chatroom_by_id(
+    mut conn: DbConn,
+    chatroom_id: i32,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+    direction: Option<String>,
+    role: Option<String>,
+    joined_after: Option<i64>,
+) -> ApiResult<Page<QChatRoomParticipants>> {
+    let page = PageRequest::new(cursor, limit, direction)?;
+    let filter = ParticipantListFilter::new(role, joined_after)?;
+    match get_chat_room_participants_page(&mut conn, chatroom_id, &filter, &page) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
 
-    match get_user_with_user_id(&mut conn, user_id) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+// ranked by relevance, `cursor` is the `next_cursor` of the previous page
+#[get("/search-users?<q>&<cursor>&<limit>")]
+fn search_users_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    q: String,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+) -> ApiResult<Page<PublicUserProfile>> {
+    let page = PageRequest::new(cursor, limit, None)?;
+    match search_users(&mut conn, auth.user.user_id, q.as_str(), &page) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
-#[get("/chatroom-participants-by-id/<chatroom_id>")]
-fn get_chatroom_by_id(chatroom_id: i32) -> Json<Result<Vec<ChatRoomParticipants>, String>> {
-    let mut conn = establish_connection();
-    match get_chat_room_participants_by_id(&mut conn, chatroom_id) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+#[get("/privacy-settings")]
+fn get_privacy_settings(auth: AuthUser, mut conn: DbConn) -> ApiResult<QUserPrivacySettings> {
+    match get_user_privacy_settings(&mut conn, auth.user.user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/chatroom-participants-by-name/<chatroom_name>")]
-fn get_chatroom_by_name(chatroom_name: String) -> Json<Result<Vec<ChatRoomParticipants>, String>> {
-    let mut conn = establish_connection();
+fn get_chatroom_by_name(
+    mut conn: DbConn,
+    chatroom_name: String,
+) -> ApiResult<Vec<ChatRoomParticipants>> {
     match get_chat_room_participants_by_name(&mut conn, &chatroom_name) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/group-owner/<owner_id>")]
-fn get_group_owner_via_id(owner_id: i32) -> Json<Result<i32, String>> {
-    let mut conn = establish_connection();
+fn get_group_owner_via_id(mut conn: DbConn, owner_id: i32) -> ApiResult<i32> {
     match get_group_owner_by_id(&mut conn, owner_id) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/group_by_name/<chatroom_name>")]
-fn get_chatroom_id_via_name(chatroom_name: String) -> Json<Result<QChatRooms, String>> {
-    let mut conn = establish_connection();
+fn get_chatroom_id_via_name(mut conn: DbConn, chatroom_name: String) -> ApiResult<QChatRooms> {
     match get_group_chat_by_name(&mut conn, &chatroom_name) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/is_valid_gp/<chatroom_id>")]
-fn validate_gp(chatroom_id: i32) -> Json<bool> {
-    let mut conn = establish_connection();
+fn validate_gp(mut conn: DbConn, chatroom_id: i32) -> Json<bool> {
     Json(is_group_chat(&mut conn, chatroom_id))
 }
 
 #[get("/is_valid_chatroom/<chatroom_id>")]
-fn validate_cr(chatroom_id: i32) -> Json<bool> {
-    let mut conn = establish_connection();
+fn validate_cr(mut conn: DbConn, chatroom_id: i32) -> Json<bool> {
     Json(is_valid_chatroom(&mut conn, chatroom_id))
 }
 
 #[get("/is_valid_user/<user_id>")]
-fn validate_user(user_id: i32) -> Json<bool> {
-    let mut conn = establish_connection();
+fn validate_user(mut conn: DbConn, user_id: i32) -> Json<bool> {
     Json(is_valid_user(&mut conn, user_id))
 }
 
 #[get("/is_user_in_chatroom/<user_id>/<chatroom_id>")]
-fn validate_chatroom_user(user_id: i32, chatroom_id: i32) -> Json<bool> {
-    let mut conn = establish_connection();
+fn validate_chatroom_user(mut conn: DbConn, user_id: i32, chatroom_id: i32) -> Json<bool> {
     Json(is_user_in_chat_room(&mut conn, user_id, chatroom_id))
 }
 
-#[get("/user_all_p2p/<user_id>")]
-fn get_all_user_p2p(user_id: i32) -> Json<Result<Vec<QChatRooms>, String>> {
-    let mut conn = establish_connection();
-
-    match get_user_p2p_chat_rooms_by_user_id(&mut conn, user_id) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+#[get("/user_all_p2p/<user_id>?<cursor>&<limit>&<direction>&<name_prefix>")]
+fn get_all_user_p2p(
+    mut conn: DbConn,
+    user_id: i32,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+    direction: Option<String>,
+    name_prefix: Option<String>,
+) -> ApiResult<Page<QChatRooms>> {
+    let page = PageRequest::new(cursor, limit, direction)?;
+    let filter = ChatRoomListFilter { name_prefix };
+    match get_user_p2p_chat_rooms_page(&mut conn, user_id, &filter, &page) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
-#[get("/user_all_gp/<user_id>")]
-fn get_all_user_groups(user_id: i32) -> Json<Result<Vec<QChatRooms>, String>> {
-    let mut conn = establish_connection();
-
-    match get_user_group_chat_rooms_by_user_id(&mut conn, user_id) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+#[get("/user_all_gp/<user_id>?<cursor>&<limit>&<direction>&<name_prefix>")]
+fn get_all_user_groups(
+    mut conn: DbConn,
+    user_id: i32,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+    direction: Option<String>,
+    name_prefix: Option<String>,
+) -> ApiResult<Page<QChatRooms>> {
+    let page = PageRequest::new(cursor, limit, direction)?;
+    let filter = ChatRoomListFilter { name_prefix };
+    match get_user_group_chat_rooms_page(&mut conn, user_id, &filter, &page) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/create-user", data = "<new_user>")]
-fn new_user(new_user: Form<NewUserIN>) -> Json<Result<QUsersResponse, String>> {
-    let mut conn = establish_connection();
+fn new_user(mut conn: DbConn, new_user: Form<NewUserIN>) -> ApiResult<QUsersResponse> {
     match add_new_user(
         &mut conn,
         &Users {
@@ -145,19 +231,139 @@ fn new_user(new_user: Form<NewUserIN>) -> Json<Result<QUsersResponse, String>> {
             profile_picture: new_user.profile_picture_in.clone(),
         },
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/login", data = "<credentials>")]
+fn login(mut conn: DbConn, credentials: Form<LoginIN>) -> ApiResult<LoginResponse> {
+    match login_user(
+        &mut conn,
+        credentials.username_in.as_str(),
+        credentials.password_in.as_str(),
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/login/mfa", data = "<challenge_info>")]
+fn login_mfa(mut conn: DbConn, challenge_info: Form<MfaLoginIN>) -> ApiResult<AuthTokensResponse> {
+    match complete_mfa_login(
+        &mut conn,
+        &challenge_info.mfa_challenge_token_in,
+        &challenge_info.code_in,
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/refresh-token", data = "<refresh_token>")]
+fn refresh_token(
+    mut conn: DbConn,
+    refresh_token: Form<RefreshTokenIN>,
+) -> ApiResult<AuthTokensResponse> {
+    match refresh_session(&mut conn, refresh_token.refresh_token_in.as_str()) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+// the websocket connections of the revoked sessions are closed too
+#[post("/logout")]
+fn logout(auth: AuthUser, mut conn: DbConn, hub: State<RealtimeHub>) -> ApiResult<bool> {
+    match revoke_session(&mut conn, auth.session_id) {
+        Ok(res) => {
+            hub.disconnect_session(auth.session_id);
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/logout-all")]
+fn logout_all(auth: AuthUser, mut conn: DbConn, hub: State<RealtimeHub>) -> ApiResult<usize> {
+    match revoke_all_user_sessions(&mut conn, auth.user.user_id) {
+        Ok(res) => {
+            hub.disconnect_user(auth.user.user_id);
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/mfa/enroll")]
+fn start_mfa_enrollment_api(auth: AuthUser, mut conn: DbConn) -> ApiResult<MfaEnrollmentResponse> {
+    match start_mfa_enrollment(&mut conn, auth.user.user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/mfa/confirm", data = "<code_info>")]
+fn confirm_mfa_enrollment_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    code_info: Form<MfaCodeIN>,
+) -> ApiResult<MfaRecoveryCodesResponse> {
+    match confirm_mfa_enrollment(
+        &mut conn,
+        auth.user.user_id,
+        auth.session_id,
+        &code_info.code_in,
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/mfa/verify", data = "<code_info>")]
+fn verify_mfa_api(auth: AuthUser, mut conn: DbConn, code_info: Form<MfaCodeIN>) -> ApiResult<bool> {
+    match verify_session_mfa(
+        &mut conn,
+        auth.user.user_id,
+        auth.session_id,
+        &code_info.code_in,
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/mfa/recovery-codes")]
+fn regenerate_recovery_codes_api(
+    auth: FreshMfaUser,
+    mut conn: DbConn,
+) -> ApiResult<MfaRecoveryCodesResponse> {
+    match regenerate_recovery_codes(&mut conn, auth.user.user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/mfa/disable", data = "<code_info>")]
+fn disable_mfa_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    code_info: Form<MfaCodeIN>,
+) -> ApiResult<bool> {
+    match disable_mfa(&mut conn, auth.user.user_id, &code_info.code_in) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/update-user-credits", data = "<new_credits>")]
 fn update_user_conditionals(
+    auth: AuthUser,
+    mut conn: DbConn,
     new_credits: Form<UpdatedUserCreditsIN>,
-) -> Json<Result<QUsers, String>> {
-    let mut conn = establish_connection();
+) -> ApiResult<QUsers> {
     match update_user_credits(
         &mut conn,
-        &new_credits.username_out.clone(),
+        &auth.user.username,
         &Users {
             username: new_credits.username_in.clone(),
             email: new_credits.email_in.clone(),
@@ -165,105 +371,377 @@ fn update_user_conditionals(
             phone_number: new_credits.phone_number_in.clone(),
         },
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/update-user-profile", data = "<new_profile>")]
 fn update_user_profile_api(
+    auth: AuthUser,
+    mut conn: DbConn,
     new_profile: Form<UpdatedUserProfileIN>,
-) -> Json<Result<UserProfiles, String>> {
-    let mut conn = establish_connection();
+) -> ApiResult<UserProfiles> {
     match update_user_profile(
         &mut conn,
-        &new_profile.username_in.clone(),
+        &auth.user.username,
         &mut UserProfiles {
             user_id: 0,
             bio: Some(new_profile.bio_in.clone()),
             profile_picture: Some(new_profile.profile_picture_in.clone()),
         },
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
-#[post("/delete-user", data = "<username>")]
-fn delete_user_via_username(username: Form<SinglePostUsername>) -> Json<Result<bool, String>> {
-    let mut conn = establish_connection();
-    match delete_user(&mut conn, &username.username_in.clone()) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+#[post("/delete-user")]
+fn delete_user_via_username(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+) -> ApiResult<bool> {
+    let _chat_room_ids = get_user_chat_room_ids(&mut conn, auth.user.user_id);
+    match delete_user(&mut conn, &auth.user.username) {
+        Ok(res) => {
+            for _chat_room_id in _chat_room_ids {
+                hub.publish(
+                    _chat_room_id,
+                    &RoomEvent::ParticipantRemoved {
+                        chat_room_id: _chat_room_id,
+                        user_id: auth.user.user_id,
+                    },
+                );
+            }
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 
-#[post("/create-p2p", data = "<new_p2p_info>")]
-fn new_p2p(new_p2p_info: Form<NewP2PChatRoomIN>) -> Json<Result<QChatRooms, String>> {
-    let mut conn = establish_connection();
-    let req_user: QUsers;
-    match get_user_with_username(
+fn parse_verification_channel(channel: &str) -> Result<VerificationChannel, ChatuzaError> {
+    match VerificationChannel::from_str(channel) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Validation(e)),
+    }
+}
+
+#[post("/verification/start", data = "<verification_info>")]
+fn start_verification_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    sender: State<MessageRouter>,
+    verification_info: Form<StartVerificationIN>,
+) -> ApiResult<VerificationStartedResponse> {
+    let channel = parse_verification_channel(&verification_info.channel_in)?;
+    match start_verification(&mut conn, sender.inner(), auth.user.user_id, channel) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/verification/confirm", data = "<verification_info>")]
+fn confirm_verification_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    verification_info: Form<ConfirmVerificationIN>,
+) -> ApiResult<QUsers> {
+    let channel = parse_verification_channel(&verification_info.channel_in)?;
+    match confirm_verification(
+        &mut conn,
+        auth.user.user_id,
+        channel,
+        &verification_info.code_in,
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+// always answers true, see `request_password_reset`
+#[post("/forgot-password", data = "<forgot_info>")]
+fn forgot_password(
+    mut conn: DbConn,
+    sender: State<MessageRouter>,
+    forgot_info: Form<ForgotPasswordIN>,
+) -> ApiResult<bool> {
+    let channel = match &forgot_info.channel_in {
+        Some(res) => parse_verification_channel(res)?,
+        None => VerificationChannel::Email,
+    };
+    match request_password_reset(
+        &mut conn,
+        sender.inner(),
+        &forgot_info.identifier_in,
+        channel,
+    ) {
+        Ok(_) => return Ok(Json(true)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/reset-password", data = "<reset_info>")]
+fn reset_password_api(
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    reset_info: Form<ResetPasswordIN>,
+) -> ApiResult<bool> {
+    match reset_password(
         &mut conn,
-        new_p2p_info.requestor_username_in.clone().as_str(),
+        &reset_info.reset_token_in,
+        &reset_info.new_password_in,
     ) {
-        Ok(res) => req_user = res,
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            hub.disconnect_user(res);
+            return Ok(Json(true));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/update-privacy-settings", data = "<privacy_info>")]
+fn update_privacy_settings(
+    auth: AuthUser,
+    mut conn: DbConn,
+    privacy_info: Form<UpdatePrivacySettingsIN>,
+) -> ApiResult<QUserPrivacySettings> {
+    let new_settings;
+    match get_user_privacy_settings(&mut conn, auth.user.user_id)
+        .and_then(|res| privacy_info.apply_to(&res))
+    {
+        Ok(res) => new_settings = res,
+        Err(e) => return Err(e),
     }
 
+    match update_user_privacy_settings(&mut conn, &new_settings) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+// the p2p chat room only gets created once the acceptor accepts the contact request
+#[post("/create-p2p", data = "<new_p2p_info>")]
+fn new_p2p(
+    auth: AuthUser,
+    mut conn: DbConn,
+    new_p2p_info: Form<NewP2PChatRoomIN>,
+) -> ApiResult<QContactRequests> {
     let acc_user: QUsers;
-    match get_user_with_username(
+    match get_visible_user_with_username(
         &mut conn,
         new_p2p_info.acceptor_username_in.clone().as_str(),
+        Some(auth.user.user_id),
     ) {
         Ok(res) => acc_user = res,
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Err(e) => return Err(e),
     }
 
-    match add_new_p2p_chat_room(
+    match send_contact_request(
         &mut conn,
-        req_user.user_id,
+        auth.user.user_id,
         acc_user.user_id,
         new_p2p_info.chat_room_pubkey_in.clone(),
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/accept-contact-request", data = "<decision_info>")]
+fn accept_contact_request(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    decision_info: Form<ContactRequestDecisionIN>,
+) -> ApiResult<ContactRequestResponse> {
+    match respond_to_contact_request(
+        &mut conn,
+        decision_info.contact_request_id_in,
+        auth.user.user_id,
+        true,
+    ) {
+        Ok(res) => {
+            if let Some(chat_room) = &res.chat_room {
+                hub.open_room(
+                    chat_room,
+                    vec![
+                        res.contact_request.requester_id,
+                        res.contact_request.addressee_id,
+                    ],
+                );
+            }
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/decline-contact-request", data = "<decision_info>")]
+fn decline_contact_request(
+    auth: AuthUser,
+    mut conn: DbConn,
+    decision_info: Form<ContactRequestDecisionIN>,
+) -> ApiResult<ContactRequestResponse> {
+    match respond_to_contact_request(
+        &mut conn,
+        decision_info.contact_request_id_in,
+        auth.user.user_id,
+        false,
+    ) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/contact-requests/incoming")]
+fn get_incoming_contact_requests(
+    auth: AuthUser,
+    mut conn: DbConn,
+) -> ApiResult<Vec<QContactRequests>> {
+    match get_pending_contact_requests(&mut conn, auth.user.user_id, true) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/contact-requests/outgoing")]
+fn get_outgoing_contact_requests(
+    auth: AuthUser,
+    mut conn: DbConn,
+) -> ApiResult<Vec<QContactRequests>> {
+    match get_pending_contact_requests(&mut conn, auth.user.user_id, false) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/block-user", data = "<block_info>")]
+fn block_user_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    block_info: Form<BlockUserIN>,
+) -> ApiResult<QUserBlocks> {
+    let blocked_user: QUsers;
+    match get_user_with_username(&mut conn, block_info.username_in.as_str()) {
+        Ok(res) => blocked_user = res,
+        Err(e) => return Err(e),
+    }
+
+    match block_user(&mut conn, auth.user.user_id, blocked_user.user_id) {
+        Ok(res) => {
+            hub.block_user(auth.user.user_id, blocked_user.user_id);
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/unblock-user", data = "<block_info>")]
+fn unblock_user_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    block_info: Form<BlockUserIN>,
+) -> ApiResult<bool> {
+    let blocked_user: QUsers;
+    match get_user_with_username(&mut conn, block_info.username_in.as_str()) {
+        Ok(res) => blocked_user = res,
+        Err(e) => return Err(e),
+    }
+
+    match unblock_user(&mut conn, auth.user.user_id, blocked_user.user_id) {
+        Ok(res) => {
+            hub.unblock_user(auth.user.user_id, blocked_user.user_id);
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/blocked-users")]
+fn get_blocked_users_api(auth: AuthUser, mut conn: DbConn) -> ApiResult<Vec<QUserBlocks>> {
+    match get_blocked_users(&mut conn, auth.user.user_id) {
+        Ok(res) => return Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/delete-p2p", data = "<new_gp_info>")]
-fn delete_p2p(new_gp_info: Form<DeleteP2PChatRoomIN>) -> Json<Result<bool, String>> {
-    let mut conn = establish_connection();
+fn delete_p2p(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    new_gp_info: Form<DeleteP2PChatRoomIN>,
+) -> ApiResult<bool> {
+    let _chat_room_id;
+    match get_user_with_username(&mut conn, new_gp_info.contact_username_in.as_str())
+        .and_then(|res| get_two_users_p2p_chat_room(&mut conn, auth.user.user_id, res.user_id))
+    {
+        Ok(res) => _chat_room_id = res.chat_room_id,
+        Err(e) => return Err(e),
+    }
+
     match delete_p2p_chat_room(
         &mut conn,
-        &new_gp_info.remover_username_in.clone(),
+        &auth.user.username,
         &new_gp_info.contact_username_in.clone(),
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            hub.publish(
+                _chat_room_id,
+                &RoomEvent::RoomDeleted {
+                    chat_room_id: _chat_room_id,
+                },
+            );
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/create-gp", data = "<new_gp_info>")]
-fn new_gp(new_gp_info: Json<NewGroupChatRoomIN>) -> Json<Result<QChatRooms, String>> {
-    let mut conn = establish_connection();
+fn new_gp(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    new_gp_info: Json<NewGroupChatRoomIN>,
+) -> ApiResult<QChatRooms> {
     match add_new_group_chat_room(
         &mut conn,
         &ChatRooms {
             room_name: new_gp_info.room_name_in.clone(),
             room_description: new_gp_info.room_description_in.clone(),
             chat_room_pubkey: new_gp_info.chat_room_pubkey.as_bytes().to_vec(),
+            room_kind: RoomKind::Group,
+            direct_user_low: None,
+            direct_user_high: None,
+            owner_leave_policy: OwnerLeavePolicy::Transfer,
         },
-        &(new_gp_info.group_owner_username_in.clone()),
+        &auth.user.username,
         new_gp_info.group_members_in.to_owned(),
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            let participant_ids = get_chat_room_participants_by_id(&mut conn, res.chat_room_id)
+                .unwrap_or(vec![])
+                .iter()
+                .map(|participant| participant.user_id)
+                .collect();
+            hub.open_room(&res, participant_ids);
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/update-gp-info", data = "<new_gp_info>")]
-fn update_gp(new_gp_info: Form<UpdatedGroupChatRoomInfoIN>) -> Json<Result<QChatRooms, String>> {
-    let mut conn = establish_connection();
+fn update_gp(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    new_gp_info: Form<UpdatedGroupChatRoomInfoIN>,
+) -> ApiResult<QChatRooms> {
     match update_group_chat_room_info(
         &mut conn,
         &new_gp_info.old_chat_room_name_in.clone(),
@@ -271,41 +749,64 @@ fn update_gp(new_gp_info: Form<UpdatedGroupChatRoomInfoIN>) -> Json<Result<QChat
             room_name: new_gp_info.room_name_in.clone(),
             room_description: new_gp_info.room_description_in.clone(),
         },
-        &new_gp_info.editor_username_in.clone(),
+        &auth.user.username,
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            hub.publish(res.chat_room_id, &RoomEvent::RoomUpdated(&res));
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/delete-gp", data = "<new_gp_info>")]
-fn delete_gp(new_gp_info: Form<DeleteGroupChatRoomIN>) -> Json<Result<bool, String>> {
-    let mut conn = establish_connection();
+fn delete_gp(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    new_gp_info: Form<DeleteGroupChatRoomIN>,
+) -> ApiResult<bool> {
+    let _chat_room_id;
+    match get_group_chat_by_name(&mut conn, &new_gp_info.chat_room_name_in) {
+        Ok(res) => _chat_room_id = res.chat_room_id,
+        Err(e) => return Err(e),
+    }
+
     match delete_group_chat_room(
         &mut conn,
         &new_gp_info.chat_room_name_in.clone(),
-        &new_gp_info.remover_username_in.clone(),
+        &auth.user.username,
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            hub.publish(
+                _chat_room_id,
+                &RoomEvent::RoomDeleted {
+                    chat_room_id: _chat_room_id,
+                },
+            );
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 #[post("/add-user-to-gp", data = "<new_participant>")]
 fn add_user_to_gp(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
     new_participant: Form<NewGroupChatParticipantIN>,
-) -> Json<Result<ChatRoomParticipants, String>> {
-    let mut conn = establish_connection();
+) -> ApiResult<ChatRoomParticipants> {
     let _chat_room_id;
 
     match get_group_chat_by_name(&mut conn, &new_participant.chat_room_name_in.clone()) {
         Ok(res) => _chat_room_id = res.chat_room_id,
-        Err(e) => return Json(Err(format!("{}", e))),
+        Err(e) => return Err(e),
     }
 
     let _user_id: i32;
     match get_user_with_username(&mut conn, new_participant.username_in.clone().as_str()) {
         Ok(res) => _user_id = res.user_id,
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Err(e) => return Err(e),
     }
 
     match add_participant_to_group_chat_room(
@@ -313,46 +814,79 @@ fn add_user_to_gp(
         &ChatRoomParticipants {
             chat_room_id: _chat_room_id,
             user_id: _user_id,
-            is_admin: false,
+            role: ParticipantRole::Member,
         },
-        &new_participant.adder_username_in,
+        &auth.user.username,
     ) {
-        Ok(res) => return Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{:?}", e))),
+        Ok(res) => {
+            hub.publish(
+                res.chat_room_id,
+                &RoomEvent::ParticipantAdded {
+                    chat_room_id: res.chat_room_id,
+                    user_id: res.user_id,
+                },
+            );
+            return Ok(Json(res));
+        }
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/delete-user-from-gp", data = "<removing_participant>")]
 fn delete_user_from_gp(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
     removing_participant: Form<GroupChatParticipantToRemoveIN>,
-) -> Json<Result<bool, String>> {
-    let mut conn = establish_connection();
-
+) -> ApiResult<bool> {
     let _chat_room_id;
     match get_group_chat_by_name(&mut conn, &removing_participant.chat_room_name_in.clone()) {
         Ok(res) => _chat_room_id = res.chat_room_id,
-        Err(e) => return Json(Err(format!("{}", e))),
+        Err(e) => return Err(e),
     }
 
     let _removing_user_id;
     match get_user_with_username(&mut conn, removing_participant.username_in.clone().as_str()) {
         Ok(res) => _removing_user_id = res.user_id,
-        Err(e) => return Json(Err(format!("{}", e))),
+        Err(e) => return Err(e),
     }
 
-    let _remover_user_id;
-    match get_user_with_username(
-        &mut conn,
-        removing_participant.remover_username_in.clone().as_str(),
-    ) {
-        Ok(res) => _remover_user_id = res.user_id,
-        Err(e) => return Json(Err(format!("{}", e))),
-    }
+    let _remover_user_id = auth.user.user_id;
 
-    let _admin;
-    match get_group_owner_by_id(&mut conn, _chat_room_id) {
-        Ok(res) => _admin = res,
-        Err(e) => return Json(Err(format!("{}", e))),
+    // an owner leaving hands the group over or deletes it, the subscribers need to know which one
+    if _remover_user_id == _removing_user_id
+        && get_group_owner_by_id(&mut conn, _chat_room_id).ok() == Some(_remover_user_id)
+    {
+        return match owner_leave_group_chat_room(&mut conn, _chat_room_id, _remover_user_id) {
+            Ok(OwnerLeaveOutcome::Transferred { new_owner_id }) => {
+                hub.publish(
+                    _chat_room_id,
+                    &RoomEvent::ParticipantRoleChanged {
+                        chat_room_id: _chat_room_id,
+                        user_id: new_owner_id,
+                        role: ParticipantRole::Owner,
+                    },
+                );
+                hub.publish(
+                    _chat_room_id,
+                    &RoomEvent::ParticipantRemoved {
+                        chat_room_id: _chat_room_id,
+                        user_id: _removing_user_id,
+                    },
+                );
+                Ok(Json(true))
+            }
+            Ok(OwnerLeaveOutcome::RoomDeleted) => {
+                hub.publish(
+                    _chat_room_id,
+                    &RoomEvent::RoomDeleted {
+                        chat_room_id: _chat_room_id,
+                    },
+                );
+                Ok(Json(true))
+            }
+            Err(e) => Err(e),
+        };
     }
 
     match del_participant_from_group_chat_room(
@@ -360,24 +894,413 @@ fn delete_user_from_gp(
         &ChatRoomParticipants {
             chat_room_id: _chat_room_id,
             user_id: _removing_user_id,
-            is_admin: _remover_user_id == _admin,
+            role: ParticipantRole::Member,
         },
         _remover_user_id,
     ) {
-        Ok(res) => Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{}", e))),
+        Ok(res) => {
+            hub.publish(
+                _chat_room_id,
+                &RoomEvent::ParticipantRemoved {
+                    chat_room_id: _chat_room_id,
+                    user_id: _removing_user_id,
+                },
+            );
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
     }
 }
 
-#[post("/add-solana-wallet", data = "<new_wallet_info>")]
-fn add_solana_wallet(new_wallet_info: Form<NewWalletIn>) -> Json<Result<QSolanaWallet, String>> {
-    let mut conn = establish_connection();
+#[post("/transfer-gp-ownership", data = "<transfer_info>")]
+fn transfer_gp_ownership(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    transfer_info: Form<TransferGroupOwnershipIN>,
+) -> ApiResult<ChatRoomParticipants> {
+    let _chat_room_id;
+    match get_group_chat_by_name(&mut conn, &transfer_info.chat_room_name_in) {
+        Ok(res) => _chat_room_id = res.chat_room_id,
+        Err(e) => return Err(e),
+    }
+
+    let _new_owner_id;
+    match get_user_with_username(&mut conn, transfer_info.new_owner_username_in.as_str()) {
+        Ok(res) => _new_owner_id = res.user_id,
+        Err(e) => return Err(e),
+    }
+
+    match transfer_group_ownership(&mut conn, _chat_room_id, auth.user.user_id, _new_owner_id) {
+        Ok(res) => {
+            hub.publish(
+                _chat_room_id,
+                &RoomEvent::ParticipantRoleChanged {
+                    chat_room_id: _chat_room_id,
+                    user_id: auth.user.user_id,
+                    role: ParticipantRole::Admin,
+                },
+            );
+            hub.publish(
+                _chat_room_id,
+                &RoomEvent::ParticipantRoleChanged {
+                    chat_room_id: _chat_room_id,
+                    user_id: res.user_id,
+                    role: res.role,
+                },
+            );
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/set-gp-owner-leave-policy", data = "<policy_info>")]
+fn set_gp_owner_leave_policy(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    policy_info: Form<OwnerLeavePolicyIN>,
+) -> ApiResult<QChatRooms> {
+    let new_policy: OwnerLeavePolicy;
+    match policy_info.policy_in.parse() {
+        Ok(res) => new_policy = res,
+        Err(e) => return Err(ChatuzaError::Validation(e)),
+    }
+
+    let _chat_room_id;
+    match get_group_chat_by_name(&mut conn, &policy_info.chat_room_name_in) {
+        Ok(res) => _chat_room_id = res.chat_room_id,
+        Err(e) => return Err(e),
+    }
+
+    match set_owner_leave_policy(&mut conn, _chat_room_id, auth.user.user_id, new_policy) {
+        Ok(res) => {
+            hub.publish(res.chat_room_id, &RoomEvent::RoomUpdated(&res));
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/create-gp-invite", data = "<invite_info>")]
+fn create_gp_invite(
+    auth: AuthUser,
+    mut conn: DbConn,
+    invite_info: Form<NewGroupInviteIN>,
+) -> ApiResult<GroupInviteResponse> {
+    match create_group_invite(
+        &mut conn,
+        invite_info.chat_room_id_in,
+        auth.user.user_id,
+        invite_info.expires_in_secs_in,
+        invite_info.max_uses_in,
+        invite_info.requires_approval_in,
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/gp-invites/<chatroom_id>")]
+fn get_gp_invites(
+    auth: AuthUser,
+    mut conn: DbConn,
+    chatroom_id: i32,
+) -> ApiResult<Vec<QGroupInvites>> {
+    match get_group_invites(&mut conn, chatroom_id, auth.user.user_id) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/revoke-gp-invite", data = "<invite_info>")]
+fn revoke_gp_invite(
+    auth: AuthUser,
+    mut conn: DbConn,
+    invite_info: Form<RevokeGroupInviteIN>,
+) -> ApiResult<QGroupInvites> {
+    match revoke_group_invite(&mut conn, invite_info.invite_id_in, auth.user.user_id) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/redeem-gp-invite", data = "<invite_info>")]
+fn redeem_gp_invite(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    invite_info: Form<RedeemGroupInviteIN>,
+) -> ApiResult<RedeemInviteResponse> {
+    match redeem_group_invite(&mut conn, &invite_info.invite_token_in, auth.user.user_id) {
+        Ok(RedeemOutcome::Joined(res)) => {
+            hub.publish(
+                res.chat_room_id,
+                &RoomEvent::ParticipantAdded {
+                    chat_room_id: res.chat_room_id,
+                    user_id: res.user_id,
+                },
+            );
+            Ok(Json(RedeemInviteResponse {
+                participant: Some(res),
+                join_request: None,
+            }))
+        }
+        Ok(RedeemOutcome::Pending(res)) => Ok(Json(RedeemInviteResponse {
+            participant: None,
+            join_request: Some(res),
+        })),
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/gp-join-requests/<chatroom_id>")]
+fn get_gp_join_requests(
+    auth: AuthUser,
+    mut conn: DbConn,
+    chatroom_id: i32,
+) -> ApiResult<Vec<QJoinRequests>> {
+    match get_pending_join_requests(&mut conn, chatroom_id, auth.user.user_id) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/approve-join-request", data = "<decision>")]
+fn approve_join_request(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    decision: Form<JoinRequestDecisionIN>,
+) -> ApiResult<QJoinRequests> {
+    match decide_join_request(
+        &mut conn,
+        decision.join_request_id_in,
+        auth.user.user_id,
+        true,
+    ) {
+        Ok(res) => {
+            hub.publish(
+                res.chat_room_id,
+                &RoomEvent::ParticipantAdded {
+                    chat_room_id: res.chat_room_id,
+                    user_id: res.user_id,
+                },
+            );
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/reject-join-request", data = "<decision>")]
+fn reject_join_request(
+    auth: AuthUser,
+    mut conn: DbConn,
+    decision: Form<JoinRequestDecisionIN>,
+) -> ApiResult<QJoinRequests> {
+    match decide_join_request(
+        &mut conn,
+        decision.join_request_id_in,
+        auth.user.user_id,
+        false,
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/gp-audit-log/<chatroom_id>?<before>&<limit>")]
+fn get_gp_audit_log(
+    auth: AuthUser,
+    mut conn: DbConn,
+    chatroom_id: i32,
+    before: Option<i32>,
+    limit: Option<i64>,
+) -> ApiResult<Vec<QGroupAuditLog>> {
+    match get_group_audit_log(
+        &mut conn,
+        chatroom_id,
+        auth.user.user_id,
+        before,
+        limit.unwrap_or(MAX_AUDIT_LOG_PAGE_SIZE),
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/promote-member", data = "<role_change>")]
+fn promote_member(
+    auth: AuthUser,
+    conn: DbConn,
+    hub: State<RealtimeHub>,
+    role_change: Form<ChangeParticipantRoleIN>,
+) -> ApiResult<ChatRoomParticipants> {
+    change_member_role(auth, conn, hub, role_change, true)
+}
+
+#[post("/demote-member", data = "<role_change>")]
+fn demote_member(
+    auth: AuthUser,
+    conn: DbConn,
+    hub: State<RealtimeHub>,
+    role_change: Form<ChangeParticipantRoleIN>,
+) -> ApiResult<ChatRoomParticipants> {
+    change_member_role(auth, conn, hub, role_change, false)
+}
+
+// shared by the promote and demote routes, the direction is checked against the current role
+fn change_member_role(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    role_change: Form<ChangeParticipantRoleIN>,
+    promoting: bool,
+) -> ApiResult<ChatRoomParticipants> {
+    let new_role: ParticipantRole;
+    match role_change.role_in.parse() {
+        Ok(res) => new_role = res,
+        Err(e) => return Err(ChatuzaError::Validation(e)),
+    }
+
+    let _chat_room_id;
+    match get_group_chat_by_name(&mut conn, &role_change.chat_room_name_in) {
+        Ok(res) => _chat_room_id = res.chat_room_id,
+        Err(e) => return Err(e),
+    }
 
     let _user_id;
-    match get_user_with_username(&mut conn, &new_wallet_info.username_in) {
+    match get_user_with_username(&mut conn, role_change.username_in.as_str()) {
         Ok(res) => _user_id = res.user_id,
-        Err(e) => return Json(Err(format!("{}", e))),
+        Err(e) => return Err(e),
+    }
+
+    let current_role;
+    match get_participant_role(&mut conn, _chat_room_id, _user_id) {
+        Ok(res) => current_role = res,
+        Err(e) => return Err(e),
+    }
+    if promoting != (new_role.rank() > current_role.rank()) {
+        return Err(ChatuzaError::Validation(format!(
+            "{} to {} isn't a {}",
+            current_role.as_str(),
+            new_role.as_str(),
+            if promoting { "promotion" } else { "demotion" }
+        )));
+    }
+
+    match change_participant_role(
+        &mut conn,
+        _chat_room_id,
+        auth.user.user_id,
+        _user_id,
+        new_role,
+    ) {
+        Ok(res) => {
+            hub.publish(
+                res.chat_room_id,
+                &RoomEvent::ParticipantRoleChanged {
+                    chat_room_id: res.chat_room_id,
+                    user_id: res.user_id,
+                    role: res.role,
+                },
+            );
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
     }
+}
+
+#[post("/send-message", data = "<new_message>")]
+fn send_message_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    new_message: Form<NewMessageIN>,
+) -> ApiResult<QMessages> {
+    match send_message(
+        &mut conn,
+        auth.user.user_id,
+        new_message.chat_room_id_in,
+        new_message.payload_in.as_bytes().to_vec(),
+        new_message.parent_message_id_in,
+    ) {
+        Ok(res) => {
+            hub.publish(res.chat_room_id, &RoomEvent::MessageCreated(&res));
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/edit-message", data = "<edited_message>")]
+fn edit_message_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    edited_message: Form<EditMessageIN>,
+) -> ApiResult<QMessages> {
+    match edit_message(
+        &mut conn,
+        auth.user.user_id,
+        edited_message.message_id_in,
+        edited_message.payload_in.as_bytes().to_vec(),
+    ) {
+        Ok(res) => {
+            hub.publish(res.chat_room_id, &RoomEvent::MessageEdited(&res));
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/delete-message", data = "<deleting_message>")]
+fn delete_message_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    deleting_message: Form<DeleteMessageIN>,
+) -> ApiResult<QMessages> {
+    match delete_message(&mut conn, auth.user.user_id, deleting_message.message_id_in) {
+        Ok(res) => {
+            hub.publish(res.chat_room_id, &RoomEvent::MessageDeleted(&res));
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/chatroom-messages/<chatroom_id>?<before>&<after>&<limit>")]
+fn get_chatroom_messages(
+    auth: AuthUser,
+    mut conn: DbConn,
+    chatroom_id: i32,
+    before: Option<i32>,
+    after: Option<i32>,
+    limit: Option<i64>,
+) -> ApiResult<Vec<QMessages>> {
+    match get_chat_room_messages(
+        &mut conn,
+        auth.user.user_id,
+        chatroom_id,
+        before,
+        after,
+        limit.unwrap_or(MAX_MESSAGES_PAGE_SIZE),
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/add-solana-wallet", data = "<new_wallet_info>")]
+fn add_solana_wallet(
+    auth: AuthUser,
+    mut conn: DbConn,
+    new_wallet_info: Form<NewWalletIn>,
+) -> ApiResult<QSolanaWallet> {
+    let _user_id = auth.user.user_id;
     match initialize_new_solana_wallet(
         &mut conn,
         &SolanaWallet {
@@ -386,57 +1309,266 @@ fn add_solana_wallet(new_wallet_info: Form<NewWalletIn>) -> Json<Result<QSolanaW
             wallet_backup: new_wallet_info.wallet_backup_in.as_bytes().to_vec(),
         },
     ) {
-        Ok(res) => Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{}", e))),
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/delete-solana-wallet")]
+fn delete_solana_wallet_api(auth: FreshMfaUser, mut conn: DbConn) -> ApiResult<bool> {
+    match delete_solana_wallet(&mut conn, &auth.user.username) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[post("/create-token-account", data = "<new_wallet_info>")]
 fn create_token_account_api(
+    auth: FreshMfaUser,
+    mut conn: DbConn,
+    limiter: State<RateLimiter>,
+    solana: State<SolanaContext>,
     new_wallet_info: Form<CreateTokenAccount>,
-) -> Json<Result<CreateTokenAccountResponse, String>> {
-    match create_token_account(&CreateTokenAccount {
-        wallet_address: new_wallet_info.wallet_address.clone(),
-        token_mint_address: new_wallet_info.token_mint_address.clone(),
-        token_program_id: new_wallet_info.token_program_id.clone(),
-        lbh: new_wallet_info.lbh.clone(),
-    }) {
-        Ok(res) => Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{}", e))),
+) -> ApiResult<CreateTokenAccountResponse> {
+    // charged only for a token account that isn't being funded yet
+    match create_token_account(
+        &mut conn,
+        solana.inner(),
+        auth.user.user_id,
+        &new_wallet_info,
+        || {
+            limiter.consume_daily_quota(
+                "/api/create-token-account",
+                auth.user.user_id,
+                &new_wallet_info.wallet_address,
+            )
+        },
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 #[post("/fund-wallet", data = "<wallet_address>")]
-fn fund_wallet(wallet_address: Form<FundWalletIn>) -> Json<Result<String, String>> {
-    match activate_wallet_account_for_transfer(wallet_address.wallet_address.clone()) {
-        Ok(res) => Json(Ok(res)),
-        Err(e) => return Json(Err(format!("{}", e))),
+fn fund_wallet(
+    auth: AuthUser,
+    limiter: State<RateLimiter>,
+    solana: State<SolanaContext>,
+    wallet_address: Form<FundWalletIn>,
+) -> ApiResult<String> {
+    limiter.consume_daily_quota(
+        "/api/fund-wallet",
+        auth.user.user_id,
+        &wallet_address.wallet_address,
+    )?;
+    match activate_wallet_account_for_transfer(
+        solana.inner(),
+        wallet_address.wallet_address.clone(),
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => {
+            // a failed activation doesn't use up the day
+            limiter.refund_daily_quota(
+                "/api/fund-wallet",
+                auth.user.user_id,
+                &wallet_address.wallet_address,
+            );
+            return Err(e);
+        }
+    }
+}
+
+#[post("/build-room-token-transfer", data = "<transfer_info>")]
+fn build_room_token_transfer_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    solana: State<SolanaContext>,
+    transfer_info: Form<RoomTokenTransferIN>,
+) -> ApiResult<RoomTokenTransferResponse> {
+    match build_room_token_transfer(&mut conn, solana.inner(), auth.user.user_id, &transfer_info) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[post("/confirm-room-token-transfer", data = "<confirm_info>")]
+fn confirm_room_token_transfer_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    hub: State<RealtimeHub>,
+    solana: State<SolanaContext>,
+    confirm_info: Form<ConfirmRoomTokenTransferIN>,
+) -> ApiResult<QRoomTokenTransfers> {
+    match confirm_room_token_transfer(
+        &mut conn,
+        solana.inner(),
+        auth.user.user_id,
+        confirm_info.transfer_id_in,
+        &confirm_info.signature_in,
+    ) {
+        Ok(res) => {
+            if res.status == TokenTransferStatus::Confirmed {
+                hub.publish(res.chat_room_id, &RoomEvent::TokenTransferred(&res));
+            }
+            Ok(Json(res))
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+#[get("/room-token-transfers/<chatroom_id>?<before>&<limit>")]
+fn get_room_token_transfers_api(
+    auth: AuthUser,
+    mut conn: DbConn,
+    chatroom_id: i32,
+    before: Option<i32>,
+    limit: Option<i64>,
+) -> ApiResult<Vec<QRoomTokenTransfers>> {
+    match get_room_token_transfers(
+        &mut conn,
+        auth.user.user_id,
+        chatroom_id,
+        before,
+        limit.unwrap_or(MAX_ROOM_TOKEN_TRANSFERS_PAGE_SIZE),
+    ) {
+        Ok(res) => Ok(Json(res)),
+        Err(e) => return Err(e),
     }
 }
 
 #[get("/get-solana-addr-by-username/<username>")]
-fn get_solana_addr(username: String) -> Json<Result<String, String>> {
-    let mut conn = establish_connection();
+fn get_solana_addr(mut conn: DbConn, username: String) -> ApiResult<String> {
     let _user_id;
     match get_user_with_username(&mut conn, &username) {
         Ok(res) => _user_id = res.user_id,
-        Err(e) => return Json(Err(format!("{}", e))),
+        Err(e) => return Err(e),
     }
 
     match get_user_solana_wallet(&mut conn, _user_id) {
-        Ok(res) => Json(Ok(String::from_utf8_lossy(res.wallet_addr.as_slice())
-            .as_ref()
-            .to_string())),
-        Err(e) => return Json(Err(format!("{}", e))),
+        Ok(res) => Ok(Json(
+            String::from_utf8_lossy(res.wallet_addr.as_slice())
+                .as_ref()
+                .to_string(),
+        )),
+        Err(e) => return Err(e),
     }
 }
 
+// -- catchers -- //
+
+// same envelope as `ChatuzaError`, the message comes from the failing guard when there is one
+fn error_envelope(
+    req: &Request,
+    _status: Status,
+    code: &str,
+) -> status::Custom<Json<ApiErrorEnvelope>> {
+    let message = match req.local_cache(|| GuardFailure(String::new())) {
+        GuardFailure(reason) if !reason.is_empty() => reason.clone(),
+        _ => _status.reason.to_owned(),
+    };
+    status::Custom(_status, Json(ApiErrorEnvelope::new(code, message, None)))
+}
+
+#[catch(400)]
+fn bad_request(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::BadRequest, "bad_request")
+}
+
+#[catch(401)]
+fn not_authorized(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::Unauthorized, "unauthorized")
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::Forbidden, "permission_denied")
+}
+
 #[catch(404)]
-fn not_found(req: &Request) -> String {
-    format!("Oh no the {} path doesn't exists !!", req.uri())
+fn not_found(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    status::Custom(
+        Status::NotFound,
+        Json(ApiErrorEnvelope::new(
+            "not_found",
+            format!("the {} path doesn't exist", req.uri()),
+            Some(serde_json::json!({ "path": req.uri().to_string() })),
+        )),
+    )
+}
+
+#[catch(422)]
+fn unprocessable_entity(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::UnprocessableEntity, "validation")
+}
+
+#[catch(500)]
+fn internal_error(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::InternalServerError, "internal")
 }
+
+#[catch(503)]
+fn service_unavailable(req: &Request) -> status::Custom<Json<ApiErrorEnvelope>> {
+    error_envelope(req, Status::ServiceUnavailable, "unavailable")
+}
+
 fn main() {
-    rocket::ignite()
-        .register(catchers![not_found])
+    let hub = RealtimeHub::new();
+    let rocket = rocket::ignite();
+    let pool = match init_pool(&PoolSettings::from_rocket_config(rocket.config())) {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("couldn't create the database pool due to \n {}", e);
+            process::exit(1)
+        }
+    };
+    let ws_address = rocket
+        .config()
+        .get_str("ws_address")
+        .unwrap_or("0.0.0.0:8001")
+        .to_owned();
+    spawn_websocket_gateway(ws_address, hub.clone(), pool.clone());
+    let message_sender =
+        match init_message_sender(&DeliverySettings::from_rocket_config(rocket.config())) {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("couldn't set up the message delivery due to \n {}", e);
+                process::exit(1)
+            }
+        };
+    let rate_limiter = match RateLimitSettings::from_rocket_config(rocket.config())
+        .and_then(|res| init_rate_limiter(&res, &pool))
+    {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("couldn't set up the rate limiter due to \n {}", e);
+            process::exit(1)
+        }
+    };
+    let solana_context = match WalletConfig::from_rocket_config(rocket.config())
+        .and_then(|res| init_solana_context(&res))
+    {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("couldn't set up the solana wallet config due to \n {}", e);
+            process::exit(1)
+        }
+    };
+
+    rocket
+        .manage(hub)
+        .manage(pool)
+        .manage(message_sender)
+        .manage(rate_limiter)
+        .manage(solana_context)
+        .attach(RateLimitFairing)
+        .register(catchers![
+            bad_request,
+            not_authorized,
+            forbidden,
+            not_found,
+            unprocessable_entity,
+            internal_error,
+            service_unavailable
+        ])
         .mount(
             "/api",
             routes![
@@ -454,22 +1586,66 @@ fn main() {
                 validate_chatroom_user,
                 get_all_user_groups,
                 get_all_user_p2p,
+                get_chatroom_messages,
+                get_room_token_transfers_api,
+                get_gp_invites,
+                get_gp_join_requests,
+                get_gp_audit_log,
+                get_incoming_contact_requests,
+                get_outgoing_contact_requests,
+                get_blocked_users_api,
+                search_users_api,
+                get_privacy_settings,
                 // setters
                 new_user,
+                login,
+                login_mfa,
+                refresh_token,
+                logout,
+                logout_all,
+                start_mfa_enrollment_api,
+                confirm_mfa_enrollment_api,
+                verify_mfa_api,
+                regenerate_recovery_codes_api,
+                disable_mfa_api,
                 update_user_conditionals,
                 update_user_profile_api,
+                update_privacy_settings,
+                start_verification_api,
+                confirm_verification_api,
+                forgot_password,
+                reset_password_api,
                 delete_user_via_username,
                 new_p2p,
+                accept_contact_request,
+                decline_contact_request,
+                block_user_api,
+                unblock_user_api,
                 delete_p2p,
                 new_gp,
                 update_gp,
                 delete_gp,
                 add_user_to_gp,
                 delete_user_from_gp,
+                transfer_gp_ownership,
+                set_gp_owner_leave_policy,
+                create_gp_invite,
+                revoke_gp_invite,
+                redeem_gp_invite,
+                approve_join_request,
+                reject_join_request,
+                promote_member,
+                demote_member,
+                send_message_api,
+                edit_message_api,
+                delete_message_api,
                 get_solana_addr,
                 add_solana_wallet,
+                delete_solana_wallet_api,
                 create_token_account_api,
-                fund_wallet
+                fund_wallet,
+                build_room_token_transfer_api,
+                confirm_room_token_transfer_api
             ],
         )
         .launch();
@@ -0,0 +1,217 @@
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// the part of the solana rpc the wallet functions use, so they can run against a real cluster,
+// a local `solana-test-validator` (the localnet cluster) or `MockChainClient`
+pub trait ChainClient: Send + Sync {
+    fn get_latest_blockhash(&self) -> Result<Hash, ClientError>;
+    // false once a transaction built on the blockhash can't land anymore
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, ClientError>;
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Signature, ClientError>;
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError>;
+    // None when the account doesn't exist
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError>;
+    // None when the cluster doesn't know the signature (yet), else the result of the transaction.
+    // older transactions are looked up in the ledger history too
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, ClientError>;
+}
+
+// -- rpc -- //
+
+// every call uses the commitment the client was built with
+impl ChainClient for RpcClient {
+    fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        RpcClient::get_latest_blockhash(self)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, ClientError> {
+        RpcClient::is_blockhash_valid(self, blockhash, self.commitment())
+    }
+
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Signature, ClientError> {
+        RpcClient::send_and_confirm_transaction(self, transaction)
+    }
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+        RpcClient::get_balance(self, pubkey)
+    }
+
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+        match self.get_account_with_commitment(pubkey, self.commitment()) {
+            Ok(res) => Ok(res.value),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, ClientError> {
+        self.get_signature_status_with_commitment_and_history(signature, self.commitment(), true)
+    }
+}
+
+// -- mock -- //
+
+#[derive(Default)]
+struct MockChainState {
+    latest_blockhash: Hash,
+    // the blockhashes handed out so far, a transaction built on another one is refused
+    issued_blockhashes: HashSet<Hash>,
+    balances: HashMap<Pubkey, u64>,
+    accounts: HashMap<Pubkey, Account>,
+    submitted: Vec<Transaction>,
+    statuses: HashMap<Signature, Result<(), TransactionError>>,
+    // returned, in order, by the next sends instead of accepting the transaction
+    queued_send_failures: VecDeque<ClientErrorKind>,
+    // returned by every other call while set, e.g. to play an unreachable node
+    unavailable: Option<String>,
+}
+
+// an in-memory chain: it records the transactions it accepts without executing them, balances
+// and accounts are whatever the caller set. clones share the chain, so a test can keep one to
+// look into after handing another to a `SolanaContext`
+#[derive(Clone, Default)]
+pub struct MockChainClient {
+    state: Arc<Mutex<MockChainState>>,
+}
+
+impl MockChainClient {
+    pub fn new() -> Self {
+        let mock = Self::default();
+        mock.advance_blockhash();
+        mock
+    }
+
+    // a new latest blockhash, the previous ones stay valid like on a real cluster
+    pub fn advance_blockhash(&self) -> Hash {
+        let mut state = self.state.lock().unwrap();
+        state.latest_blockhash = Hash::new_unique();
+        let latest_blockhash = state.latest_blockhash;
+        state.issued_blockhashes.insert(latest_blockhash);
+        latest_blockhash
+    }
+
+    // the transactions built on the blockhashes issued so far fail with BlockhashNotFound
+    pub fn expire_blockhashes(&self) {
+        self.state.lock().unwrap().issued_blockhashes.clear();
+        self.advance_blockhash();
+    }
+
+    pub fn set_balance(&self, pubkey: Pubkey, lamports: u64) {
+        self.state.lock().unwrap().balances.insert(pubkey, lamports);
+    }
+
+    pub fn set_account(&self, pubkey: Pubkey, account: Account) {
+        self.state.lock().unwrap().accounts.insert(pubkey, account);
+    }
+
+    pub fn fail_next_send(&self, kind: ClientErrorKind) {
+        self.state
+            .lock()
+            .unwrap()
+            .queued_send_failures
+            .push_back(kind);
+    }
+
+    pub fn set_unavailable(&self, reason: Option<String>) {
+        self.state.lock().unwrap().unavailable = reason;
+    }
+
+    pub fn submitted_transactions(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().submitted.clone()
+    }
+
+    fn check_available(state: &MockChainState) -> Result<(), ClientError> {
+        match &state.unavailable {
+            Some(reason) => Err(ClientErrorKind::Custom(reason.clone()).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ChainClient for MockChainClient {
+    fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        let state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        Ok(state.latest_blockhash)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool, ClientError> {
+        let state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        Ok(state.issued_blockhashes.contains(blockhash))
+    }
+
+    fn send_and_confirm_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Signature, ClientError> {
+        let mut state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        if let Some(kind) = state.queued_send_failures.pop_front() {
+            return Err(kind.into());
+        }
+        if !state
+            .issued_blockhashes
+            .contains(&transaction.message.recent_blockhash)
+        {
+            return Err(
+                ClientErrorKind::TransactionError(TransactionError::BlockhashNotFound).into(),
+            );
+        }
+        if transaction.verify().is_err() {
+            return Err(
+                ClientErrorKind::TransactionError(TransactionError::SignatureFailure).into(),
+            );
+        }
+
+        let signature = transaction.signatures[0];
+        // a resend of a landed transaction is answered like the cluster does
+        if state.statuses.contains_key(&signature) {
+            return Err(
+                ClientErrorKind::TransactionError(TransactionError::AlreadyProcessed).into(),
+            );
+        }
+        state.submitted.push(transaction.clone());
+        state.statuses.insert(signature, Ok(()));
+        Ok(signature)
+    }
+
+    fn get_balance(&self, pubkey: &Pubkey) -> Result<u64, ClientError> {
+        let state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        Ok(*state.balances.get(pubkey).unwrap_or(&0))
+    }
+
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, ClientError> {
+        let state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        Ok(state.accounts.get(pubkey).cloned())
+    }
+
+    fn get_signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, ClientError> {
+        let state = self.state.lock().unwrap();
+        Self::check_available(&state)?;
+        Ok(state.statuses.get(signature).cloned())
+    }
+}
@@ -0,0 +1,347 @@
+use crate::api_models::ContactRequestResponse;
+use crate::db_models::{
+    ContactRequestStatus, ContactRequests, QContactRequests, QUserBlocks, QUsers, UserBlocks,
+};
+use crate::errors::ChatuzaError;
+use crate::schema::{contact_requests, user_blocks};
+use crate::{
+    add_new_p2p_chat_room, get_two_users_p2p_chat_room, get_user_with_username, is_valid_user,
+};
+use chrono::{Duration, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+pub const CONTACT_REQUEST_TTL_SECS: i64 = 14 * 24 * 60 * 60;
+
+// -- Blocks -- //
+
+pub fn has_blocked(_conn: &mut PgConnection, _blocker_id: i32, _blocked_id: i32) -> bool {
+    let block_rows: Vec<QUserBlocks> = user_blocks::table
+        .filter(
+            user_blocks::blocker_id
+                .eq(_blocker_id)
+                .and(user_blocks::blocked_id.eq(_blocked_id)),
+        )
+        .select(QUserBlocks::as_select())
+        .load(_conn)
+        .unwrap_or(vec![]);
+    block_rows.len() == 1
+}
+
+// true when any of the two users blocked the other one
+pub fn is_blocked_between(_conn: &mut PgConnection, _user_id_1: i32, _user_id_2: i32) -> bool {
+    has_blocked(_conn, _user_id_1, _user_id_2) || has_blocked(_conn, _user_id_2, _user_id_1)
+}
+
+pub fn block_user(
+    _conn: &mut PgConnection,
+    _blocker_id: i32,
+    _blocked_id: i32,
+) -> Result<QUserBlocks, ChatuzaError> {
+    if _blocker_id == _blocked_id {
+        return Err(ChatuzaError::Validation(
+            "users can't block themselves".to_owned(),
+        ));
+    }
+    if !is_valid_user(_conn, _blocked_id) {
+        return Err(ChatuzaError::NotFound("user id", _blocked_id.to_string()));
+    }
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let block;
+        match diesel::insert_into(user_blocks::table)
+            .values(&UserBlocks {
+                blocker_id: _blocker_id,
+                blocked_id: _blocked_id,
+            })
+            .returning(QUserBlocks::as_returning())
+            .get_result(_conn)
+        {
+            Ok(res) => block = res,
+            Err(diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => {
+                return Err(ChatuzaError::AlreadyExists(format!(
+                    "user id {} is already blocked",
+                    _blocked_id
+                )))
+            }
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        // the pending requests between the two are dropped with the block
+        if let Err(e) = diesel::update(
+            contact_requests::table.filter(
+                contact_requests::status
+                    .eq(ContactRequestStatus::Pending)
+                    .and(
+                        contact_requests::requester_id
+                            .eq(_blocker_id)
+                            .and(contact_requests::addressee_id.eq(_blocked_id))
+                            .or(contact_requests::requester_id
+                                .eq(_blocked_id)
+                                .and(contact_requests::addressee_id.eq(_blocker_id))),
+                    ),
+            ),
+        )
+        .set((
+            contact_requests::status.eq(ContactRequestStatus::Declined),
+            contact_requests::responded_at.eq(Utc::now().naive_utc()),
+        ))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        Ok(block)
+    })
+}
+
+pub fn unblock_user(
+    _conn: &mut PgConnection,
+    _blocker_id: i32,
+    _blocked_id: i32,
+) -> Result<bool, ChatuzaError> {
+    match diesel::delete(
+        user_blocks::table.filter(
+            user_blocks::blocker_id
+                .eq(_blocker_id)
+                .and(user_blocks::blocked_id.eq(_blocked_id)),
+        ),
+    )
+    .execute(_conn)
+    {
+        Ok(0) => Err(ChatuzaError::NotFound(
+            "block of user id",
+            _blocked_id.to_string(),
+        )),
+        Ok(_) => Ok(true),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn get_blocked_users(
+    _conn: &mut PgConnection,
+    _blocker_id: i32,
+) -> Result<Vec<QUserBlocks>, ChatuzaError> {
+    match user_blocks::table
+        .filter(user_blocks::blocker_id.eq(_blocker_id))
+        .order(user_blocks::block_id.desc())
+        .select(QUserBlocks::as_select())
+        .load(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the ids blocked by the user, the realtime hub drops their messages before delivery
+pub fn get_blocked_user_ids(_conn: &mut PgConnection, _blocker_id: i32) -> Vec<i32> {
+    user_blocks::table
+        .filter(user_blocks::blocker_id.eq(_blocker_id))
+        .select(user_blocks::blocked_id)
+        .load(_conn)
+        .unwrap_or(vec![])
+}
+
+// a user who got blocked can't find the blocker, the lookup fails like for a missing username
+pub fn get_visible_user_with_username(
+    _conn: &mut PgConnection,
+    _username: &str,
+    viewer_user_id: Option<i32>,
+) -> Result<QUsers, ChatuzaError> {
+    let user_info = get_user_with_username(_conn, _username)?;
+    if let Some(viewer_id) = viewer_user_id {
+        if has_blocked(_conn, user_info.user_id, viewer_id) {
+            return Err(ChatuzaError::NotFound("username", _username.to_string()));
+        }
+    }
+    Ok(user_info)
+}
+
+// -- Contact requests SETTER functions -- //
+
+pub fn send_contact_request(
+    _conn: &mut PgConnection,
+    requester_id: i32,
+    addressee_id: i32,
+    _chat_room_pubkey: String,
+) -> Result<QContactRequests, ChatuzaError> {
+    if requester_id == addressee_id {
+        return Err(ChatuzaError::Validation(
+            "users can't send a contact request to themselves".to_owned(),
+        ));
+    }
+    if has_blocked(_conn, requester_id, addressee_id) {
+        return Err(ChatuzaError::Validation(format!(
+            "user id {} has to be unblocked first",
+            addressee_id
+        )));
+    }
+    // being blocked looks the same as the user not existing
+    if !is_valid_user(_conn, addressee_id) || has_blocked(_conn, addressee_id, requester_id) {
+        return Err(ChatuzaError::NotFound("user id", addressee_id.to_string()));
+    }
+    if get_two_users_p2p_chat_room(_conn, requester_id, addressee_id).is_ok() {
+        return Err(ChatuzaError::AlreadyExists(format!(
+            "user id {} already has a p2p chat with user id {}",
+            requester_id, addressee_id
+        )));
+    }
+
+    expire_contact_requests(_conn)?;
+    match diesel::insert_into(contact_requests::table)
+        .values(&ContactRequests {
+            requester_id,
+            addressee_id,
+            chat_room_pubkey: _chat_room_pubkey.as_bytes().to_vec(),
+            expires_at: (Utc::now() + Duration::seconds(CONTACT_REQUEST_TTL_SECS)).naive_utc(),
+        })
+        .returning(QContactRequests::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Err(ChatuzaError::AlreadyExists(format!(
+            "user id {} already has a pending contact request to user id {}",
+            requester_id, addressee_id
+        ))),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// only the addressee answers, accepting is what creates the p2p chat room
+pub fn respond_to_contact_request(
+    _conn: &mut PgConnection,
+    _contact_request_id: i32,
+    responder_id: i32,
+    accept: bool,
+) -> Result<ContactRequestResponse, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        expire_contact_requests(_conn)?;
+
+        let contact_request = get_contact_request_by_id(_conn, _contact_request_id)?;
+        if contact_request.addressee_id != responder_id {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "answer the contact request id {}",
+                _contact_request_id
+            )));
+        }
+        if contact_request.status != ContactRequestStatus::Pending {
+            return Err(ChatuzaError::Validation(format!(
+                "contact request id {} is already {}",
+                _contact_request_id,
+                match contact_request.status {
+                    ContactRequestStatus::Accepted => "accepted",
+                    ContactRequestStatus::Declined => "declined",
+                    _ => "expired",
+                }
+            )));
+        }
+
+        let mut chat_room = None;
+        if accept {
+            match add_new_p2p_chat_room(
+                _conn,
+                contact_request.requester_id,
+                contact_request.addressee_id,
+                String::from_utf8_lossy(&contact_request.chat_room_pubkey).to_string(),
+            ) {
+                Ok(res) => chat_room = Some(res),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let answered_request;
+        match diesel::update(
+            contact_requests::table
+                .filter(contact_requests::contact_request_id.eq(_contact_request_id)),
+        )
+        .set((
+            contact_requests::status.eq(if accept {
+                ContactRequestStatus::Accepted
+            } else {
+                ContactRequestStatus::Declined
+            }),
+            contact_requests::responded_at.eq(Utc::now().naive_utc()),
+            contact_requests::chat_room_id.eq(chat_room.as_ref().map(|res| res.chat_room_id)),
+        ))
+        .returning(QContactRequests::as_returning())
+        .get_result(_conn)
+        {
+            Ok(res) => answered_request = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        Ok(ContactRequestResponse {
+            contact_request: answered_request,
+            chat_room,
+        })
+    })
+}
+
+// flips every pending request past its expiry to expired
+pub fn expire_contact_requests(_conn: &mut PgConnection) -> Result<usize, ChatuzaError> {
+    match diesel::update(
+        contact_requests::table.filter(
+            contact_requests::status
+                .eq(ContactRequestStatus::Pending)
+                .and(contact_requests::expires_at.le(Utc::now().naive_utc())),
+        ),
+    )
+    .set(contact_requests::status.eq(ContactRequestStatus::Expired))
+    .execute(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- Contact requests GETTER functions -- //
+
+pub fn get_contact_request_by_id(
+    _conn: &mut PgConnection,
+    _contact_request_id: i32,
+) -> Result<QContactRequests, ChatuzaError> {
+    match contact_requests::table
+        .filter(contact_requests::contact_request_id.eq(_contact_request_id))
+        .select(QContactRequests::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(ChatuzaError::NotFound(
+            "contact request id",
+            _contact_request_id.to_string(),
+        )),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the pending requests sent to the user (incoming) or by the user (outgoing), oldest first
+pub fn get_pending_contact_requests(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    incoming: bool,
+) -> Result<Vec<QContactRequests>, ChatuzaError> {
+    expire_contact_requests(_conn)?;
+
+    let mut query = contact_requests::table
+        .filter(contact_requests::status.eq(ContactRequestStatus::Pending))
+        .order(contact_requests::contact_request_id.asc())
+        .select(QContactRequests::as_select())
+        .into_boxed();
+    if incoming {
+        query = query.filter(contact_requests::addressee_id.eq(_user_id));
+    } else {
+        query = query.filter(contact_requests::requester_id.eq(_user_id));
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
@@ -1,9 +1,475 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
 // use merge_derivable;
-use crate::schema::chat_room_participants;
 use serde::{self, Deserialize, Serialize};
+use std::io::Write;
+use std::str::FromStr;
 use struct_iterable::Iterable;
 
+// maps the `room_kind` postgres enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::RoomKind)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomKind {
+    Direct,
+    Group,
+    Channel,
+}
+
+impl RoomKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            RoomKind::Direct => "direct",
+            RoomKind::Group => "group",
+            RoomKind::Channel => "channel",
+        }
+    }
+}
+
+impl FromStr for RoomKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "direct" => Ok(RoomKind::Direct),
+            "group" => Ok(RoomKind::Group),
+            "channel" => Ok(RoomKind::Channel),
+            _ => Err(format!("{} isn't a room kind", kind)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::RoomKind, Pg> for RoomKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::RoomKind, Pg> for RoomKind {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(RoomKind::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `owner_leave_policy` postgres enum, what happens to a group when its owner leaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::OwnerLeavePolicy)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnerLeavePolicy {
+    // hand the group to the highest ranked, longest standing participant
+    Transfer,
+    DeleteRoom,
+}
+
+impl OwnerLeavePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OwnerLeavePolicy::Transfer => "transfer",
+            OwnerLeavePolicy::DeleteRoom => "delete_room",
+        }
+    }
+}
+
+impl FromStr for OwnerLeavePolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "transfer" => Ok(OwnerLeavePolicy::Transfer),
+            "delete_room" => Ok(OwnerLeavePolicy::DeleteRoom),
+            _ => Err(format!("{} isn't an owner leave policy", policy)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::OwnerLeavePolicy, Pg> for OwnerLeavePolicy {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::OwnerLeavePolicy, Pg> for OwnerLeavePolicy {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(OwnerLeavePolicy::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `profile_visibility` postgres enum, who gets to see a field of a profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::ProfileVisibility)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileVisibility {
+    Everyone,
+    // the users sharing a p2p chat room with the owner
+    Contacts,
+    Nobody,
+}
+
+impl ProfileVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ProfileVisibility::Everyone => "everyone",
+            ProfileVisibility::Contacts => "contacts",
+            ProfileVisibility::Nobody => "nobody",
+        }
+    }
+}
+
+impl FromStr for ProfileVisibility {
+    type Err = String;
+
+    fn from_str(visibility: &str) -> Result<Self, Self::Err> {
+        match visibility {
+            "everyone" => Ok(ProfileVisibility::Everyone),
+            "contacts" => Ok(ProfileVisibility::Contacts),
+            "nobody" => Ok(ProfileVisibility::Nobody),
+            _ => Err(format!("{} isn't a profile visibility", visibility)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::ProfileVisibility, Pg> for ProfileVisibility {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::ProfileVisibility, Pg> for ProfileVisibility {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(ProfileVisibility::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `verification_channel` postgres enum, where a verification code gets delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::VerificationChannel)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationChannel {
+    Email,
+    Phone,
+}
+
+impl VerificationChannel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            VerificationChannel::Email => "email",
+            VerificationChannel::Phone => "phone",
+        }
+    }
+}
+
+impl FromStr for VerificationChannel {
+    type Err = String;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel {
+            "email" => Ok(VerificationChannel::Email),
+            "phone" => Ok(VerificationChannel::Phone),
+            _ => Err(format!("{} isn't a verification channel", channel)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::VerificationChannel, Pg> for VerificationChannel {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::VerificationChannel, Pg> for VerificationChannel {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(VerificationChannel::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `contact_request_status` postgres enum, pending requests past their expiry are
+// flipped to expired lazily by the contact lib
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::ContactRequestStatus)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactRequestStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Expired,
+}
+
+impl ContactRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ContactRequestStatus::Pending => "pending",
+            ContactRequestStatus::Accepted => "accepted",
+            ContactRequestStatus::Declined => "declined",
+            ContactRequestStatus::Expired => "expired",
+        }
+    }
+}
+
+impl FromStr for ContactRequestStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(ContactRequestStatus::Pending),
+            "accepted" => Ok(ContactRequestStatus::Accepted),
+            "declined" => Ok(ContactRequestStatus::Declined),
+            "expired" => Ok(ContactRequestStatus::Expired),
+            _ => Err(format!("{} isn't a contact request status", status)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::ContactRequestStatus, Pg> for ContactRequestStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::ContactRequestStatus, Pg> for ContactRequestStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(ContactRequestStatus::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `join_request_status` postgres enum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::JoinRequestStatus)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl JoinRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            JoinRequestStatus::Pending => "pending",
+            JoinRequestStatus::Approved => "approved",
+            JoinRequestStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl FromStr for JoinRequestStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(JoinRequestStatus::Pending),
+            "approved" => Ok(JoinRequestStatus::Approved),
+            "rejected" => Ok(JoinRequestStatus::Rejected),
+            _ => Err(format!("{} isn't a join request status", status)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::JoinRequestStatus, Pg> for JoinRequestStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::JoinRequestStatus, Pg> for JoinRequestStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(JoinRequestStatus::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `participant_role` postgres enum, ordered from the most to the least privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::ParticipantRole)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantRole {
+    Owner,
+    Admin,
+    Moderator,
+    Member,
+    ReadOnly,
+}
+
+impl ParticipantRole {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ParticipantRole::Owner => "owner",
+            ParticipantRole::Admin => "admin",
+            ParticipantRole::Moderator => "moderator",
+            ParticipantRole::Member => "member",
+            ParticipantRole::ReadOnly => "read_only",
+        }
+    }
+
+    // higher ranks can manage the lower ones
+    pub fn rank(&self) -> u8 {
+        match *self {
+            ParticipantRole::Owner => 4,
+            ParticipantRole::Admin => 3,
+            ParticipantRole::Moderator => 2,
+            ParticipantRole::Member => 1,
+            ParticipantRole::ReadOnly => 0,
+        }
+    }
+}
+
+impl FromStr for ParticipantRole {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "owner" => Ok(ParticipantRole::Owner),
+            "admin" => Ok(ParticipantRole::Admin),
+            "moderator" => Ok(ParticipantRole::Moderator),
+            "member" => Ok(ParticipantRole::Member),
+            "read_only" => Ok(ParticipantRole::ReadOnly),
+            _ => Err(format!("{} isn't a participant role", role)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::ParticipantRole, Pg> for ParticipantRole {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::ParticipantRole, Pg> for ParticipantRole {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(ParticipantRole::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `funding_status` postgres enum, a submitted funding transaction may or may not have
+// landed and is looked up before anything gets sent again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::FundingStatus)]
+#[serde(rename_all = "snake_case")]
+pub enum FundingStatus {
+    Unfunded,
+    Submitted,
+    Confirmed,
+}
+
+impl FundingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            FundingStatus::Unfunded => "unfunded",
+            FundingStatus::Submitted => "submitted",
+            FundingStatus::Confirmed => "confirmed",
+        }
+    }
+}
+
+impl FromStr for FundingStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "unfunded" => Ok(FundingStatus::Unfunded),
+            "submitted" => Ok(FundingStatus::Submitted),
+            "confirmed" => Ok(FundingStatus::Confirmed),
+            _ => Err(format!("{} isn't a funding status", status)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::FundingStatus, Pg> for FundingStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::FundingStatus, Pg> for FundingStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(FundingStatus::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// maps the `token_transfer_status` postgres enum, a room token transfer is pending until its
+// sender reports a signature the cluster has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[diesel(sql_type = crate::schema::sql_types::TokenTransferStatus)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTransferStatus {
+    Pending,
+    Confirmed,
+    Failed,
+}
+
+impl TokenTransferStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TokenTransferStatus::Pending => "pending",
+            TokenTransferStatus::Confirmed => "confirmed",
+            TokenTransferStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for TokenTransferStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(TokenTransferStatus::Pending),
+            "confirmed" => Ok(TokenTransferStatus::Confirmed),
+            "failed" => Ok(TokenTransferStatus::Failed),
+            _ => Err(format!("{} isn't a token transfer status", status)),
+        }
+    }
+}
+
+impl ToSql<crate::schema::sql_types::TokenTransferStatus, Pg> for TokenTransferStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<crate::schema::sql_types::TokenTransferStatus, Pg> for TokenTransferStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match std::str::from_utf8(bytes.as_bytes()) {
+            Ok(res) => Ok(TokenTransferStatus::from_str(res)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable, PartialEq)]
 #[diesel(table_name = crate::schema::users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -33,6 +499,11 @@ pub struct ChatRooms {
     pub room_name: String,
     pub room_description: String,
     pub chat_room_pubkey: Vec<u8>,
+    pub room_kind: RoomKind,
+    // only set on direct rooms, the lower user id always goes first
+    pub direct_user_low: Option<i32>,
+    pub direct_user_high: Option<i32>,
+    pub owner_leave_policy: OwnerLeavePolicy,
 }
 
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
@@ -51,7 +522,7 @@ pub struct ChatRoomParticipants {
     // pub participant_id: i32,
     pub chat_room_id: i32,
     pub user_id: i32,
-    pub is_admin: bool,
+    pub role: ParticipantRole,
 }
 
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
@@ -62,6 +533,179 @@ pub struct SolanaWallet {
     pub wallet_addr: Vec<u8>,
     pub wallet_backup: Vec<u8>,
 }
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Sessions {
+    pub user_id: i32,
+    pub refresh_token_hash: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+    pub mfa_verified_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::password_reset_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PasswordResetTokens {
+    pub user_id: i32,
+    pub token_hash: Vec<u8>,
+    pub channel: VerificationChannel,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::user_mfa)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserMfa {
+    pub user_id: i32,
+    pub totp_secret: Vec<u8>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::mfa_recovery_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MfaRecoveryCodes {
+    pub user_id: i32,
+    pub code_hash: Vec<u8>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::mfa_login_challenges)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MfaLoginChallenges {
+    pub user_id: i32,
+    pub challenge_hash: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::rate_limit_buckets)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RateLimitBuckets {
+    pub bucket_key: String,
+    pub tokens: f64,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::rate_limit_quotas)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RateLimitQuotas {
+    pub quota_key: String,
+    pub quota_day: NaiveDate,
+    pub used: i32,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::token_account_fundings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TokenAccountFundings {
+    pub user_id: i32,
+    pub wallet_address: String,
+    pub token_mint_address: String,
+    pub token_program_id: String,
+    pub token_account_address: String,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::room_token_transfers)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RoomTokenTransfers {
+    pub chat_room_id: i32,
+    pub sender_id: i32,
+    pub recipient_id: i32,
+    pub sender_wallet_address: String,
+    pub recipient_wallet_address: String,
+    pub token_mint_address: String,
+    pub token_program_id: String,
+    pub amount: i64,
+    pub decimals: i16,
+    pub transaction_message: Vec<u8>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Messages {
+    pub chat_room_id: i32,
+    pub sender_id: Option<i32>,
+    pub payload: Vec<u8>,
+    pub parent_message_id: Option<i32>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::group_invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct GroupInvites {
+    pub chat_room_id: i32,
+    pub created_by: Option<i32>,
+    pub token_hash: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+    pub max_uses: Option<i32>,
+    pub requires_approval: bool,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::join_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct JoinRequests {
+    pub chat_room_id: i32,
+    pub user_id: i32,
+    pub invite_id: Option<i32>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::group_audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct GroupAuditLog {
+    pub chat_room_id: i32,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target_user_id: Option<i32>,
+    pub invite_id: Option<i32>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::contact_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ContactRequests {
+    pub requester_id: i32,
+    pub addressee_id: i32,
+    pub chat_room_pubkey: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::user_blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserBlocks {
+    pub blocker_id: i32,
+    pub blocked_id: i32,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::verification_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct VerificationCodes {
+    pub user_id: i32,
+    pub channel: VerificationChannel,
+    pub target: String,
+    pub code_hash: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
+#[diesel(table_name = crate::schema::user_privacy_settings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserPrivacySettings {
+    pub user_id: i32,
+    pub discoverable: bool,
+    pub email_visibility: ProfileVisibility,
+    pub phone_visibility: ProfileVisibility,
+    pub bio_visibility: ProfileVisibility,
+    pub avatar_visibility: ProfileVisibility,
+}
 // --  models with queryable primary keys -- //
 
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
@@ -71,8 +715,11 @@ pub struct QUsers {
     pub user_id: i32,
     pub username: String,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password: String,
     pub phone_number: String,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub phone_verified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -80,7 +727,6 @@ pub struct QUsersResponse {
     pub user_id: i32,
     pub username: String,
     pub email: String,
-    pub password: String,
     pub phone_number: String,
     pub bio: String,
     pub profile_picture: String,
@@ -94,6 +740,10 @@ pub struct QChatRooms {
     pub room_name: String,
     pub room_description: String,
     pub chat_room_pubkey: Vec<u8>,
+    pub room_kind: RoomKind,
+    pub direct_user_low: Option<i32>,
+    pub direct_user_high: Option<i32>,
+    pub owner_leave_policy: OwnerLeavePolicy,
 }
 
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
@@ -103,28 +753,10 @@ pub struct QChatRoomParticipants {
     pub participant_id: i32,
     pub chat_room_id: i32,
     pub user_id: i32,
-    pub is_admin: bool,
-}
-
-macro_rules! allow_group_by {
-    ($group_by:ty, $($col_for: ty),+) => {
-        $(
-            impl
-                ::diesel::expression::ValidGrouping<$group_by> for $col_for
-            {
-                type IsAggregate = ::diesel::expression::is_aggregate::Yes;
-            }
-        )+
-    };
+    pub role: ParticipantRole,
+    pub joined_at: NaiveDateTime,
 }
 
-allow_group_by!(
-    chat_room_participants::columns::chat_room_id,
-    chat_room_participants::columns::participant_id,
-    chat_room_participants::user_id,
-    chat_room_participants::is_admin
-);
-
 #[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Insertable, Iterable)]
 #[diesel(table_name = crate::schema::solana_wallets)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -133,3 +765,212 @@ pub struct QSolanaWallet {
     pub user_id: i32,
     pub wallet_addr: Vec<u8>,
 }
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QSessions {
+    pub session_id: i32,
+    pub user_id: i32,
+    pub refresh_token_hash: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub mfa_verified_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::messages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QMessages {
+    pub message_id: i32,
+    pub chat_room_id: i32,
+    pub sender_id: Option<i32>,
+    pub payload: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub parent_message_id: Option<i32>,
+    pub edited_at: Option<NaiveDateTime>,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+// the token hash never leaves the server
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::group_invites)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QGroupInvites {
+    pub invite_id: i32,
+    pub chat_room_id: i32,
+    pub created_by: Option<i32>,
+    #[serde(skip_serializing)]
+    pub token_hash: Vec<u8>,
+    pub expires_at: NaiveDateTime,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub requires_approval: bool,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::join_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QJoinRequests {
+    pub join_request_id: i32,
+    pub chat_room_id: i32,
+    pub user_id: i32,
+    pub invite_id: Option<i32>,
+    pub status: JoinRequestStatus,
+    pub created_at: NaiveDateTime,
+    pub decided_by: Option<i32>,
+    pub decided_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::group_audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QGroupAuditLog {
+    pub audit_id: i32,
+    pub chat_room_id: i32,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target_user_id: Option<i32>,
+    pub invite_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::contact_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QContactRequests {
+    pub contact_request_id: i32,
+    pub requester_id: i32,
+    pub addressee_id: i32,
+    pub chat_room_pubkey: Vec<u8>,
+    pub status: ContactRequestStatus,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub responded_at: Option<NaiveDateTime>,
+    pub chat_room_id: Option<i32>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::user_blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QUserBlocks {
+    pub block_id: i32,
+    pub blocker_id: i32,
+    pub blocked_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::user_privacy_settings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QUserPrivacySettings {
+    pub user_id: i32,
+    pub discoverable: bool,
+    pub updated_at: NaiveDateTime,
+    pub email_visibility: ProfileVisibility,
+    pub phone_visibility: ProfileVisibility,
+    pub bio_visibility: ProfileVisibility,
+    pub avatar_visibility: ProfileVisibility,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::verification_codes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QVerificationCodes {
+    pub verification_id: i32,
+    pub user_id: i32,
+    pub channel: VerificationChannel,
+    pub target: String,
+    #[serde(skip_serializing)]
+    pub code_hash: Vec<u8>,
+    pub attempts: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub consumed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::password_reset_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QPasswordResetTokens {
+    pub reset_token_id: i32,
+    pub user_id: i32,
+    #[serde(skip_serializing)]
+    pub token_hash: Vec<u8>,
+    pub channel: VerificationChannel,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::user_mfa)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QUserMfa {
+    pub user_id: i32,
+    #[serde(skip_serializing)]
+    pub totp_secret: Vec<u8>,
+    pub enabled_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub last_used_step: Option<i64>,
+    pub failed_attempts: i32,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::mfa_login_challenges)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QMfaLoginChallenges {
+    pub challenge_id: i32,
+    pub user_id: i32,
+    #[serde(skip_serializing)]
+    pub challenge_hash: Vec<u8>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub consumed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::token_account_fundings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QTokenAccountFundings {
+    pub funding_id: i32,
+    pub user_id: i32,
+    pub wallet_address: String,
+    pub token_mint_address: String,
+    pub token_program_id: String,
+    pub token_account_address: String,
+    pub token_account_created: bool,
+    pub token_account_signature: Option<String>,
+    pub funding_status: FundingStatus,
+    pub funding_signature: Option<String>,
+    pub funding_blockhash: Option<String>,
+    pub locked_until: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Deserialize, Serialize, Selectable, Debug, Iterable)]
+#[diesel(table_name = crate::schema::room_token_transfers)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QRoomTokenTransfers {
+    pub transfer_id: i32,
+    pub chat_room_id: i32,
+    pub sender_id: i32,
+    pub recipient_id: i32,
+    pub sender_wallet_address: String,
+    pub recipient_wallet_address: String,
+    pub token_mint_address: String,
+    pub token_program_id: String,
+    pub amount: i64,
+    pub decimals: i16,
+    #[serde(skip_serializing)]
+    pub transaction_message: Vec<u8>,
+    pub status: TokenTransferStatus,
+    pub signature: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub confirmed_at: Option<NaiveDateTime>,
+}
@@ -0,0 +1,96 @@
+use crate::errors::ChatuzaError;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use dotenvy::dotenv;
+use rocket::Config;
+use std::env;
+use std::time::Duration;
+
+pub type PgPool = Pool<ConnectionManager<PgConnection>>;
+pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+// read from the `db_pool_*` extras of Rocket.toml, the defaults are used for the missing keys
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout_secs: u64,
+    pub idle_timeout_secs: Option<u64>,
+    pub max_lifetime_secs: Option<u64>,
+    // runs a `SELECT 1` health check before a connection is handed out
+    pub test_on_check_out: bool,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout_secs: 5,
+            idle_timeout_secs: Some(600),
+            max_lifetime_secs: Some(1800),
+            test_on_check_out: true,
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn from_rocket_config(config: &Config) -> Self {
+        let defaults = PoolSettings::default();
+        // negative values are ignored, a 0 timeout switches the optional ones off
+        let positive = |key: &str| config.get_int(key).ok().filter(|res| *res >= 0);
+
+        PoolSettings {
+            max_size: match positive("db_pool_max_size") {
+                Some(res) if res > 0 => res as u32,
+                _ => defaults.max_size,
+            },
+            min_idle: match positive("db_pool_min_idle") {
+                Some(res) => Some(res as u32),
+                None => defaults.min_idle,
+            },
+            connection_timeout_secs: match positive("db_pool_connection_timeout_secs") {
+                Some(res) if res > 0 => res as u64,
+                _ => defaults.connection_timeout_secs,
+            },
+            idle_timeout_secs: match positive("db_pool_idle_timeout_secs") {
+                Some(0) => None,
+                Some(res) => Some(res as u64),
+                None => defaults.idle_timeout_secs,
+            },
+            max_lifetime_secs: match positive("db_pool_max_lifetime_secs") {
+                Some(0) => None,
+                Some(res) => Some(res as u64),
+                None => defaults.max_lifetime_secs,
+            },
+            test_on_check_out: config
+                .get_bool("db_pool_test_on_check_out")
+                .unwrap_or(defaults.test_on_check_out),
+        }
+    }
+}
+
+// the pool is built without opening any connection so the server still boots while postgres
+// is down, the requests made in the meantime get a 503 once `connection_timeout` runs out
+pub fn init_pool(settings: &PoolSettings) -> Result<PgPool, ChatuzaError> {
+    // loading the env vars into the current scope
+    dotenv().ok();
+
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(res) => res,
+        Err(_) => {
+            return Err(ChatuzaError::Internal(
+                "DATABASE_URL must be set".to_owned(),
+            ))
+        }
+    };
+
+    Ok(Pool::builder()
+        .max_size(settings.max_size)
+        .min_idle(settings.min_idle)
+        .connection_timeout(Duration::from_secs(settings.connection_timeout_secs))
+        .idle_timeout(settings.idle_timeout_secs.map(Duration::from_secs))
+        .max_lifetime(settings.max_lifetime_secs.map(Duration::from_secs))
+        .test_on_check_out(settings.test_on_check_out)
+        .build_unchecked(ConnectionManager::<PgConnection>::new(database_url)))
+}
@@ -0,0 +1,313 @@
+use crate::db_models::VerificationChannel;
+use crate::errors::ChatuzaError;
+use chrono::Utc;
+use dotenvy::dotenv;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use rocket::Config;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// what gets handed to a sender, the subject is ignored by the sms backends
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    pub channel: VerificationChannel,
+    // an email address or an e.164 phone number
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// anything an email or an sms can be pushed through, errors mean the message wasn't accepted
+pub trait MessageSender: Send + Sync {
+    fn send(&self, message: &OutgoingMessage) -> Result<(), ChatuzaError>;
+}
+
+// -- SMTP -- //
+
+pub struct SmtpSender {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpSender {
+    // STARTTLS on the given port, the password is read from the SMTP_PASSWORD env var
+    pub fn new(host: &str, port: u16, username: &str, from: &str) -> Result<Self, ChatuzaError> {
+        dotenv().ok();
+        let password = env::var("SMTP_PASSWORD").unwrap_or_default();
+
+        let transport = match SmtpTransport::starttls_relay(host) {
+            Ok(res) => res
+                .port(port)
+                .credentials(Credentials::new(username.to_owned(), password))
+                .timeout(Some(Duration::from_secs(10)))
+                .build(),
+            Err(e) => {
+                return Err(ChatuzaError::Internal(format!(
+                    "invalid smtp relay {} due to \n {}",
+                    host, e
+                )))
+            }
+        };
+        let from = match from.parse::<Mailbox>() {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(ChatuzaError::Internal(format!(
+                    "invalid smtp sender address {} due to \n {}",
+                    from, e
+                )))
+            }
+        };
+        Ok(SmtpSender { transport, from })
+    }
+}
+
+impl MessageSender for SmtpSender {
+    fn send(&self, message: &OutgoingMessage) -> Result<(), ChatuzaError> {
+        if message.channel != VerificationChannel::Email {
+            return Err(ChatuzaError::Internal(
+                "the smtp sender only delivers emails".to_owned(),
+            ));
+        }
+        let to = match message.recipient.parse::<Mailbox>() {
+            Ok(res) => res,
+            Err(_) => {
+                return Err(ChatuzaError::Validation(format!(
+                    "{} isn't a valid email address",
+                    message.recipient
+                )))
+            }
+        };
+        let email = match Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(message.subject.clone())
+            .body(message.body.clone())
+        {
+            Ok(res) => res,
+            Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+        };
+
+        match self.transport.send(&email) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ChatuzaError::Delivery(format!("{}", e))),
+        }
+    }
+}
+
+// -- SMS gateway -- //
+
+// posts `{ "from", "to", "text" }` as json to the gateway with a bearer token, which is what
+// most http sms providers take, the token is read from the SMS_GATEWAY_TOKEN env var
+pub struct SmsGatewaySender {
+    endpoint: String,
+    sender_id: String,
+    api_token: String,
+}
+
+impl SmsGatewaySender {
+    pub fn new(endpoint: &str, sender_id: &str) -> Self {
+        dotenv().ok();
+        SmsGatewaySender {
+            endpoint: endpoint.to_owned(),
+            sender_id: sender_id.to_owned(),
+            api_token: env::var("SMS_GATEWAY_TOKEN").unwrap_or_default(),
+        }
+    }
+}
+
+impl MessageSender for SmsGatewaySender {
+    fn send(&self, message: &OutgoingMessage) -> Result<(), ChatuzaError> {
+        if message.channel != VerificationChannel::Phone {
+            return Err(ChatuzaError::Internal(
+                "the sms gateway only delivers text messages".to_owned(),
+            ));
+        }
+
+        match ureq::post(&self.endpoint)
+            .timeout(Duration::from_secs(10))
+            .set("Authorization", &format!("Bearer {}", self.api_token))
+            .send_json(serde_json::json!({
+                "from": self.sender_id,
+                "to": message.recipient,
+                "text": message.body,
+            })) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, _)) => Err(ChatuzaError::Delivery(format!(
+                "the sms gateway answered with status {}",
+                code
+            ))),
+            Err(e) => Err(ChatuzaError::Delivery(format!("{}", e))),
+        }
+    }
+}
+
+// -- file / log -- //
+
+// appends every message to a file, or prints it when there's no file, for local runs
+pub struct LogSender {
+    path: Option<PathBuf>,
+    // keeps the lines of concurrent sends from interleaving
+    lock: Mutex<()>,
+}
+
+impl LogSender {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        LogSender {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl MessageSender for LogSender {
+    fn send(&self, message: &OutgoingMessage) -> Result<(), ChatuzaError> {
+        let line = format!(
+            "[{}] {} to {} | {} | {}\n",
+            Utc::now().naive_utc(),
+            message.channel.as_str(),
+            message.recipient,
+            message.subject,
+            message.body.replace('\n', " "),
+        );
+
+        let _guard = self.lock.lock().unwrap();
+        match &self.path {
+            Some(path) => {
+                match OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()))
+                {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(ChatuzaError::Delivery(format!(
+                        "couldn't write to {} due to \n {}",
+                        path.display(),
+                        e
+                    ))),
+                }
+            }
+            None => {
+                print!("{}", line);
+                Ok(())
+            }
+        }
+    }
+}
+
+// -- routing -- //
+
+// sends the emails and the text messages through their own backend
+pub struct MessageRouter {
+    email_sender: Box<dyn MessageSender>,
+    sms_sender: Box<dyn MessageSender>,
+}
+
+impl MessageRouter {
+    pub fn new(email_sender: Box<dyn MessageSender>, sms_sender: Box<dyn MessageSender>) -> Self {
+        MessageRouter {
+            email_sender,
+            sms_sender,
+        }
+    }
+}
+
+impl MessageSender for MessageRouter {
+    fn send(&self, message: &OutgoingMessage) -> Result<(), ChatuzaError> {
+        match message.channel {
+            VerificationChannel::Email => self.email_sender.send(message),
+            VerificationChannel::Phone => self.sms_sender.send(message),
+        }
+    }
+}
+
+// read from the `delivery_*`, `smtp_*` and `sms_*` extras of Rocket.toml, both backends
+// default to "log" so a local run needs no outside service
+#[derive(Debug, Clone)]
+pub struct DeliverySettings {
+    // "smtp" or "log"
+    pub email_backend: String,
+    // "gateway" or "log"
+    pub sms_backend: String,
+    // the log backend prints to stdout without it
+    pub log_path: Option<String>,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    pub smtp_from: String,
+    pub sms_gateway_url: String,
+    pub sms_sender_id: String,
+}
+
+impl DeliverySettings {
+    pub fn from_rocket_config(config: &Config) -> Self {
+        let string = |key: &str, default: &str| {
+            config
+                .get_str(key)
+                .map(|res| res.to_owned())
+                .unwrap_or(default.to_owned())
+        };
+
+        DeliverySettings {
+            email_backend: string("delivery_email_backend", "log"),
+            sms_backend: string("delivery_sms_backend", "log"),
+            log_path: config
+                .get_str("delivery_log_path")
+                .ok()
+                .map(|res| res.to_owned()),
+            smtp_host: string("smtp_host", "localhost"),
+            smtp_port: match config.get_int("smtp_port") {
+                Ok(res) if res > 0 && res <= u16::MAX as i64 => res as u16,
+                _ => 587,
+            },
+            smtp_username: string("smtp_username", ""),
+            smtp_from: string("smtp_from", "Chatuza <no-reply@localhost>"),
+            sms_gateway_url: string("sms_gateway_url", ""),
+            sms_sender_id: string("sms_sender_id", "Chatuza"),
+        }
+    }
+}
+
+pub fn init_message_sender(settings: &DeliverySettings) -> Result<MessageRouter, ChatuzaError> {
+    let log_sender = || {
+        Box::new(LogSender::new(
+            settings.log_path.as_ref().map(PathBuf::from),
+        ))
+    };
+
+    let email_sender: Box<dyn MessageSender> = match settings.email_backend.as_str() {
+        "smtp" => Box::new(SmtpSender::new(
+            &settings.smtp_host,
+            settings.smtp_port,
+            &settings.smtp_username,
+            &settings.smtp_from,
+        )?),
+        "log" => log_sender(),
+        other => {
+            return Err(ChatuzaError::Internal(format!(
+                "unknown email delivery backend {}",
+                other
+            )))
+        }
+    };
+    let sms_sender: Box<dyn MessageSender> = match settings.sms_backend.as_str() {
+        "gateway" => Box::new(SmsGatewaySender::new(
+            &settings.sms_gateway_url,
+            &settings.sms_sender_id,
+        )),
+        "log" => log_sender(),
+        other => {
+            return Err(ChatuzaError::Internal(format!(
+                "unknown sms delivery backend {}",
+                other
+            )))
+        }
+    };
+    Ok(MessageRouter::new(email_sender, sms_sender))
+}
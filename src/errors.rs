@@ -0,0 +1,160 @@
+use crate::api_models::ApiErrorEnvelope;
+use diesel::result::DatabaseErrorKind;
+use log::error;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket_contrib::json::Json;
+use solana_client::client_error::ClientError;
+use std::fmt;
+
+// what every api handler returns, errors are rendered through the `Responder` below
+pub type ApiResult<T> = Result<Json<T>, ChatuzaError>;
+
+#[derive(Debug)]
+pub enum ChatuzaError {
+    // (entity, key) e.g. ("user", "javad")
+    NotFound(&'static str, String),
+    AlreadyExists(String),
+    // the action the caller wasn't allowed to do
+    PermissionDenied(String),
+    Unauthorized(String),
+    Validation(String),
+    // a retry limit got hit, e.g. too many wrong verification codes
+    TooManyRequests(String),
+    // (reason, seconds until the next try is allowed) sent back in the Retry-After header
+    RateLimited(String, u64),
+    Database(diesel::result::Error),
+    // boxed, the rpc error would make every result of the crate a few hundred bytes
+    Chain(Box<ClientError>),
+    // the email or sms provider didn't take the message
+    Delivery(String),
+    Internal(String),
+}
+
+impl ChatuzaError {
+    // stable machine readable code, clients branch on this instead of the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            ChatuzaError::NotFound(..) => "not_found",
+            ChatuzaError::AlreadyExists(_) => "already_exists",
+            ChatuzaError::PermissionDenied(_) => "permission_denied",
+            ChatuzaError::Unauthorized(_) => "unauthorized",
+            ChatuzaError::Validation(_) => "validation",
+            ChatuzaError::TooManyRequests(_) | ChatuzaError::RateLimited(..) => "too_many_requests",
+            ChatuzaError::Database(_) => "database",
+            ChatuzaError::Chain(_) => "chain",
+            ChatuzaError::Delivery(_) => "delivery",
+            ChatuzaError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ChatuzaError::NotFound(..) => Status::NotFound,
+            ChatuzaError::AlreadyExists(_) => Status::Conflict,
+            ChatuzaError::PermissionDenied(_) => Status::Forbidden,
+            ChatuzaError::Unauthorized(_) => Status::Unauthorized,
+            ChatuzaError::Validation(_) => Status::UnprocessableEntity,
+            ChatuzaError::TooManyRequests(_) | ChatuzaError::RateLimited(..) => {
+                Status::TooManyRequests
+            }
+            ChatuzaError::Database(e) => match e {
+                diesel::result::Error::NotFound => Status::NotFound,
+                diesel::result::Error::DatabaseError(kind, _) => match kind {
+                    DatabaseErrorKind::UniqueViolation => Status::Conflict,
+                    DatabaseErrorKind::ForeignKeyViolation
+                    | DatabaseErrorKind::NotNullViolation
+                    | DatabaseErrorKind::CheckViolation => Status::BadRequest,
+                    _ => Status::InternalServerError,
+                },
+                _ => Status::InternalServerError,
+            },
+            ChatuzaError::Chain(_) | ChatuzaError::Delivery(_) => Status::BadGateway,
+            ChatuzaError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn envelope(&self) -> ApiErrorEnvelope {
+        let status = self.status();
+        // the client only gets a generic message for the server side failures
+        let message = if status.code >= 500 && !matches!(self, ChatuzaError::Chain(_)) {
+            status.reason.to_owned()
+        } else {
+            format!("{}", self)
+        };
+        let details = match self {
+            ChatuzaError::NotFound(entity, key) => {
+                Some(serde_json::json!({ "entity": entity, "key": key }))
+            }
+            ChatuzaError::RateLimited(_, retry_after_secs) => {
+                Some(serde_json::json!({ "retry_after_secs": retry_after_secs }))
+            }
+            _ => None,
+        };
+        ApiErrorEnvelope::new(self.code(), message, details)
+    }
+}
+
+impl<'r> Responder<'r> for ChatuzaError {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        // logged here, the envelope hides the details of the server side failures
+        if self.status().code >= 500 {
+            error!("{} {} failed due to \n {}", req.method(), req.uri(), self);
+        }
+        let mut response = Response::build_from(Json(self.envelope()).respond_to(req)?);
+        response.status(self.status());
+        if let ChatuzaError::RateLimited(_, retry_after_secs) = &self {
+            response.raw_header("Retry-After", retry_after_secs.to_string());
+        }
+        response.ok()
+    }
+}
+
+impl fmt::Display for ChatuzaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatuzaError::NotFound(entity, key) => write!(f, "{} {} not found", entity, key),
+            ChatuzaError::AlreadyExists(reason) => write!(f, "{}", reason),
+            ChatuzaError::PermissionDenied(action) => write!(f, "not allowed to {}", action),
+            ChatuzaError::Unauthorized(reason) => write!(f, "{}", reason),
+            ChatuzaError::Validation(reason) => write!(f, "{}", reason),
+            ChatuzaError::TooManyRequests(reason) => write!(f, "{}", reason),
+            ChatuzaError::RateLimited(reason, retry_after_secs) => {
+                write!(f, "{}, retry in {} seconds", reason, retry_after_secs)
+            }
+            ChatuzaError::Database(e) => write!(f, "database error: {}", e),
+            ChatuzaError::Chain(e) => write!(f, "solana rpc error: {}", e),
+            ChatuzaError::Delivery(reason) => write!(f, "message delivery failed: {}", reason),
+            ChatuzaError::Internal(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ChatuzaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChatuzaError::Database(e) => Some(e),
+            ChatuzaError::Chain(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for ChatuzaError {
+    fn from(e: diesel::result::Error) -> Self {
+        ChatuzaError::Database(e)
+    }
+}
+
+impl From<ClientError> for ChatuzaError {
+    fn from(e: ClientError) -> Self {
+        ChatuzaError::Chain(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for ChatuzaError {
+    fn from(e: serde_json::Error) -> Self {
+        ChatuzaError::Internal(format!("serialization error: {}", e))
+    }
+}
@@ -0,0 +1,466 @@
+use crate::api_models::GroupInviteResponse;
+use crate::auth_lib::{hash_token_id, new_token_id};
+use crate::db_models::{
+    ChatRoomParticipants, GroupAuditLog, GroupInvites, JoinRequestStatus, JoinRequests,
+    ParticipantRole, QGroupAuditLog, QGroupInvites, QJoinRequests,
+};
+use crate::errors::ChatuzaError;
+use crate::insert_group_participant;
+use crate::is_user_in_chat_room;
+use crate::permission_lib::{ensure_group_permission, GroupAction};
+use crate::schema::{group_audit_log, group_invites, join_requests};
+use chrono::{Duration, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+pub const MAX_INVITE_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+pub const MAX_AUDIT_LOG_PAGE_SIZE: i64 = 100;
+
+// the actions written to `group_audit_log`
+pub const AUDIT_INVITE_CREATED: &str = "invite_created";
+pub const AUDIT_INVITE_REVOKED: &str = "invite_revoked";
+pub const AUDIT_INVITE_REDEEMED: &str = "invite_redeemed";
+pub const AUDIT_JOIN_REQUESTED: &str = "join_requested";
+pub const AUDIT_JOIN_APPROVED: &str = "join_approved";
+pub const AUDIT_JOIN_REJECTED: &str = "join_rejected";
+
+pub enum RedeemOutcome {
+    Joined(ChatRoomParticipants),
+    // the invite needs an approval, the user waits in the join request queue
+    Pending(QJoinRequests),
+}
+
+pub fn record_group_audit(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    _actor_id: Option<i32>,
+    _action: &str,
+    _target_user_id: Option<i32>,
+    _invite_id: Option<i32>,
+) -> Result<(), ChatuzaError> {
+    match diesel::insert_into(group_audit_log::table)
+        .values(&GroupAuditLog {
+            chat_room_id: _chat_room_id,
+            actor_id: _actor_id,
+            action: _action.to_owned(),
+            target_user_id: _target_user_id,
+            invite_id: _invite_id,
+        })
+        .execute(_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- Invites SETTER functions -- //
+
+pub fn create_group_invite(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    creator_user_id: i32,
+    expires_in_secs: i64,
+    _max_uses: Option<i32>,
+    _requires_approval: bool,
+) -> Result<GroupInviteResponse, ChatuzaError> {
+    if expires_in_secs <= 0 || expires_in_secs > MAX_INVITE_TTL_SECS {
+        return Err(ChatuzaError::Validation(format!(
+            "invites expire within 1 and {} seconds",
+            MAX_INVITE_TTL_SECS
+        )));
+    }
+    if let Some(uses) = _max_uses {
+        if uses <= 0 {
+            return Err(ChatuzaError::Validation(
+                "max uses of an invite must be positive".to_owned(),
+            ));
+        }
+    }
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        if let Err(e) = ensure_group_permission(
+            _conn,
+            _chat_room_id,
+            creator_user_id,
+            GroupAction::AddMembers,
+        ) {
+            return Err(e);
+        }
+
+        let invite_token = new_token_id();
+        let invite;
+        match diesel::insert_into(group_invites::table)
+            .values(&GroupInvites {
+                chat_room_id: _chat_room_id,
+                created_by: Some(creator_user_id),
+                token_hash: hash_token_id(&invite_token),
+                expires_at: (Utc::now() + Duration::seconds(expires_in_secs)).naive_utc(),
+                max_uses: _max_uses,
+                requires_approval: _requires_approval,
+            })
+            .returning(QGroupInvites::as_returning())
+            .get_result(_conn)
+        {
+            Ok(res) => invite = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        record_group_audit(
+            _conn,
+            _chat_room_id,
+            Some(creator_user_id),
+            AUDIT_INVITE_CREATED,
+            None,
+            Some(invite.invite_id),
+        )?;
+        Ok(GroupInviteResponse {
+            invite,
+            invite_token,
+        })
+    })
+}
+
+pub fn revoke_group_invite(
+    _conn: &mut PgConnection,
+    _invite_id: i32,
+    revoker_user_id: i32,
+) -> Result<QGroupInvites, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let invite = get_group_invite_by_id(_conn, _invite_id)?;
+        if let Err(e) = ensure_group_permission(
+            _conn,
+            invite.chat_room_id,
+            revoker_user_id,
+            GroupAction::AddMembers,
+        ) {
+            return Err(e);
+        }
+        if invite.revoked_at.is_some() {
+            return Ok(invite);
+        }
+
+        let revoked_invite;
+        match diesel::update(group_invites::table.filter(group_invites::invite_id.eq(_invite_id)))
+            .set(group_invites::revoked_at.eq(Utc::now().naive_utc()))
+            .returning(QGroupInvites::as_returning())
+            .get_result(_conn)
+        {
+            Ok(res) => revoked_invite = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        record_group_audit(
+            _conn,
+            invite.chat_room_id,
+            Some(revoker_user_id),
+            AUDIT_INVITE_REVOKED,
+            None,
+            Some(_invite_id),
+        )?;
+        Ok(revoked_invite)
+    })
+}
+
+pub fn redeem_group_invite(
+    _conn: &mut PgConnection,
+    invite_token: &str,
+    _user_id: i32,
+) -> Result<RedeemOutcome, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let invite;
+        match group_invites::table
+            .filter(group_invites::token_hash.eq(hash_token_id(invite_token)))
+            .select(QGroupInvites::as_select())
+            .first(_conn)
+            .optional()
+        {
+            Ok(Some(res)) => invite = res,
+            Ok(None) => return Err(ChatuzaError::NotFound("invite", "token".to_owned())),
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        if invite.revoked_at.is_some() || invite.expires_at <= Utc::now().naive_utc() {
+            return Err(ChatuzaError::Validation(format!(
+                "invite id {} is no longer valid",
+                invite.invite_id
+            )));
+        }
+        if is_user_in_chat_room(_conn, invite.chat_room_id, _user_id) {
+            return Err(ChatuzaError::AlreadyExists(format!(
+                "user id {} is already in the group chat room id {}",
+                _user_id, invite.chat_room_id
+            )));
+        }
+
+        // the use is counted in the same statement that checks the limit so two redemptions
+        // racing for the last use can't both get it
+        match diesel::update(
+            group_invites::table.filter(
+                group_invites::invite_id.eq(invite.invite_id).and(
+                    group_invites::max_uses
+                        .is_null()
+                        .or(group_invites::use_count.lt(group_invites::max_uses.assume_not_null())),
+                ),
+            ),
+        )
+        .set(group_invites::use_count.eq(group_invites::use_count + 1))
+        .execute(_conn)
+        {
+            Ok(0) => {
+                return Err(ChatuzaError::Validation(format!(
+                    "invite id {} has no uses left",
+                    invite.invite_id
+                )))
+            }
+            Ok(_) => {}
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        if invite.requires_approval {
+            let join_request;
+            match diesel::insert_into(join_requests::table)
+                .values(&JoinRequests {
+                    chat_room_id: invite.chat_room_id,
+                    user_id: _user_id,
+                    invite_id: Some(invite.invite_id),
+                })
+                .returning(QJoinRequests::as_returning())
+                .get_result(_conn)
+            {
+                Ok(res) => join_request = res,
+                Err(diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => {
+                    return Err(ChatuzaError::AlreadyExists(format!(
+                        "user id {} already asked to join the chat room id {}",
+                        _user_id, invite.chat_room_id
+                    )))
+                }
+                Err(e) => return Err(ChatuzaError::Database(e)),
+            }
+            record_group_audit(
+                _conn,
+                invite.chat_room_id,
+                Some(_user_id),
+                AUDIT_JOIN_REQUESTED,
+                Some(_user_id),
+                Some(invite.invite_id),
+            )?;
+            return Ok(RedeemOutcome::Pending(join_request));
+        }
+
+        let participant = insert_group_participant(
+            _conn,
+            &ChatRoomParticipants {
+                chat_room_id: invite.chat_room_id,
+                user_id: _user_id,
+                role: ParticipantRole::Member,
+            },
+        )?;
+        record_group_audit(
+            _conn,
+            invite.chat_room_id,
+            Some(_user_id),
+            AUDIT_INVITE_REDEEMED,
+            Some(_user_id),
+            Some(invite.invite_id),
+        )?;
+        Ok(RedeemOutcome::Joined(participant))
+    })
+}
+
+// approving adds the user as a member, both outcomes close the request
+pub fn decide_join_request(
+    _conn: &mut PgConnection,
+    _join_request_id: i32,
+    decider_user_id: i32,
+    approve: bool,
+) -> Result<QJoinRequests, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let join_request;
+        match join_requests::table
+            .filter(join_requests::join_request_id.eq(_join_request_id))
+            .select(QJoinRequests::as_select())
+            .first(_conn)
+            .optional()
+        {
+            Ok(Some(res)) => join_request = res,
+            Ok(None) => {
+                return Err(ChatuzaError::NotFound(
+                    "join request id",
+                    _join_request_id.to_string(),
+                ))
+            }
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        if let Err(e) = ensure_group_permission(
+            _conn,
+            join_request.chat_room_id,
+            decider_user_id,
+            GroupAction::AddMembers,
+        ) {
+            return Err(e);
+        }
+        if join_request.status != JoinRequestStatus::Pending {
+            return Err(ChatuzaError::Validation(format!(
+                "join request id {} was already decided",
+                _join_request_id
+            )));
+        }
+
+        if approve {
+            insert_group_participant(
+                _conn,
+                &ChatRoomParticipants {
+                    chat_room_id: join_request.chat_room_id,
+                    user_id: join_request.user_id,
+                    role: ParticipantRole::Member,
+                },
+            )?;
+        }
+
+        let decided_request;
+        match diesel::update(
+            join_requests::table.filter(join_requests::join_request_id.eq(_join_request_id)),
+        )
+        .set((
+            join_requests::status.eq(if approve {
+                JoinRequestStatus::Approved
+            } else {
+                JoinRequestStatus::Rejected
+            }),
+            join_requests::decided_by.eq(decider_user_id),
+            join_requests::decided_at.eq(Utc::now().naive_utc()),
+        ))
+        .returning(QJoinRequests::as_returning())
+        .get_result(_conn)
+        {
+            Ok(res) => decided_request = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        record_group_audit(
+            _conn,
+            join_request.chat_room_id,
+            Some(decider_user_id),
+            if approve {
+                AUDIT_JOIN_APPROVED
+            } else {
+                AUDIT_JOIN_REJECTED
+            },
+            Some(join_request.user_id),
+            join_request.invite_id,
+        )?;
+        Ok(decided_request)
+    })
+}
+
+// -- Invites GETTER functions -- //
+
+pub fn get_group_invite_by_id(
+    _conn: &mut PgConnection,
+    _invite_id: i32,
+) -> Result<QGroupInvites, ChatuzaError> {
+    match group_invites::table
+        .filter(group_invites::invite_id.eq(_invite_id))
+        .select(QGroupInvites::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(ChatuzaError::NotFound("invite id", _invite_id.to_string())),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the invites that haven't been revoked, newest first
+pub fn get_group_invites(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    reader_user_id: i32,
+) -> Result<Vec<QGroupInvites>, ChatuzaError> {
+    if let Err(e) = ensure_group_permission(
+        _conn,
+        _chat_room_id,
+        reader_user_id,
+        GroupAction::AddMembers,
+    ) {
+        return Err(e);
+    }
+
+    match group_invites::table
+        .filter(
+            group_invites::chat_room_id
+                .eq(_chat_room_id)
+                .and(group_invites::revoked_at.is_null()),
+        )
+        .order(group_invites::invite_id.desc())
+        .select(QGroupInvites::as_select())
+        .load(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn get_pending_join_requests(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    reader_user_id: i32,
+) -> Result<Vec<QJoinRequests>, ChatuzaError> {
+    if let Err(e) = ensure_group_permission(
+        _conn,
+        _chat_room_id,
+        reader_user_id,
+        GroupAction::AddMembers,
+    ) {
+        return Err(e);
+    }
+
+    match join_requests::table
+        .filter(
+            join_requests::chat_room_id
+                .eq(_chat_room_id)
+                .and(join_requests::status.eq(JoinRequestStatus::Pending)),
+        )
+        .order(join_requests::join_request_id.asc())
+        .select(QJoinRequests::as_select())
+        .load(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the audit trail is kept for the admins, newest first
+pub fn get_group_audit_log(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    reader_user_id: i32,
+    before_audit_id: Option<i32>,
+    _limit: i64,
+) -> Result<Vec<QGroupAuditLog>, ChatuzaError> {
+    if let Err(e) =
+        ensure_group_permission(_conn, _chat_room_id, reader_user_id, GroupAction::EditInfo)
+    {
+        return Err(e);
+    }
+
+    let mut query = group_audit_log::table
+        .filter(group_audit_log::chat_room_id.eq(_chat_room_id))
+        .order(group_audit_log::audit_id.desc())
+        .limit(_limit.clamp(1, MAX_AUDIT_LOG_PAGE_SIZE))
+        .select(QGroupAuditLog::as_select())
+        .into_boxed();
+    if let Some(before_id) = before_audit_id {
+        query = query.filter(group_audit_log::audit_id.lt(before_id));
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
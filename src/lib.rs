@@ -1,13 +1,43 @@
 #![recursion_limit = "256"]
+pub mod api_guards;
 pub mod api_models;
+pub mod auth_lib;
+pub mod chain_client;
+pub mod contact_lib;
 pub mod db_models;
+pub mod db_pool;
+pub mod delivery;
+pub mod errors;
+pub mod invite_lib;
+pub mod message_lib;
+pub mod mfa_lib;
+pub mod permission_lib;
+pub mod privacy_lib;
+pub mod rate_limit;
+pub mod realtime;
+pub mod recovery_lib;
+pub mod room_transfer_lib;
 pub mod schema;
+pub mod search_lib;
+pub mod verification_lib;
+pub mod wallet_config;
 pub mod wallet_lib;
 
+use crate::api_models::{
+    ChatRoomListFilter, Page, PageDirection, PageRequest, ParticipantListFilter,
+};
+use crate::auth_lib::hash_password;
+use crate::contact_lib::{get_visible_user_with_username, is_blocked_between};
 use crate::db_models::{ChatRoomParticipants, ChatRooms, QUsers, UserProfiles, Users};
+use crate::errors::ChatuzaError;
+use crate::permission_lib::{ensure_group_permission, get_participant_role, GroupAction};
+use crate::privacy_lib::{get_contact_user_ids, get_user_privacy_settings, is_field_visible};
 use crate::schema::{chat_room_participants, chat_rooms, solana_wallets, user_profiles, users};
-use chrono::Local;
-use db_models::{QChatRooms, QSolanaWallet, QUsersResponse, UpdatableChatRooms};
+use crate::verification_lib::normalize_phone_number;
+use db_models::{
+    OwnerLeavePolicy, ParticipantRole, QChatRoomParticipants, QChatRooms, QSolanaWallet,
+    QUsersResponse, RoomKind, UpdatableChatRooms,
+};
 pub use diesel;
 pub use diesel::pg::PgConnection;
 pub use diesel::prelude::*;
@@ -20,7 +50,9 @@ use schema::{
 use wallet_lib::delete_solana_wallet;
 
 pub use std::env;
-use std::hash::{DefaultHasher, Hash, Hasher};
+
+// `add_new_p2p_chat_room` names the direct rooms `direct-<lower user id>-<higher user id>`
+pub const DIRECT_ROOM_NAME_PREFIX: &str = "direct-";
 
 pub fn establish_connection() -> PgConnection {
     // loading the env vars into the current scope
@@ -36,203 +68,172 @@ pub fn add_new_user(
     conn: &mut PgConnection,
     user_credits: &Users,
     user_profile: &mut UserProfiles,
-) -> Result<QUsersResponse, Box<dyn std::error::Error>> {
-    // inserting user credits
-    // db avoids the duplicated values
-    if let Err(e) = diesel::insert_into(users::table)
-        .values(user_credits)
-        .returning(Users::as_returning())
-        .get_result(conn)
-    {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("{:?}", e),
-        )));
-    }
+) -> Result<QUsersResponse, ChatuzaError> {
+    conn.transaction::<_, ChatuzaError, _>(|conn| {
+        // inserting user credits, the password never touches the db as plaintext
+        // db avoids the duplicated values
+        // phone numbers are stored as e.164 so the unique constraint catches every spelling
+        let hashed_user_credits = Users {
+            username: user_credits.username.clone(),
+            email: user_credits.email.clone(),
+            password: hash_password(&user_credits.password)?,
+            phone_number: normalize_phone_number(&user_credits.phone_number)?,
+        };
+        if let Err(e) = diesel::insert_into(users::table)
+            .values(&hashed_user_credits)
+            .returning(Users::as_returning())
+            .get_result(conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
 
-    // fetching the user info
-    let user_info: QUsers;
-    match get_user_with_username(conn, &user_credits.username) {
-        Ok(res) => user_info = res,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        // fetching the user info
+        let user_info: QUsers;
+        match get_user_with_username(conn, &user_credits.username) {
+            Ok(res) => user_info = res,
+            Err(e) => return Err(e),
         }
-    }
 
-    user_profile.user_id = user_info.user_id;
-    let u_p: &UserProfiles = user_profile;
+        user_profile.user_id = user_info.user_id;
+        let u_p: &UserProfiles = user_profile;
 
-    // inserting user profiles
-    match diesel::insert_into(user_profiles::table)
-        .values(u_p)
-        .returning(UserProfiles::as_returning())
-        .get_result(conn)
-    {
-        Ok(_) => Ok(QUsersResponse {
-            user_id: user_info.user_id,
-            username: user_info.username,
-            email: user_info.email,
-            password: user_info.password,
-            phone_number: user_info.phone_number,
-            bio: user_profile.bio.clone().unwrap_or("".to_string()),
-            profile_picture: user_profile
-                .profile_picture
-                .clone()
-                .unwrap_or("".to_string()),
-        }),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+        // inserting user profiles
+        match diesel::insert_into(user_profiles::table)
+            .values(u_p)
+            .returning(UserProfiles::as_returning())
+            .get_result(conn)
+        {
+            Ok(_) => Ok(QUsersResponse {
+                user_id: user_info.user_id,
+                username: user_info.username,
+                email: user_info.email,
+                phone_number: user_info.phone_number,
+                bio: user_profile.bio.clone().unwrap_or("".to_string()),
+                profile_picture: user_profile
+                    .profile_picture
+                    .clone()
+                    .unwrap_or("".to_string()),
+            }),
+            Err(e) => return Err(ChatuzaError::Database(e)),
         }
-    }
+    })
 }
 
 pub fn update_user_credits(
     conn: &mut PgConnection,
     old_username: &String,
     new_user_credits: &Users,
-) -> Result<QUsers, Box<dyn std::error::Error>> {
-    let user_info: QUsers;
-    match get_user_with_username(conn, old_username.as_str()) {
-        Ok(res) => user_info = res,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+) -> Result<QUsers, ChatuzaError> {
+    conn.transaction::<_, ChatuzaError, _>(|conn| {
+        let user_info: QUsers;
+        match get_user_with_username(conn, old_username.as_str()) {
+            Ok(res) => user_info = res,
+            Err(e) => return Err(e),
         }
-    }
 
-    match diesel::update(users.filter(users::user_id.eq(user_info.user_id)))
-        .set((
-            username.eq(&new_user_credits.username),
-            email.eq(&new_user_credits.email),
-            password.eq(&new_user_credits.password),
-        ))
-        .returning(Users::as_returning())
-        .get_result(conn)
-    {
-        Ok(_) => Ok(get_user_with_username(conn, new_user_credits.username.as_str()).unwrap()),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+        // a new email has to be verified again
+        let new_email_verified_at = if user_info.email == new_user_credits.email {
+            user_info.email_verified_at
+        } else {
+            None
+        };
+        match diesel::update(users.filter(users::user_id.eq(user_info.user_id)))
+            .set((
+                username.eq(&new_user_credits.username),
+                email.eq(&new_user_credits.email),
+                password.eq(hash_password(&new_user_credits.password)?),
+                email_verified_at.eq(new_email_verified_at),
+            ))
+            .returning(Users::as_returning())
+            .get_result(conn)
+        {
+            Ok(_) => get_user_with_username(conn, new_user_credits.username.as_str()),
+            Err(e) => return Err(ChatuzaError::Database(e)),
         }
-    }
+    })
 }
 
 pub fn update_user_profile(
     conn: &mut PgConnection,
     old_username: &String,
     user_profile: &mut UserProfiles,
-) -> Result<UserProfiles, Box<dyn std::error::Error>> {
-    //fetching the user id and set it accordingly
-    user_profile.user_id = get_user_with_username(conn, &old_username).unwrap().user_id;
-
-    match diesel::update(user_profiles.filter(user_profiles::user_id.eq(user_profile.user_id)))
-        .set((
-            bio.eq(&user_profile.bio),
-            profile_picture.eq(&user_profile.profile_picture),
-        ))
-        .returning(UserProfiles::as_returning())
-        .get_result(conn)
-    {
-        Ok(_) => Ok(get_user_profile_with_user_id(conn, user_profile.user_id).unwrap()),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+) -> Result<UserProfiles, ChatuzaError> {
+    conn.transaction::<_, ChatuzaError, _>(|conn| {
+        //fetching the user id and set it accordingly
+        match get_user_with_username(conn, &old_username) {
+            Ok(res) => user_profile.user_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
+
+        match diesel::update(user_profiles.filter(user_profiles::user_id.eq(user_profile.user_id)))
+            .set((
+                bio.eq(&user_profile.bio),
+                profile_picture.eq(&user_profile.profile_picture),
+            ))
+            .returning(UserProfiles::as_returning())
+            .get_result(conn)
+        {
+            Ok(_) => get_user_profile_with_user_id(conn, user_profile.user_id),
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+    })
 }
 
-pub fn delete_user(
-    conn: &mut PgConnection,
-    _username: &String,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let _user_id: i32;
-    match get_user_with_username(conn, _username.as_str()) {
-        Ok(res) => _user_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+pub fn delete_user(conn: &mut PgConnection, _username: &String) -> Result<bool, ChatuzaError> {
+    // a failure on any step rolls the whole removal back instead of leaving ownerless rooms behind
+    conn.transaction::<_, ChatuzaError, _>(|conn| {
+        let _user_id: i32;
+        match get_user_with_username(conn, _username.as_str()) {
+            Ok(res) => _user_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
-    // deleting the users p2p chatroom's
-    if let Ok(_chat_rooms) = get_user_p2p_chat_rooms_by_user_id(conn, _user_id) {
-        for chat_room in _chat_rooms {
-            let p2p_chat_room_participants =
-                get_chat_room_participants_by_id(conn, chat_room.chat_room_id).unwrap();
-            let remover_username =
-                get_user_with_user_id(conn, p2p_chat_room_participants[0].user_id)
-                    .unwrap()
-                    .username;
-            let contact_username =
-                get_user_with_user_id(conn, p2p_chat_room_participants[1].user_id)
-                    .unwrap()
-                    .username;
-            let _ = delete_p2p_chat_room(conn, &remover_username, &contact_username).unwrap();
+        // deleting the users p2p chatroom's
+        if let Ok(_chat_rooms) = get_user_p2p_chat_rooms_by_user_id(conn, _user_id) {
+            for chat_room in _chat_rooms {
+                remove_chat_room(conn, chat_room.chat_room_id)?;
+            }
         }
-    }
-    if let Ok(gps) = get_user_group_chat_rooms_by_user_id(conn, _user_id) {
-        for gp in gps {
-            let group_owner = get_group_owner_by_id(conn, gp.chat_room_id).unwrap();
-            if group_owner == _user_id {
-                // deleting users owned group chats
-                let _ = delete_group_chat_room(conn, &gp.room_name, &_username).unwrap();
-            } else {
-                // deleting the user from group chats
-                let _ = del_participant_from_group_chat_room(
-                    conn,
-                    &ChatRoomParticipants {
-                        user_id: _user_id,
-                        chat_room_id: gp.chat_room_id,
-                        is_admin: false,
-                    },
-                    group_owner,
-                )
-                .unwrap();
+        if let Ok(gps) = get_user_group_chat_rooms_by_user_id(conn, _user_id) {
+            for gp in gps {
+                let group_owner = get_group_owner_by_id(conn, gp.chat_room_id)?;
+                if group_owner == _user_id {
+                    // the owned groups follow their owner leave policy instead of being dropped
+                    owner_leave_group_chat_room(conn, gp.chat_room_id, _user_id)?;
+                } else {
+                    // deleting the user from group chats
+                    del_participant_from_group_chat_room(
+                        conn,
+                        &ChatRoomParticipants {
+                            user_id: _user_id,
+                            chat_room_id: gp.chat_room_id,
+                            role: ParticipantRole::Member,
+                        },
+                        _user_id,
+                    )?;
+                }
             }
         }
-    }
-
-    if let Err(e) =
-        diesel::delete(user_profiles.filter(user_profiles::user_id.eq(_user_id))).execute(conn)
-    {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("{:?}", e),
-        )));
-    }
-    // checking if the user has any wallets created and deleting them
 
-    let _solana_wallets = solana_wallets
-        .filter(solana_wallets::user_id.eq(_user_id))
-        .select(QSolanaWallet::as_select())
-        .load(conn)
-        .unwrap_or(vec![]);
+        if let Err(e) =
+            diesel::delete(user_profiles.filter(user_profiles::user_id.eq(_user_id))).execute(conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        // checking if the user has any wallets created and deleting them
+        let _solana_wallets = solana_wallets
+            .filter(solana_wallets::user_id.eq(_user_id))
+            .select(QSolanaWallet::as_select())
+            .load(conn)
+            .unwrap_or(vec![]);
 
-    if _solana_wallets.len() as u32 == 1 {
-        delete_solana_wallet(conn, _username).unwrap();
-    }
-    match diesel::delete(users.filter(users::user_id.eq(_user_id))).execute(conn) {
-        Ok(_) => Ok(true),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+        if _solana_wallets.len() as u32 == 1 {
+            delete_solana_wallet(conn, _username)?;
         }
-    }
+        match diesel::delete(users.filter(users::user_id.eq(_user_id))).execute(conn) {
+            Ok(_) => Ok(true),
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+    })
 }
 
 // -- Users / UserProfiles GETTER functions -- //
@@ -241,7 +242,7 @@ pub fn delete_user(
 pub fn get_user_with_username(
     _conn: &mut PgConnection,
     _username: &str,
-) -> Result<QUsers, Box<dyn std::error::Error>> {
+) -> Result<QUsers, ChatuzaError> {
     let user_row: Vec<QUsers> = users
         .filter(username.eq(&_username))
         .select(QUsers::as_select())
@@ -254,19 +255,15 @@ pub fn get_user_with_username(
             email: user_row[0].email.clone(),
             password: user_row[0].password.clone(),
             phone_number: user_row[0].phone_number.clone(),
+            email_verified_at: user_row[0].email_verified_at,
+            phone_verified_at: user_row[0].phone_verified_at,
         })
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "username not found !",
-        )))
+        Err(ChatuzaError::NotFound("username", _username.to_string()))
     }
 }
 // EH
-pub fn get_user_with_email(
-    _conn: &mut PgConnection,
-    _email: &str,
-) -> Result<QUsers, Box<dyn std::error::Error>> {
+pub fn get_user_with_email(_conn: &mut PgConnection, _email: &str) -> Result<QUsers, ChatuzaError> {
     let user_row: Vec<QUsers> = users
         .filter(email.eq(&_email))
         .select(QUsers::as_select())
@@ -279,20 +276,19 @@ pub fn get_user_with_email(
             email: user_row[0].email.clone(),
             password: user_row[0].password.clone(),
             phone_number: user_row[0].phone_number.clone(),
+            email_verified_at: user_row[0].email_verified_at,
+            phone_verified_at: user_row[0].phone_verified_at,
         })
     } else {
         // some thing is wrong
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "email not found !",
-        )))
+        Err(ChatuzaError::NotFound("email", _email.to_string()))
     }
 }
 // EH
 pub fn get_user_with_user_id(
     _conn: &mut PgConnection,
     _user_id: i32,
-) -> Result<QUsers, Box<dyn std::error::Error>> {
+) -> Result<QUsers, ChatuzaError> {
     let user_row: Vec<QUsers> = users
         .filter(users::user_id.eq(&_user_id))
         .select(QUsers::as_select())
@@ -305,20 +301,19 @@ pub fn get_user_with_user_id(
             email: user_row[0].email.clone(),
             password: user_row[0].password.clone(),
             phone_number: user_row[0].phone_number.clone(),
+            email_verified_at: user_row[0].email_verified_at,
+            phone_verified_at: user_row[0].phone_verified_at,
         })
     } else {
         // some thing is wrong
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "user id not found !",
-        )))
+        Err(ChatuzaError::NotFound("user id", _user_id.to_string()))
     }
 }
 // EH
 pub fn get_user_profile_with_user_id(
     _conn: &mut PgConnection,
     _user_id: i32,
-) -> Result<UserProfiles, Box<dyn std::error::Error>> {
+) -> Result<UserProfiles, ChatuzaError> {
     let user_row: Vec<UserProfiles> = user_profiles
         .filter(user_profiles::user_id.eq(&_user_id))
         .select(UserProfiles::as_select())
@@ -332,26 +327,20 @@ pub fn get_user_profile_with_user_id(
             profile_picture: user_row[0].profile_picture.clone(),
         })
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "user id not found !",
-        )))
+        Err(ChatuzaError::NotFound("user id", _user_id.to_string()))
     }
 }
 // EH
+// the bio and the picture hidden from the viewer by the privacy settings come back as None
 pub fn get_user_profile_with_username(
     _conn: &mut PgConnection,
     _username: &String,
-) -> Result<UserProfiles, Box<dyn std::error::Error>> {
+    viewer_user_id: Option<i32>,
+) -> Result<UserProfiles, ChatuzaError> {
     let _user_id;
-    match get_user_with_username(_conn, _username.as_str()) {
+    match get_visible_user_with_username(_conn, _username.as_str(), viewer_user_id) {
         Ok(res) => _user_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
-        }
+        Err(e) => return Err(e),
     }
     let user_row: Vec<UserProfiles> = user_profiles
         .filter(user_profiles::user_id.eq(&_user_id))
@@ -360,17 +349,29 @@ pub fn get_user_profile_with_username(
         .unwrap_or(vec![]);
 
     if user_row.len() == 1 {
+        let settings = get_user_privacy_settings(_conn, _user_id)?;
+        let is_contact = match viewer_user_id {
+            Some(viewer_id) => get_contact_user_ids(_conn, _user_id).contains(&viewer_id),
+            None => false,
+        };
+        let visible =
+            |visibility| is_field_visible(visibility, _user_id, viewer_user_id, is_contact);
         Ok(UserProfiles {
             user_id: _user_id,
-            bio: user_row[0].bio.clone(),
-            profile_picture: user_row[0].profile_picture.clone(),
+            bio: if visible(settings.bio_visibility) {
+                user_row[0].bio.clone()
+            } else {
+                None
+            },
+            profile_picture: if visible(settings.avatar_visibility) {
+                user_row[0].profile_picture.clone()
+            } else {
+                None
+            },
         })
     } else {
         // some thing is wrong
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "username not found !",
-        )))
+        Err(ChatuzaError::NotFound("username", _username.to_string()))
     }
 }
 
@@ -381,157 +382,113 @@ pub fn add_new_p2p_chat_room(
     requestor_user: i32,
     acceptor_user: i32,
     _chat_room_pubkey: String,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
-    // checking if the two users are having an existing chat room, there may be some group chats, we check that too.
-
-    if let Err(_) = get_two_users_p2p_chat_room(_conn, requestor_user, acceptor_user) {
-        // updating the chat rooms db
-        // hashing the chatroom name because it doesn't have a name and it must be using the now time and the literal of the private room
-        let mut hasher = DefaultHasher::new();
-        Local::now()
-            .offset()
-            .to_string()
-            .push_str("Private Room")
-            .hash(&mut hasher);
-        let chat_room_name_hashed = hasher.finish().to_string();
-        let values_of_chat_rooms = ChatRooms {
-            room_name: chat_room_name_hashed,
-            room_description: "private room".to_owned(),
-            chat_room_pubkey: _chat_room_pubkey.as_bytes().to_vec(),
-        };
-
-        let new_chat_room;
-        match diesel::insert_into(chat_rooms)
-            .values(&values_of_chat_rooms)
-            .returning(QChatRooms::as_returning())
-            .get_result(_conn)
-        {
-            Ok(res) => new_chat_room = res,
-            Err(e) => {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("{:?}", e),
-                )))
-            }
+) -> Result<QChatRooms, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        if requestor_user == acceptor_user {
+            return Err(ChatuzaError::Validation(
+                "a p2p chat room needs two different users".to_owned(),
+            ));
         }
-        // adding requester to the participants table
-        if let Err(e) = diesel::insert_into(chat_room_participants)
-            .values(ChatRoomParticipants {
-                chat_room_id: new_chat_room.chat_room_id,
-                user_id: requestor_user,
-                is_admin: false,
-            })
-            .returning(ChatRoomParticipants::as_returning())
-            .get_result(_conn)
-        {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
+        if is_blocked_between(_conn, requestor_user, acceptor_user) {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "open a p2p chat with user id {}",
+                acceptor_user
             )));
         }
-        // adding requester to the acceptor table
-        match diesel::insert_into(chat_room_participants)
-            .values(ChatRoomParticipants {
-                chat_room_id: new_chat_room.chat_room_id,
-                user_id: acceptor_user,
-                is_admin: false,
-            })
-            .returning(ChatRoomParticipants::as_returning())
-            .get_result(_conn)
-        {
-            Ok(_) => Ok(new_chat_room),
-            Err(e) => {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    format!("{:?}", e),
-                )))
+        // checking if the two users are having an existing chat room
+        if let Err(_) = get_two_users_p2p_chat_room(_conn, requestor_user, acceptor_user) {
+            // the sorted user pair names the room, the unique index on it catches concurrent requests
+            let (user_low, user_high) = direct_user_pair(requestor_user, acceptor_user);
+            let values_of_chat_rooms = ChatRooms {
+                room_name: format!("{}{}-{}", DIRECT_ROOM_NAME_PREFIX, user_low, user_high),
+                room_description: "".to_owned(),
+                chat_room_pubkey: _chat_room_pubkey.as_bytes().to_vec(),
+                room_kind: RoomKind::Direct,
+                direct_user_low: Some(user_low),
+                direct_user_high: Some(user_high),
+                owner_leave_policy: OwnerLeavePolicy::Transfer,
+            };
+
+            let new_chat_room;
+            match diesel::insert_into(chat_rooms)
+                .values(&values_of_chat_rooms)
+                .returning(QChatRooms::as_returning())
+                .get_result(_conn)
+            {
+                Ok(res) => new_chat_room = res,
+                Err(diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    _,
+                )) => {
+                    return Err(ChatuzaError::AlreadyExists(format!(
+                        "user id {} already has a p2p chat with user id {}",
+                        requestor_user, acceptor_user
+                    )))
+                }
+                Err(e) => return Err(ChatuzaError::Database(e)),
             }
-        }
-    } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            format!(
-                " user id {} already has a p2p chat with user id {}",
+            // adding requester to the participants table
+            if let Err(e) = diesel::insert_into(chat_room_participants)
+                .values(ChatRoomParticipants {
+                    chat_room_id: new_chat_room.chat_room_id,
+                    user_id: requestor_user,
+                    role: ParticipantRole::Member,
+                })
+                .returning(ChatRoomParticipants::as_returning())
+                .get_result(_conn)
+            {
+                return Err(ChatuzaError::Database(e));
+            }
+            // adding requester to the acceptor table
+            match diesel::insert_into(chat_room_participants)
+                .values(ChatRoomParticipants {
+                    chat_room_id: new_chat_room.chat_room_id,
+                    user_id: acceptor_user,
+                    role: ParticipantRole::Member,
+                })
+                .returning(ChatRoomParticipants::as_returning())
+                .get_result(_conn)
+            {
+                Ok(_) => Ok(new_chat_room),
+                Err(e) => return Err(ChatuzaError::Database(e)),
+            }
+        } else {
+            Err(ChatuzaError::AlreadyExists(format!(
+                "user id {} already has a p2p chat with user id {}",
                 requestor_user, acceptor_user
-            ),
-        )))
-    }
+            )))
+        }
+    })
 }
 
 pub fn delete_p2p_chat_room(
     _conn: &mut PgConnection,
     remover_username: &String,
     contact_username: &String,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let _remover_id;
-    match get_user_with_username(_conn, remover_username) {
-        Ok(res) => _remover_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+) -> Result<bool, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let _remover_id;
+        match get_user_with_username(_conn, remover_username) {
+            Ok(res) => _remover_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
-    let _contact_id;
-    match get_user_with_username(_conn, contact_username) {
-        Ok(res) => _contact_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        let _contact_id;
+        match get_user_with_username(_conn, contact_username) {
+            Ok(res) => _contact_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    let _chat_room_id;
-    match get_two_users_p2p_chat_room(_conn, _remover_id, _contact_id) {
-        Ok(res) => _chat_room_id = res.chat_room_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        let _chat_room_id;
+        match get_two_users_p2p_chat_room(_conn, _remover_id, _contact_id) {
+            Ok(res) => _chat_room_id = res.chat_room_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    // checking the authority of the remover
-    let participants;
-    match get_chat_room_participants_by_id(_conn, _chat_room_id) {
-        Ok(res) => participants = res,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        match remove_chat_room(_conn, _chat_room_id) {
+            Ok(_) => Ok(true),
+            Err(e) => return Err(e),
         }
-    }
-    // deleting the users from the participants table
-    for participant in participants.iter() {
-        if let Err(e) = diesel::delete(
-            chat_room_participants.filter(chat_room_participants::user_id.eq(participant.user_id)),
-        )
-        .execute(_conn)
-        {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )));
-        }
-    }
-
-    // deleting the chat room from the chat rooms table
-    match diesel::delete(chat_rooms.filter(chat_rooms::chat_room_id.eq(_chat_room_id)))
-        .execute(_conn)
-    {
-        Ok(_) => Ok(true),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
-        }
-    }
+    })
 }
 
 pub fn add_new_group_chat_room(
@@ -539,220 +496,175 @@ pub fn add_new_group_chat_room(
     _chat_room_info: &ChatRooms,
     group_owner_username: &String,
     group_members: Vec<String>,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
-    let group_owner_id: i32;
-    match get_user_with_username(_conn, group_owner_username) {
-        Ok(res) => group_owner_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+) -> Result<QChatRooms, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        // direct rooms only come out of `add_new_p2p_chat_room`
+        if _chat_room_info.room_kind == RoomKind::Direct {
+            return Err(ChatuzaError::Validation(
+                "a group chat room can't be of the direct kind".to_owned(),
+            ));
         }
-    }
-
-    // creating the chat room
-    let new_chat_room;
-    match diesel::insert_into(chat_rooms)
-        .values(_chat_room_info)
-        .returning(QChatRooms::as_returning())
-        .get_result(_conn)
-    {
-        Ok(res) => new_chat_room = res,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+        ensure_group_room_name(&_chat_room_info.room_name)?;
+        let group_owner_id: i32;
+        match get_user_with_username(_conn, group_owner_username) {
+            Ok(res) => group_owner_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
-    // adding the owner to the participants
-    let owner = ChatRoomParticipants {
-        user_id: group_owner_id,
-        chat_room_id: new_chat_room.chat_room_id,
-        is_admin: true,
-    };
-    if let Err(e) = diesel::insert_into(chat_room_participants)
-        .values(&owner)
-        .returning(ChatRoomParticipants::as_returning())
-        .get_result(_conn)
-    {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("{:?}", e),
-        )));
-    }
-    // adding members if any specified
-    if group_members.len() > 0 {
-        let mut group_members_up: Vec<ChatRoomParticipants> = Vec::new();
-        for member in group_members {
-            let _member_id: i32;
-            match get_user_with_username(_conn, member.as_str()) {
-                Ok(res) => _member_id = res.user_id,
-                Err(e) => {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        format!("{:?}", e),
-                    )))
-                }
-            }
-            if _member_id != group_owner_id {
-                group_members_up.push(ChatRoomParticipants {
-                    user_id: _member_id,
-                    chat_room_id: new_chat_room.chat_room_id,
-                    is_admin: false,
-                });
-            }
+
+        // creating the chat room
+        let new_chat_room;
+        match diesel::insert_into(chat_rooms)
+            .values(_chat_room_info)
+            .returning(QChatRooms::as_returning())
+            .get_result(_conn)
+        {
+            Ok(res) => new_chat_room = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
         }
-        if let Err(e) = diesel::insert_into(chat_room_participants::table)
-            .values(&group_members_up)
+        // adding the owner to the participants
+        let owner = ChatRoomParticipants {
+            user_id: group_owner_id,
+            chat_room_id: new_chat_room.chat_room_id,
+            role: ParticipantRole::Owner,
+        };
+        if let Err(e) = diesel::insert_into(chat_room_participants)
+            .values(&owner)
             .returning(ChatRoomParticipants::as_returning())
             .get_result(_conn)
         {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )));
+            return Err(ChatuzaError::Database(e));
+        }
+        // adding members if any specified
+        if group_members.len() > 0 {
+            let mut group_members_up: Vec<ChatRoomParticipants> = Vec::new();
+            for member in group_members {
+                let _member_id: i32;
+                match get_user_with_username(_conn, member.as_str()) {
+                    Ok(res) => _member_id = res.user_id,
+                    Err(e) => return Err(e),
+                }
+                if _member_id != group_owner_id {
+                    group_members_up.push(ChatRoomParticipants {
+                        user_id: _member_id,
+                        chat_room_id: new_chat_room.chat_room_id,
+                        role: ParticipantRole::Member,
+                    });
+                }
+            }
+            if let Err(e) = diesel::insert_into(chat_room_participants::table)
+                .values(&group_members_up)
+                .execute(_conn)
+            {
+                return Err(ChatuzaError::Database(e));
+            }
         }
+        Ok(new_chat_room)
+    })
+}
+
+// the direct rooms are named after their user pair, a group taking such a name would keep the pair
+// from ever opening their p2p chat
+fn ensure_group_room_name(_room_name: &str) -> Result<(), ChatuzaError> {
+    if _room_name.starts_with(DIRECT_ROOM_NAME_PREFIX) {
+        return Err(ChatuzaError::Validation(format!(
+            "group chat room names can't start with {}",
+            DIRECT_ROOM_NAME_PREFIX
+        )));
     }
-    Ok(new_chat_room)
+    Ok(())
 }
+
 pub fn update_group_chat_room_info(
     _conn: &mut PgConnection,
     old_chat_room_name: &String,
     new_chat_room_info: &UpdatableChatRooms,
     editor_username: &String,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
-    let _chat_room_id: i32;
-    match get_group_chat_by_name(_conn, old_chat_room_name) {
-        Ok(res) => _chat_room_id = res.chat_room_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+) -> Result<QChatRooms, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let _chat_room_id: i32;
+        match get_group_chat_by_name(_conn, old_chat_room_name) {
+            Ok(res) => _chat_room_id = res.chat_room_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    let editor_user_id: i32;
-    match get_user_with_username(_conn, &editor_username) {
-        Ok(res) => editor_user_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        let editor_user_id: i32;
+        match get_user_with_username(_conn, &editor_username) {
+            Ok(res) => editor_user_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    match get_group_owner_by_id(_conn, _chat_room_id) {
-        Ok(res) => {
-            if editor_user_id != res {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::PermissionDenied,
-                    format!(
-                        "user id {} is not allowed to edit the group info",
-                        editor_user_id
-                    ),
-                )));
-            }
-        }
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        if let Err(e) =
+            ensure_group_permission(_conn, _chat_room_id, editor_user_id, GroupAction::EditInfo)
+        {
+            return Err(e);
         }
-    }
-
-    // updating the chat room info
-    match diesel::update(chat_rooms.filter(chat_rooms::chat_room_id.eq(_chat_room_id)))
-        .set((
-            room_name.eq(&new_chat_room_info.room_name),
-            room_description.eq(&new_chat_room_info.room_description),
-        ))
-        .returning(QChatRooms::as_returning())
-        .get_result(_conn)
-    {
-        Ok(_) => Ok(get_group_chat_by_name(_conn, &new_chat_room_info.room_name).unwrap()),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
+        ensure_group_room_name(&new_chat_room_info.room_name)?;
+
+        // updating the chat room info
+        match diesel::update(chat_rooms.filter(chat_rooms::chat_room_id.eq(_chat_room_id)))
+            .set((
+                room_name.eq(&new_chat_room_info.room_name),
+                room_description.eq(&new_chat_room_info.room_description),
+            ))
+            .returning(QChatRooms::as_returning())
+            .get_result(_conn)
+        {
+            Ok(_) => get_group_chat_by_name(_conn, &new_chat_room_info.room_name),
+            Err(e) => return Err(ChatuzaError::Database(e)),
         }
-    }
+    })
 }
 
 pub fn delete_group_chat_room(
     _conn: &mut PgConnection,
     _chat_room_name: &String,
     remover_username: &String,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let remover_user_id: i32;
-    match get_user_with_username(_conn, remover_username) {
-        Ok(res) => remover_user_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+) -> Result<bool, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let remover_user_id: i32;
+        match get_user_with_username(_conn, remover_username) {
+            Ok(res) => remover_user_id = res.user_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    let _chat_room_id: i32;
-    match get_group_chat_by_name(_conn, _chat_room_name) {
-        Ok(res) => _chat_room_id = res.chat_room_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+        let _chat_room_id: i32;
+        match get_group_chat_by_name(_conn, _chat_room_name) {
+            Ok(res) => _chat_room_id = res.chat_room_id,
+            Err(e) => return Err(e),
         }
-    }
 
-    match get_group_owner_by_id(_conn, _chat_room_id) {
-        Ok(res) => {
-            if remover_user_id != res {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::PermissionDenied,
-                    format!(
-                        "user id {} is not allowed to delete the group",
-                        remover_user_id
-                    ),
-                )));
-            }
+        if let Err(e) = ensure_group_permission(
+            _conn,
+            _chat_room_id,
+            remover_user_id,
+            GroupAction::DeleteRoom,
+        ) {
+            return Err(e);
         }
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
+
+        match remove_chat_room(_conn, _chat_room_id) {
+            Ok(_) => Ok(true),
+            Err(e) => return Err(e),
         }
-    }
+    })
+}
 
-    // deleting the members associated to the chat room group
+// drops the members of the room and then the room itself, the callers run it inside their transaction
+fn remove_chat_room(_conn: &mut PgConnection, _chat_room_id: i32) -> Result<(), ChatuzaError> {
+    // deleting the members associated to the chat room
     if let Err(e) = diesel::delete(
         chat_room_participants.filter(chat_room_participants::chat_room_id.eq(_chat_room_id)),
     )
     .execute(_conn)
     {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("{:?}", e),
-        )));
+        return Err(ChatuzaError::Database(e));
     }
     // deleting the room from the chat rooms table
     match diesel::delete(chat_rooms.filter(chat_rooms::chat_room_id.eq(_chat_room_id)))
         .execute(_conn)
     {
-        Ok(_) => Ok(true),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
-        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
@@ -760,43 +672,45 @@ pub fn add_participant_to_group_chat_room(
     _conn: &mut PgConnection,
     _adding_user: &ChatRoomParticipants,
     _adder_username: &String,
-) -> Result<ChatRoomParticipants, Box<dyn std::error::Error>> {
+) -> Result<ChatRoomParticipants, ChatuzaError> {
     let _adder_user_id: i32;
     match get_user_with_username(_conn, _adder_username) {
         Ok(res) => _adder_user_id = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
-        }
+        Err(e) => return Err(e),
+    }
+
+    let adder_role;
+    match ensure_group_permission(
+        _conn,
+        _adding_user.chat_room_id,
+        _adder_user_id,
+        GroupAction::AddMembers,
+    ) {
+        Ok(res) => adder_role = res,
+        Err(e) => return Err(e),
+    }
+    // new participants can't be handed a role as high as the one of the adder
+    if _adding_user.role.rank() >= adder_role.rank() {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "add a participant as {} to the chat room id {}",
+            _adding_user.role.as_str(),
+            _adding_user.chat_room_id
+        )));
     }
 
-    match get_group_owner_by_id(_conn, _adding_user.chat_room_id) {
-        Ok(res) => {
-            if _adder_user_id != res {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::PermissionDenied,
-                    format!(
-                        "user id {} is not allowed to add members the group",
-                        _adder_user_id
-                    ),
-                )));
-            }
-        }
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
-        }
-    }
+    insert_group_participant(_conn, _adding_user)
+}
 
+// inserts the participant once the caller checked it may join, shared with the invite redemption
+pub fn insert_group_participant(
+    _conn: &mut PgConnection,
+    _adding_user: &ChatRoomParticipants,
+) -> Result<ChatRoomParticipants, ChatuzaError> {
     if !is_group_chat(_conn, _adding_user.chat_room_id) {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "couldn't find the group chat room",
-        )));
+        return Err(ChatuzaError::NotFound(
+            "group chat room id",
+            _adding_user.chat_room_id.to_string(),
+        ));
     }
 
     // checking if the user is not already in the group
@@ -811,12 +725,9 @@ pub fn add_participant_to_group_chat_room(
         .unwrap_or(vec![]);
 
     if chat_room_info.len() != 0 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            format!(
-                "user id {} is already in the group chat room id {}",
-                _adding_user.user_id, _adding_user.chat_room_id
-            ),
+        return Err(ChatuzaError::AlreadyExists(format!(
+            "user id {} is already in the group chat room id {}",
+            _adding_user.user_id, _adding_user.chat_room_id
         )));
     }
 
@@ -827,12 +738,7 @@ pub fn add_participant_to_group_chat_room(
         .get_result(_conn)
     {
         Ok(res) => Ok(res),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
-        }
+        Err(e) => return Err(ChatuzaError::Database(e)),
     }
 }
 
@@ -840,97 +746,350 @@ pub fn del_participant_from_group_chat_room(
     _conn: &mut PgConnection,
     _removing_user: &ChatRoomParticipants,
     remover_user_id: i32,
-) -> Result<bool, Box<dyn std::error::Error>> {
+) -> Result<bool, ChatuzaError> {
     if !is_group_chat(_conn, _removing_user.chat_room_id) {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "couldn't find the group chat room",
-        )));
+        return Err(ChatuzaError::NotFound(
+            "group chat room id",
+            _removing_user.chat_room_id.to_string(),
+        ));
     }
-    let owner;
-    match get_group_owner_by_id(_conn, _removing_user.chat_room_id) {
-        Ok(res) => owner = res,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{}", e),
-            )))
+
+    // checking if the user is in the group
+    let removing_role;
+    match get_participant_role(_conn, _removing_user.chat_room_id, _removing_user.user_id) {
+        Ok(res) => removing_role = res,
+        Err(e) => return Err(e),
+    }
+
+    if remover_user_id == _removing_user.user_id {
+        // leaving, the owner goes through the leave policy so the room never ends up without one
+        if removing_role == ParticipantRole::Owner {
+            return match owner_leave_group_chat_room(
+                _conn,
+                _removing_user.chat_room_id,
+                _removing_user.user_id,
+            ) {
+                Ok(_) => Ok(true),
+                Err(e) => Err(e),
+            };
+        }
+    } else {
+        let remover_role;
+        match ensure_group_permission(
+            _conn,
+            _removing_user.chat_room_id,
+            remover_user_id,
+            GroupAction::RemoveMembers,
+        ) {
+            Ok(res) => remover_role = res,
+            Err(e) => return Err(e),
+        }
+        if removing_role.rank() >= remover_role.rank() {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "remove a participant ranked {} from the chat room id {}",
+                removing_role.as_str(),
+                _removing_user.chat_room_id
+            )));
         }
     }
 
-    if remover_user_id != owner && remover_user_id != _removing_user.user_id {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::PermissionDenied,
-            format!(
-                "user id {} is not allowed to remove users from group",
-                remover_user_id
-            ),
-        )));
+    // deleting the user from the participants table
+    match diesel::delete(
+        chat_room_participants.filter(
+            chat_room_participants::chat_room_id
+                .eq(_removing_user.chat_room_id)
+                .and(chat_room_participants::user_id.eq(_removing_user.user_id)),
+        ),
+    )
+    .execute(_conn)
+    {
+        Ok(_) => Ok(true),
+        Err(e) => return Err(ChatuzaError::Database(e)),
     }
+}
 
-    // checking if the user is in the group
-    let chat_room_info: Vec<ChatRoomParticipants> = chat_room_participants
+// promotes or demotes a participant, the owner role only changes hands through an ownership transfer
+pub fn change_participant_role(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    changer_user_id: i32,
+    target_user_id: i32,
+    new_role: ParticipantRole,
+) -> Result<ChatRoomParticipants, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let changer_role;
+        match ensure_group_permission(
+            _conn,
+            _chat_room_id,
+            changer_user_id,
+            GroupAction::ChangeRoles,
+        ) {
+            Ok(res) => changer_role = res,
+            Err(e) => return Err(e),
+        }
+
+        let target_role;
+        match get_participant_role(_conn, _chat_room_id, target_user_id) {
+            Ok(res) => target_role = res,
+            Err(e) => return Err(e),
+        }
+
+        if new_role == ParticipantRole::Owner {
+            return Err(ChatuzaError::Validation(
+                "the owner role can only be transferred".to_owned(),
+            ));
+        }
+        // both the current and the new role of the target must stay below the changer's
+        if target_role.rank() >= changer_role.rank() || new_role.rank() >= changer_role.rank() {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "make user id {} {} in chat room id {}",
+                target_user_id,
+                new_role.as_str(),
+                _chat_room_id
+            )));
+        }
+
+        set_participant_role(_conn, _chat_room_id, target_user_id, new_role)
+    })
+}
+
+pub fn transfer_group_ownership(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    from_user_id: i32,
+    to_user_id: i32,
+) -> Result<ChatRoomParticipants, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        match get_group_owner_by_id(_conn, _chat_room_id) {
+            Ok(res) => {
+                if res != from_user_id {
+                    return Err(ChatuzaError::PermissionDenied(format!(
+                        "transfer the ownership of chat room id {}",
+                        _chat_room_id
+                    )));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        if from_user_id == to_user_id {
+            return Err(ChatuzaError::Validation(format!(
+                "user id {} already owns the chat room id {}",
+                to_user_id, _chat_room_id
+            )));
+        }
+        if let Err(e) = get_participant_role(_conn, _chat_room_id, to_user_id) {
+            return Err(e);
+        }
+
+        // the old owner steps down first, the owner index allows a single owner per room
+        if let Err(e) =
+            set_participant_role(_conn, _chat_room_id, from_user_id, ParticipantRole::Admin)
+        {
+            return Err(e);
+        }
+        set_participant_role(_conn, _chat_room_id, to_user_id, ParticipantRole::Owner)
+    })
+}
+
+pub enum OwnerLeaveOutcome {
+    Transferred { new_owner_id: i32 },
+    RoomDeleted,
+}
+
+// removes the owner from the group and applies the owner leave policy of the room,
+// a group that would be left empty is deleted whatever the policy says
+pub fn owner_leave_group_chat_room(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    owner_user_id: i32,
+) -> Result<OwnerLeaveOutcome, ChatuzaError> {
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let chat_room;
+        match get_group_chat_by_id(_conn, _chat_room_id) {
+            Ok(res) => chat_room = res,
+            Err(e) => return Err(e),
+        }
+
+        let successor = match chat_room.owner_leave_policy {
+            OwnerLeavePolicy::Transfer => {
+                get_group_owner_successor(_conn, _chat_room_id, owner_user_id)?
+            }
+            OwnerLeavePolicy::DeleteRoom => None,
+        };
+
+        match successor {
+            Some(res) => {
+                transfer_group_ownership(_conn, _chat_room_id, owner_user_id, res.user_id)?;
+                if let Err(e) = diesel::delete(
+                    chat_room_participants.filter(
+                        chat_room_participants::chat_room_id
+                            .eq(_chat_room_id)
+                            .and(chat_room_participants::user_id.eq(owner_user_id)),
+                    ),
+                )
+                .execute(_conn)
+                {
+                    return Err(ChatuzaError::Database(e));
+                }
+                Ok(OwnerLeaveOutcome::Transferred {
+                    new_owner_id: res.user_id,
+                })
+            }
+            None => match remove_chat_room(_conn, _chat_room_id) {
+                Ok(_) => Ok(OwnerLeaveOutcome::RoomDeleted),
+                Err(e) => Err(e),
+            },
+        }
+    })
+}
+
+// admins come first, then moderators, members and read only ones, the oldest of them wins
+pub fn get_group_owner_successor(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    owner_user_id: i32,
+) -> Result<Option<QChatRoomParticipants>, ChatuzaError> {
+    // the postgres enum sorts in declaration order, owner first and read_only last
+    match chat_room_participants
         .filter(
             chat_room_participants::chat_room_id
-                .eq(_removing_user.chat_room_id)
-                .and(chat_room_participants::user_id.eq(_removing_user.user_id)),
+                .eq(_chat_room_id)
+                .and(chat_room_participants::user_id.ne(owner_user_id)),
         )
-        .select(ChatRoomParticipants::as_returning())
-        .load(_conn)
-        .unwrap_or(vec![]);
+        .order((
+            chat_room_participants::role.asc(),
+            chat_room_participants::joined_at.asc(),
+            chat_room_participants::participant_id.asc(),
+        ))
+        .select(QChatRoomParticipants::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
 
-    if chat_room_info.len() != 1 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!(
-                "user id {} is not in the group chat room id {}",
-                _removing_user.user_id, _removing_user.chat_room_id
-            ),
-        )));
+pub fn set_owner_leave_policy(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    editor_user_id: i32,
+    new_policy: OwnerLeavePolicy,
+) -> Result<QChatRooms, ChatuzaError> {
+    // only the owner decides what happens to the group once they are gone
+    if let Err(e) = ensure_group_permission(
+        _conn,
+        _chat_room_id,
+        editor_user_id,
+        GroupAction::DeleteRoom,
+    ) {
+        return Err(e);
     }
 
-    // deleting the user from the participants table
-    match diesel::delete(
-        chat_room_participants.filter(chat_room_participants::user_id.eq(_removing_user.user_id)),
+    match diesel::update(chat_rooms.filter(chat_rooms::chat_room_id.eq(_chat_room_id)))
+        .set(chat_rooms::owner_leave_policy.eq(new_policy))
+        .returning(QChatRooms::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+fn set_participant_role(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    _user_id: i32,
+    new_role: ParticipantRole,
+) -> Result<ChatRoomParticipants, ChatuzaError> {
+    match diesel::update(
+        chat_room_participants.filter(
+            chat_room_participants::chat_room_id
+                .eq(_chat_room_id)
+                .and(chat_room_participants::user_id.eq(_user_id)),
+        ),
     )
-    .execute(_conn)
+    .set(chat_room_participants::role.eq(new_role))
+    .returning(ChatRoomParticipants::as_returning())
+    .get_result(_conn)
     {
-        Ok(_) => Ok(true),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("{:?}", e),
-            )))
-        }
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
 pub fn get_chat_room_participants_by_id(
     _conn: &mut PgConnection,
     _chat_room_id: i32,
-) -> Result<Vec<ChatRoomParticipants>, Box<dyn std::error::Error>> {
+) -> Result<Vec<ChatRoomParticipants>, ChatuzaError> {
+    if !is_valid_chatroom(_conn, _chat_room_id) {
+        return Err(ChatuzaError::NotFound(
+            "chat room id",
+            _chat_room_id.to_string(),
+        ));
+    }
     // getting the participants
-    let participants: Vec<ChatRoomParticipants> = chat_room_participants
+    match chat_room_participants
         .filter(chat_room_participants::chat_room_id.eq(_chat_room_id))
         .select(ChatRoomParticipants::as_select())
         .load(_conn)
-        .unwrap_or(vec![]);
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
 
-    if participants.len() == 0 {
-        // chat room id doesn't exists
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "chat room id not found or it has no members!",
-        )))
-    } else {
-        Ok(participants)
+// one page of the participants ordered by participant id, the filters are optional
+pub fn get_chat_room_participants_page(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    filter: &ParticipantListFilter,
+    page: &PageRequest,
+) -> Result<Page<QChatRoomParticipants>, ChatuzaError> {
+    if !is_valid_chatroom(_conn, _chat_room_id) {
+        return Err(ChatuzaError::NotFound(
+            "chat room id",
+            _chat_room_id.to_string(),
+        ));
+    }
+
+    let mut query = chat_room_participants
+        .filter(chat_room_participants::chat_room_id.eq(_chat_room_id))
+        .select(QChatRoomParticipants::as_select())
+        .limit(page.limit + 1)
+        .into_boxed();
+
+    if let Some(_role) = filter.role {
+        query = query.filter(chat_room_participants::role.eq(_role));
+    }
+    if let Some(_joined_after) = filter.joined_after {
+        query = query.filter(chat_room_participants::joined_at.gt(_joined_after));
+    }
+    match page.direction {
+        PageDirection::Forward => {
+            if let Some(cursor) = page.cursor {
+                query = query.filter(chat_room_participants::participant_id.gt(cursor));
+            }
+            query = query.order(chat_room_participants::participant_id.asc());
+        }
+        PageDirection::Backward => {
+            if let Some(cursor) = page.cursor {
+                query = query.filter(chat_room_participants::participant_id.lt(cursor));
+            }
+            query = query.order(chat_room_participants::participant_id.desc());
+        }
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(Page::from_rows(res, page, |res| res.participant_id)),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
 pub fn get_chat_room_participants_by_name(
     _conn: &mut PgConnection,
     _chat_room_name: &String,
-) -> Result<Vec<ChatRoomParticipants>, Box<dyn std::error::Error>> {
+) -> Result<Vec<ChatRoomParticipants>, ChatuzaError> {
     let _chat_room: Vec<QChatRooms> = chat_rooms
         .filter(chat_rooms::room_name.eq(_chat_room_name))
         .select(QChatRooms::as_select())
@@ -938,56 +1097,49 @@ pub fn get_chat_room_participants_by_name(
         .unwrap_or(vec![]);
 
     if _chat_room.len() != 1 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("chat room {} not found !", _chat_room_name),
-        )));
+        return Err(ChatuzaError::NotFound(
+            "chat room",
+            _chat_room_name.to_string(),
+        ));
     }
     // getting the participants
-    let participants: Vec<ChatRoomParticipants> = chat_room_participants
+    match chat_room_participants
         .filter(chat_room_participants::chat_room_id.eq(_chat_room[0].chat_room_id))
         .select(ChatRoomParticipants::as_select())
         .load(_conn)
-        .unwrap_or(vec![]);
-
-    if participants.len() == 0 {
-        // chat room id doesn't exists
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "chat room id not found or it has no members",
-        )))
-    } else {
-        Ok(participants)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
 pub fn get_group_owner_by_id(
     _conn: &mut PgConnection,
     _chat_room_id: i32,
-) -> Result<i32, Box<dyn std::error::Error>> {
+) -> Result<i32, ChatuzaError> {
     // checking if the gp is valid
     if !is_group_chat(_conn, _chat_room_id) {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "invalid chat room id !",
-        )));
+        return Err(ChatuzaError::NotFound(
+            "group chat room id",
+            _chat_room_id.to_string(),
+        ));
     }
     let _chat_room_owner: Vec<ChatRoomParticipants> = chat_room_participants
         .filter(
             chat_room_participants::chat_room_id
                 .eq(_chat_room_id)
-                .and(chat_room_participants::is_admin.eq(true)),
+                .and(chat_room_participants::role.eq(ParticipantRole::Owner)),
         )
         .select(ChatRoomParticipants::as_select())
         .load(_conn)
         .unwrap_or(vec![]);
 
     if _chat_room_owner.len() != 1 {
-        // write now only one admin is supported
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "chat room owner not found !",
-        )))
+        // the owner index keeps it to one owner per group
+        Err(ChatuzaError::NotFound(
+            "owner of chat room id",
+            _chat_room_id.to_string(),
+        ))
     } else {
         Ok(_chat_room_owner[0].user_id)
     }
@@ -996,32 +1148,28 @@ pub fn get_group_owner_by_id(
 pub fn get_group_chat_by_name(
     _conn: &mut PgConnection,
     _chat_room_name: &String,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
+) -> Result<QChatRooms, ChatuzaError> {
     let _chat_rooms: Vec<QChatRooms> = chat_rooms
         .filter(chat_rooms::room_name.eq(_chat_room_name))
+        .filter(chat_rooms::room_kind.eq(RoomKind::Group))
         .select(QChatRooms::as_returning())
         .load(_conn)
         .unwrap_or(vec![]);
 
     if _chat_rooms.len() != 1 {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("{} chat room not found !", _chat_room_name),
-        )))
+        Err(ChatuzaError::NotFound(
+            "chat room",
+            _chat_room_name.to_string(),
+        ))
     } else {
-        return Ok(QChatRooms {
-            chat_room_id: _chat_rooms[0].chat_room_id,
-            room_name: _chat_rooms[0].room_name.clone(),
-            room_description: _chat_rooms[0].room_description.clone(),
-            chat_room_pubkey: _chat_rooms[0].chat_room_pubkey.to_owned(),
-        });
+        return Ok(_chat_rooms.into_iter().next().unwrap()); // panic impossible
     }
 }
 
 pub fn get_group_chat_by_id(
     _conn: &mut PgConnection,
     _chat_room_id: i32,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
+) -> Result<QChatRooms, ChatuzaError> {
     let _chat_rooms: Vec<QChatRooms> = chat_rooms
         .filter(chat_rooms::chat_room_id.eq(_chat_room_id))
         .select(QChatRooms::as_returning())
@@ -1029,17 +1177,12 @@ pub fn get_group_chat_by_id(
         .unwrap_or(vec![]);
 
     if _chat_rooms.len() != 1 {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("chat room id {} not found !", _chat_room_id),
-        )))
+        Err(ChatuzaError::NotFound(
+            "chat room id",
+            _chat_room_id.to_string(),
+        ))
     } else {
-        return Ok(QChatRooms {
-            chat_room_id: _chat_rooms[0].chat_room_id,
-            room_name: _chat_rooms[0].room_name.clone(),
-            room_description: _chat_rooms[0].room_description.clone(),
-            chat_room_pubkey: _chat_rooms[0].chat_room_pubkey.to_owned(),
-        });
+        return Ok(_chat_rooms.into_iter().next().unwrap()); // panic impossible
     }
 }
 pub fn is_group_chat(_conn: &mut PgConnection, _chat_room_id: i32) -> bool {
@@ -1051,10 +1194,8 @@ pub fn is_group_chat(_conn: &mut PgConnection, _chat_room_id: i32) -> bool {
 
     if chat_room_info.len() != 1 {
         false
-    } else if chat_room_info[0].room_description == "private room" {
-        false
     } else {
-        true
+        chat_room_info[0].room_kind == RoomKind::Group
     }
 }
 
@@ -1107,52 +1248,104 @@ pub fn is_user_in_chat_room(_conn: &mut PgConnection, _chat_room_id: i32, _user_
 pub fn get_user_p2p_chat_rooms_by_user_id(
     _conn: &mut PgConnection,
     _user_id: i32,
-) -> Result<Vec<QChatRooms>, Box<dyn std::error::Error>> {
-    let mut _chat_rooms: Vec<QChatRooms> = chat_rooms
+) -> Result<Vec<QChatRooms>, ChatuzaError> {
+    match chat_rooms
         .inner_join(
             chat_room_participants
                 .on(chat_room_participants::chat_room_id.eq(chat_rooms::chat_room_id)),
         )
-        .filter(chat_room_participants::user_id.eq(chat_room_participants::user_id))
-        .filter(chat_rooms::room_description.eq("private room")) // Add this filter for room name
+        .filter(chat_room_participants::user_id.eq(_user_id))
+        .filter(chat_rooms::room_kind.eq(RoomKind::Direct))
         .select(QChatRooms::as_select())
         .load(_conn)
-        .unwrap_or(vec![]);
-
-    if _chat_rooms.len() == 0 {
-        // chat room id doesn't exists
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("no p2p chat rooms for user id {} ", _user_id),
-        )))
-    } else {
-        Ok(_chat_rooms)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
+pub fn get_user_p2p_chat_rooms_page(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    filter: &ChatRoomListFilter,
+    page: &PageRequest,
+) -> Result<Page<QChatRooms>, ChatuzaError> {
+    get_user_chat_rooms_page(_conn, _user_id, RoomKind::Direct, filter, page)
+}
+
 pub fn get_user_group_chat_rooms_by_user_id(
     _conn: &mut PgConnection,
     _user_id: i32,
-) -> Result<Vec<QChatRooms>, Box<dyn std::error::Error>> {
-    let mut _chat_rooms: Vec<QChatRooms> = chat_rooms
+) -> Result<Vec<QChatRooms>, ChatuzaError> {
+    match chat_rooms
         .inner_join(
             chat_room_participants
                 .on(chat_room_participants::chat_room_id.eq(chat_rooms::chat_room_id)),
         )
         .filter(chat_room_participants::user_id.eq(_user_id))
-        .filter(chat_rooms::room_description.ne("private room")) // Add this filter for room name
+        .filter(chat_rooms::room_kind.eq(RoomKind::Group))
         .select(QChatRooms::as_select())
         .load(_conn)
-        .unwrap_or(vec![]); // this function never fails, if there is none we will get a empty vector
-
-    if _chat_rooms.len() == 0 {
-        // chat room id doesn't exists
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("no group chat rooms for user id {} ", _user_id),
-        )))
-    } else {
-        Ok(_chat_rooms)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+pub fn get_user_group_chat_rooms_page(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    filter: &ChatRoomListFilter,
+    page: &PageRequest,
+) -> Result<Page<QChatRooms>, ChatuzaError> {
+    get_user_chat_rooms_page(_conn, _user_id, RoomKind::Group, filter, page)
+}
+
+// one page of the rooms of the user ordered by chat room id, optionally narrowed to a name prefix
+fn get_user_chat_rooms_page(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    kind: RoomKind,
+    filter: &ChatRoomListFilter,
+    page: &PageRequest,
+) -> Result<Page<QChatRooms>, ChatuzaError> {
+    let mut query = chat_rooms
+        .inner_join(
+            chat_room_participants
+                .on(chat_room_participants::chat_room_id.eq(chat_rooms::chat_room_id)),
+        )
+        .filter(chat_room_participants::user_id.eq(_user_id))
+        .filter(chat_rooms::room_kind.eq(kind))
+        .select(QChatRooms::as_select())
+        .limit(page.limit + 1)
+        .into_boxed();
+
+    if let Some(prefix) = &filter.name_prefix {
+        // the like wildcards typed by the user are matched literally
+        let escaped_prefix = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query = query.filter(chat_rooms::room_name.like(format!("{}%", escaped_prefix)));
+    }
+    match page.direction {
+        PageDirection::Forward => {
+            if let Some(cursor) = page.cursor {
+                query = query.filter(chat_rooms::chat_room_id.gt(cursor));
+            }
+            query = query.order(chat_rooms::chat_room_id.asc());
+        }
+        PageDirection::Backward => {
+            if let Some(cursor) = page.cursor {
+                query = query.filter(chat_rooms::chat_room_id.lt(cursor));
+            }
+            query = query.order(chat_rooms::chat_room_id.desc());
+        }
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(Page::from_rows(res, page, |res| res.chat_room_id)),
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
@@ -1160,48 +1353,283 @@ pub fn get_two_users_p2p_chat_room(
     _conn: &mut PgConnection,
     _user_id_1: i32,
     _user_id_2: i32,
-) -> Result<QChatRooms, Box<dyn std::error::Error>> {
+) -> Result<QChatRooms, ChatuzaError> {
     if !get_user_with_user_id(_conn, _user_id_1).is_ok() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("user id  {} doesn't exist's", _user_id_1),
-        )));
+        return Err(ChatuzaError::NotFound("user id", _user_id_1.to_string()));
     }
     if !get_user_with_user_id(_conn, _user_id_2).is_ok() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("user id  {} doesn't exist's", _user_id_2),
-        )));
+        return Err(ChatuzaError::NotFound("user id", _user_id_2.to_string()));
     }
 
-    let _chat_room_participants: Vec<ChatRoomParticipants>;
-    {
-        _chat_room_participants = chat_room_participants
-            .filter(
-                chat_room_participants::user_id
-                    .eq(_user_id_1)
-                    .or(chat_room_participants::user_id.eq(_user_id_2)),
-            )
-            .group_by(chat_room_participants::chat_room_id)
-            .having(diesel::dsl::count(chat_room_participants::user_id).eq(2))
-            .select(ChatRoomParticipants::as_select())
-            .load(_conn)
-            .unwrap_or(vec![]);
+    let (user_low, user_high) = direct_user_pair(_user_id_1, _user_id_2);
+    let _chat_rooms: Vec<QChatRooms> = chat_rooms
+        .filter(
+            chat_rooms::room_kind
+                .eq(RoomKind::Direct)
+                .and(chat_rooms::direct_user_low.eq(user_low))
+                .and(chat_rooms::direct_user_high.eq(user_high)),
+        )
+        .select(QChatRooms::as_select())
+        .load(_conn)
+        .unwrap_or(vec![]);
+
+    if _chat_rooms.len() != 1 {
+        return Err(ChatuzaError::NotFound(
+            "p2p chat room",
+            format!("between user id {} and user id {}", _user_id_1, _user_id_2),
+        ));
     }
-    if _chat_room_participants.len() != 1 {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!(
-                "user id  {} and user id {} are not charing any p2p chat rooms !",
-                _user_id_1, _user_id_2
-            ),
-        )));
+    Ok(_chat_rooms.into_iter().next().unwrap()) // panic impossible
+}
+
+// direct rooms store their users sorted so a pair maps to a single row whoever created it
+pub fn direct_user_pair(_user_id_1: i32, _user_id_2: i32) -> (i32, i32) {
+    if _user_id_1 < _user_id_2 {
+        (_user_id_1, _user_id_2)
+    } else {
+        (_user_id_2, _user_id_1)
     }
-    match get_group_chat_by_id(_conn, _chat_room_participants[0].chat_room_id) {
-        Ok(res) => Ok(res),
-        Err(e) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("{}", e),
-        ))),
+}
+
+// these run against the `DATABASE_URL` database, every test stays inside a transaction that is never committed
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_connection() -> PgConnection {
+        let mut conn = establish_connection();
+        conn.begin_test_transaction().unwrap();
+        conn
+    }
+
+    // makes the matching `<event>` on `<table>` raise, the function and trigger go away with the test transaction
+    fn fail_on(conn: &mut PgConnection, _name: &str, _event: &str, _table: &str, _condition: &str) {
+        diesel::sql_query(format!(
+            "CREATE FUNCTION fail_{0}() RETURNS trigger AS $$ BEGIN RAISE EXCEPTION 'injected failure'; END; $$ LANGUAGE plpgsql",
+            _name
+        ))
+        .execute(conn)
+        .unwrap();
+        diesel::sql_query(format!(
+            "CREATE TRIGGER fail_{0} BEFORE {1} ON {2} FOR EACH ROW WHEN ({3}) EXECUTE FUNCTION fail_{0}()",
+            _name, _event, _table, _condition
+        ))
+        .execute(conn)
+        .unwrap();
+    }
+
+    fn new_test_user(conn: &mut PgConnection, _username: &str, _phone_number: &str) -> i32 {
+        add_new_user(
+            conn,
+            &Users {
+                username: _username.to_owned(),
+                email: format!("{}@example.com", _username),
+                password: "correct horse battery staple".to_owned(),
+                phone_number: _phone_number.to_owned(),
+            },
+            &mut UserProfiles {
+                user_id: 0,
+                bio: None,
+                profile_picture: None,
+            },
+        )
+        .unwrap()
+        .user_id
+    }
+
+    fn count_users(conn: &mut PgConnection, _username: &str) -> i64 {
+        users
+            .filter(username.eq(_username))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn count_user_profiles(conn: &mut PgConnection, _user_id: i32) -> i64 {
+        user_profiles
+            .filter(user_profiles::user_id.eq(_user_id))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn count_chat_rooms(conn: &mut PgConnection, _room_name: &str) -> i64 {
+        chat_rooms
+            .filter(chat_rooms::room_name.eq(_room_name))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn count_participations(conn: &mut PgConnection, _user_id: i32) -> i64 {
+        chat_room_participants
+            .filter(chat_room_participants::user_id.eq(_user_id))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn add_new_user_leaves_no_user_behind_when_the_profile_fails() {
+        let conn = &mut test_connection();
+        fail_on(conn, "new_user_profile", "INSERT", "user_profiles", "true");
+
+        let res = add_new_user(
+            conn,
+            &Users {
+                username: "tx_new_user".to_owned(),
+                email: "tx_new_user@example.com".to_owned(),
+                password: "correct horse battery staple".to_owned(),
+                phone_number: "+14155550111".to_owned(),
+            },
+            &mut UserProfiles {
+                user_id: 0,
+                bio: Some("bio".to_owned()),
+                profile_picture: None,
+            },
+        );
+
+        assert!(matches!(res, Err(ChatuzaError::Database(_))));
+        assert_eq!(count_users(conn, "tx_new_user"), 0);
+        assert_eq!(
+            user_profiles
+                .filter(bio.eq("bio"))
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn add_new_p2p_chat_room_leaves_no_room_behind_when_the_acceptor_fails() {
+        let conn = &mut test_connection();
+        let requestor = new_test_user(conn, "tx_p2p_requestor", "+14155550121");
+        let acceptor = new_test_user(conn, "tx_p2p_acceptor", "+14155550122");
+        fail_on(
+            conn,
+            "p2p_acceptor",
+            "INSERT",
+            "chat_room_participants",
+            &format!("NEW.user_id = {}", acceptor),
+        );
+
+        let res = add_new_p2p_chat_room(conn, requestor, acceptor, "pubkey".to_owned());
+
+        assert!(matches!(res, Err(ChatuzaError::Database(_))));
+        let (user_low, user_high) = direct_user_pair(requestor, acceptor);
+        assert_eq!(
+            count_chat_rooms(
+                conn,
+                &format!("{}{}-{}", DIRECT_ROOM_NAME_PREFIX, user_low, user_high)
+            ),
+            0
+        );
+        assert_eq!(count_participations(conn, requestor), 0);
+        assert_eq!(count_participations(conn, acceptor), 0);
+    }
+
+    #[test]
+    fn add_new_group_chat_room_leaves_no_room_behind_when_a_member_fails() {
+        let conn = &mut test_connection();
+        let owner = new_test_user(conn, "tx_group_owner", "+14155550131");
+        let member = new_test_user(conn, "tx_group_member", "+14155550132");
+        fail_on(
+            conn,
+            "group_member",
+            "INSERT",
+            "chat_room_participants",
+            &format!("NEW.user_id = {}", member),
+        );
+
+        let res = add_new_group_chat_room(
+            conn,
+            &ChatRooms {
+                room_name: "tx_group".to_owned(),
+                room_description: "".to_owned(),
+                chat_room_pubkey: b"pubkey".to_vec(),
+                room_kind: RoomKind::Group,
+                direct_user_low: None,
+                direct_user_high: None,
+                owner_leave_policy: OwnerLeavePolicy::Transfer,
+            },
+            &"tx_group_owner".to_owned(),
+            vec!["tx_group_member".to_owned()],
+        );
+
+        assert!(matches!(res, Err(ChatuzaError::Database(_))));
+        assert_eq!(count_chat_rooms(conn, "tx_group"), 0);
+        assert_eq!(count_participations(conn, owner), 0);
+        assert_eq!(count_participations(conn, member), 0);
+    }
+
+    #[test]
+    fn delete_user_keeps_everything_when_the_last_step_fails() {
+        let conn = &mut test_connection();
+        let leaving = new_test_user(conn, "tx_delete_leaving", "+14155550141");
+        let contact = new_test_user(conn, "tx_delete_contact", "+14155550142");
+        let p2p_chat_room =
+            add_new_p2p_chat_room(conn, leaving, contact, "pubkey".to_owned()).unwrap();
+        add_new_group_chat_room(
+            conn,
+            &ChatRooms {
+                room_name: "tx_delete_group".to_owned(),
+                room_description: "".to_owned(),
+                chat_room_pubkey: b"pubkey".to_vec(),
+                room_kind: RoomKind::Group,
+                direct_user_low: None,
+                direct_user_high: None,
+                owner_leave_policy: OwnerLeavePolicy::Transfer,
+            },
+            &"tx_delete_contact".to_owned(),
+            vec!["tx_delete_leaving".to_owned()],
+        )
+        .unwrap();
+        // the rooms and the profile are gone by the time the user row is deleted
+        fail_on(
+            conn,
+            "delete_user",
+            "DELETE",
+            "users",
+            &format!("OLD.user_id = {}", leaving),
+        );
+
+        let res = delete_user(conn, &"tx_delete_leaving".to_owned());
+
+        assert!(matches!(res, Err(ChatuzaError::Database(_))));
+        assert_eq!(count_users(conn, "tx_delete_leaving"), 1);
+        assert_eq!(count_user_profiles(conn, leaving), 1);
+        assert_eq!(count_chat_rooms(conn, &p2p_chat_room.room_name), 1);
+        assert_eq!(count_chat_rooms(conn, "tx_delete_group"), 1);
+        assert_eq!(count_participations(conn, leaving), 2);
+        assert_eq!(count_participations(conn, contact), 2);
+    }
+
+    #[test]
+    fn groups_cant_take_the_name_of_a_direct_room() {
+        let conn = &mut test_connection();
+        let first = new_test_user(conn, "tx_squat_first", "+14155550161");
+        let second = new_test_user(conn, "tx_squat_second", "+14155550162");
+        let (user_low, user_high) = direct_user_pair(first, second);
+
+        let res = add_new_group_chat_room(
+            conn,
+            &ChatRooms {
+                room_name: format!("{}{}-{}", DIRECT_ROOM_NAME_PREFIX, user_low, user_high),
+                room_description: "".to_owned(),
+                chat_room_pubkey: b"pubkey".to_vec(),
+                room_kind: RoomKind::Group,
+                direct_user_low: None,
+                direct_user_high: None,
+                owner_leave_policy: OwnerLeavePolicy::Transfer,
+            },
+            &"tx_squat_first".to_owned(),
+            vec![],
+        );
+
+        assert!(matches!(res, Err(ChatuzaError::Validation(_))));
+        let p2p_chat_room =
+            add_new_p2p_chat_room(conn, first, second, "pubkey".to_owned()).unwrap();
+        assert!(get_group_chat_by_name(conn, &p2p_chat_room.room_name).is_err());
     }
 }
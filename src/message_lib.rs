@@ -0,0 +1,240 @@
+use crate::contact_lib::{has_blocked, is_blocked_between};
+use crate::db_models::{Messages, QMessages, RoomKind};
+use crate::errors::ChatuzaError;
+use crate::is_user_in_chat_room;
+use crate::permission_lib::{get_participant_role, role_can, GroupAction};
+use crate::schema::{chat_rooms, messages};
+use chrono::Utc;
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+pub const MAX_MESSAGES_PAGE_SIZE: i64 = 100;
+
+// -- Messages SETTER functions -- //
+
+pub fn send_message(
+    _conn: &mut PgConnection,
+    _sender_id: i32,
+    _chat_room_id: i32,
+    _payload: Vec<u8>,
+    _parent_message_id: Option<i32>,
+) -> Result<QMessages, ChatuzaError> {
+    // read only participants can follow the room but not post in it
+    match get_participant_role(_conn, _chat_room_id, _sender_id) {
+        Ok(res) if role_can(res, GroupAction::SendMessages) => {}
+        Ok(_) | Err(ChatuzaError::NotFound(..)) => {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "post in chat room id {}",
+                _chat_room_id
+            )))
+        }
+        Err(e) => return Err(e),
+    }
+
+    // a block on either side closes the p2p chat room until it's lifted
+    match get_direct_peer_id(_conn, _chat_room_id, _sender_id) {
+        Some(peer_id) if is_blocked_between(_conn, _sender_id, peer_id) => {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "post in chat room id {}",
+                _chat_room_id
+            )))
+        }
+        _ => {}
+    }
+
+    // replies must point to a message of the same chat room
+    if let Some(parent_id) = _parent_message_id {
+        match get_message_by_id(_conn, parent_id) {
+            Ok(res) => {
+                if res.chat_room_id != _chat_room_id {
+                    return Err(ChatuzaError::NotFound(
+                        "message",
+                        format!("id {} in chat room id {}", parent_id, _chat_room_id),
+                    ));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match diesel::insert_into(messages::table)
+        .values(&Messages {
+            chat_room_id: _chat_room_id,
+            sender_id: Some(_sender_id),
+            payload: _payload,
+            parent_message_id: _parent_message_id,
+        })
+        .returning(QMessages::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the author has to still be in the room, and the p2p chat room has to be open, to change a message
+fn ensure_message_author(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    message: &QMessages,
+    action: &str,
+) -> Result<(), ChatuzaError> {
+    let denied =
+        || ChatuzaError::PermissionDenied(format!("{} message id {}", action, message.message_id));
+    if message.sender_id != Some(_user_id) {
+        return Err(denied());
+    }
+    if !is_user_in_chat_room(_conn, message.chat_room_id, _user_id) {
+        return Err(denied());
+    }
+    match get_direct_peer_id(_conn, message.chat_room_id, _user_id) {
+        Some(peer_id) if is_blocked_between(_conn, _user_id, peer_id) => Err(denied()),
+        _ => Ok(()),
+    }
+}
+
+pub fn edit_message(
+    _conn: &mut PgConnection,
+    editor_user_id: i32,
+    _message_id: i32,
+    new_payload: Vec<u8>,
+) -> Result<QMessages, ChatuzaError> {
+    let message = get_message_by_id(_conn, _message_id)?;
+
+    ensure_message_author(_conn, editor_user_id, &message, "edit")?;
+    if message.deleted_at.is_some() {
+        return Err(ChatuzaError::NotFound(
+            "message id",
+            _message_id.to_string(),
+        ));
+    }
+
+    match diesel::update(messages::table.filter(messages::message_id.eq(_message_id)))
+        .set((
+            messages::payload.eq(new_payload),
+            messages::edited_at.eq(Utc::now().naive_utc()),
+        ))
+        .returning(QMessages::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// messages are soft deleted so the clients syncing the history can drop their local copy
+pub fn delete_message(
+    _conn: &mut PgConnection,
+    remover_user_id: i32,
+    _message_id: i32,
+) -> Result<QMessages, ChatuzaError> {
+    let message = get_message_by_id(_conn, _message_id)?;
+
+    ensure_message_author(_conn, remover_user_id, &message, "delete")?;
+    if message.deleted_at.is_some() {
+        return Ok(message);
+    }
+
+    match diesel::update(messages::table.filter(messages::message_id.eq(_message_id)))
+        .set((
+            messages::payload.eq(Vec::<u8>::new()),
+            messages::deleted_at.eq(Utc::now().naive_utc()),
+        ))
+        .returning(QMessages::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- Messages GETTER functions -- //
+
+pub fn get_message_by_id(
+    _conn: &mut PgConnection,
+    _message_id: i32,
+) -> Result<QMessages, ChatuzaError> {
+    let message_rows: Vec<QMessages> = messages::table
+        .filter(messages::message_id.eq(_message_id))
+        .select(QMessages::as_select())
+        .load(_conn)
+        .unwrap_or(vec![]);
+
+    if message_rows.len() == 1 {
+        Ok(message_rows.into_iter().next().unwrap()) // panic impossible
+    } else {
+        Err(ChatuzaError::NotFound(
+            "message id",
+            _message_id.to_string(),
+        ))
+    }
+}
+
+// `before` walks the history backwards (newest first), `after` syncs forward (oldest first)
+pub fn get_chat_room_messages(
+    _conn: &mut PgConnection,
+    reader_user_id: i32,
+    _chat_room_id: i32,
+    before_message_id: Option<i32>,
+    after_message_id: Option<i32>,
+    _limit: i64,
+) -> Result<Vec<QMessages>, ChatuzaError> {
+    if !is_user_in_chat_room(_conn, _chat_room_id, reader_user_id) {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "read the history of chat room id {}",
+            _chat_room_id
+        )));
+    }
+    // a user blocked by the other side of a direct room loses its history too
+    if let Some(peer_id) = get_direct_peer_id(_conn, _chat_room_id, reader_user_id) {
+        if has_blocked(_conn, peer_id, reader_user_id) {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "read the history of chat room id {}",
+                _chat_room_id
+            )));
+        }
+    }
+
+    let mut query = messages::table
+        .filter(messages::chat_room_id.eq(_chat_room_id))
+        .select(QMessages::as_select())
+        .limit(_limit.clamp(1, MAX_MESSAGES_PAGE_SIZE))
+        .into_boxed();
+
+    if let Some(before_id) = before_message_id {
+        query = query.filter(messages::message_id.lt(before_id));
+    }
+    match after_message_id {
+        Some(after_id) => {
+            query = query
+                .filter(messages::message_id.gt(after_id))
+                .order(messages::message_id.asc())
+        }
+        None => query = query.order(messages::message_id.desc()),
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the other user of a p2p chat room, None for the group chat rooms
+fn get_direct_peer_id(_conn: &mut PgConnection, _chat_room_id: i32, _user_id: i32) -> Option<i32> {
+    let pair: Option<(Option<i32>, Option<i32>)> = chat_rooms::table
+        .filter(
+            chat_rooms::chat_room_id
+                .eq(_chat_room_id)
+                .and(chat_rooms::room_kind.eq(RoomKind::Direct)),
+        )
+        .select((chat_rooms::direct_user_low, chat_rooms::direct_user_high))
+        .first(_conn)
+        .optional()
+        .unwrap_or(None);
+
+    match pair {
+        Some((Some(low), Some(high))) => Some(if low == _user_id { high } else { low }),
+        _ => None,
+    }
+}
@@ -0,0 +1,549 @@
+use crate::api_models::{
+    AuthTokensResponse, LoginResponse, MfaEnrollmentResponse, MfaRecoveryCodesResponse,
+};
+use crate::auth_lib::{create_session, get_active_session, hash_token_id, new_token_id};
+use crate::db_models::{
+    MfaLoginChallenges, MfaRecoveryCodes, QMfaLoginChallenges, QUserMfa, UserMfa,
+};
+use crate::errors::ChatuzaError;
+use crate::get_user_with_user_id;
+use crate::schema::{mfa_login_challenges, mfa_recovery_codes, sessions, user_mfa};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, NaiveDateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use rocket::http::uri::Uri;
+use sha1::Sha1;
+
+pub const MFA_ISSUER: &str = "Chatuza";
+// rfc 6238 defaults, the only ones every authenticator app supports
+const TOTP_STEP_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// steps accepted before and after the current one, for the clock drift of the phones
+const TOTP_SKEW_STEPS: i64 = 1;
+const TOTP_SECRET_LEN: usize = 20;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+pub const MFA_CHALLENGE_TTL_SECS: i64 = 5 * 60;
+// how long a passed second factor counts for the wallet operations
+pub const MFA_FRESHNESS_SECS: i64 = 5 * 60;
+// wrong codes in a row before the second factor gets locked for MFA_LOCKOUT_SECS
+pub const MAX_MFA_ATTEMPTS: i32 = 5;
+pub const MFA_LOCKOUT_SECS: i64 = 15 * 60;
+
+type HmacSha1 = Hmac<Sha1>;
+
+// -- TOTP -- //
+
+fn totp_code(secret: &[u8], step: i64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(secret).unwrap(); // panic impossible, hmac takes any key length
+    mac.update(&(step as u64).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // dynamic truncation of rfc 4226
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+// the time step the code was generated for, the steps up to `last_used_step` are refused
+// so a code can't be replayed while it's still in its window
+fn match_totp_step(mfa: &QUserMfa, code: &str) -> Option<i64> {
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|res| res.is_ascii_digit()) {
+        return None;
+    }
+    let submitted: u32 = code.parse().ok()?;
+    let current_step = Utc::now().timestamp() / TOTP_STEP_SECS;
+
+    (-TOTP_SKEW_STEPS..=TOTP_SKEW_STEPS)
+        .map(|drift| current_step + drift)
+        .filter(|step| mfa.last_used_step.map_or(true, |last| *step > last))
+        .find(|step| totp_code(&mfa.totp_secret, *step) == submitted)
+}
+
+pub fn build_provisioning_uri(_username: &str, totp_secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        Uri::percent_encode(MFA_ISSUER),
+        Uri::percent_encode(_username),
+        BASE32_NOPAD.encode(totp_secret),
+        Uri::percent_encode(MFA_ISSUER),
+        TOTP_DIGITS,
+        TOTP_STEP_SECS
+    )
+}
+
+// -- recovery codes -- //
+
+// 80 random bits shown as "abcd-efgh-ijkl-mnop"
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; 10];
+    OsRng.fill_bytes(&mut bytes);
+    let encoded = BASE32_NOPAD.encode(&bytes).to_lowercase();
+    format!(
+        "{}-{}-{}-{}",
+        &encoded[0..4],
+        &encoded[4..8],
+        &encoded[8..12],
+        &encoded[12..16]
+    )
+}
+
+// the dashes, spaces and the case don't matter when the code is typed back
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|res| res.is_ascii_alphanumeric())
+        .map(|res| res.to_ascii_lowercase())
+        .collect()
+}
+
+// the old codes stop working, the new ones are returned in plain text this one time
+fn replace_recovery_codes(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<Vec<String>, ChatuzaError> {
+    if let Err(e) =
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(_user_id)))
+            .execute(_conn)
+    {
+        return Err(ChatuzaError::Database(e));
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| new_recovery_code())
+        .collect();
+    let rows: Vec<MfaRecoveryCodes> = recovery_codes
+        .iter()
+        .map(|res| MfaRecoveryCodes {
+            user_id: _user_id,
+            code_hash: hash_token_id(&normalize_recovery_code(res)),
+        })
+        .collect();
+    match diesel::insert_into(mfa_recovery_codes::table)
+        .values(&rows)
+        .execute(_conn)
+    {
+        Ok(_) => Ok(recovery_codes),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- MFA GETTER functions -- //
+
+pub fn get_user_mfa(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<Option<QUserMfa>, ChatuzaError> {
+    match user_mfa::table
+        .filter(user_mfa::user_id.eq(_user_id))
+        .select(QUserMfa::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// a pending enrollment doesn't count, errors are passed up so a failing lookup never skips 2fa
+pub fn is_mfa_enabled(_conn: &mut PgConnection, _user_id: i32) -> Result<bool, ChatuzaError> {
+    match get_user_mfa(_conn, _user_id)? {
+        Some(res) => Ok(res.enabled_at.is_some()),
+        None => Ok(false),
+    }
+}
+
+fn get_enabled_user_mfa(_conn: &mut PgConnection, _user_id: i32) -> Result<QUserMfa, ChatuzaError> {
+    match get_user_mfa(_conn, _user_id)? {
+        Some(res) if res.enabled_at.is_some() => Ok(res),
+        _ => Err(ChatuzaError::NotFound(
+            "two-factor authentication of user id",
+            _user_id.to_string(),
+        )),
+    }
+}
+
+// what the wallet operations ask for: 2fa enabled and passed by this session recently
+pub fn ensure_fresh_mfa(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    _session_id: i32,
+) -> Result<(), ChatuzaError> {
+    if !is_mfa_enabled(_conn, _user_id)? {
+        return Err(ChatuzaError::PermissionDenied(
+            "do this before enabling two-factor authentication".to_owned(),
+        ));
+    }
+    let session = get_active_session(_conn, _session_id)?;
+    match session.mfa_verified_at {
+        Some(res) if res > Utc::now().naive_utc() - Duration::seconds(MFA_FRESHNESS_SECS) => Ok(()),
+        _ => Err(ChatuzaError::PermissionDenied(
+            "do this without a fresh second factor, verify one on /mfa/verify first".to_owned(),
+        )),
+    }
+}
+
+// -- MFA SETTER functions -- //
+
+fn clear_failed_mfa_attempts(_conn: &mut PgConnection, _user_id: i32) -> Result<(), ChatuzaError> {
+    match diesel::update(user_mfa::table.filter(user_mfa::user_id.eq(_user_id)))
+        .set((
+            user_mfa::failed_attempts.eq(0),
+            user_mfa::locked_until.eq(None::<NaiveDateTime>),
+        ))
+        .execute(_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// counts the wrong code and returns the error to answer with
+fn record_failed_mfa_attempt(_conn: &mut PgConnection, _user_id: i32) -> ChatuzaError {
+    let failed_attempts: i32;
+    match diesel::update(user_mfa::table.filter(user_mfa::user_id.eq(_user_id)))
+        .set(user_mfa::failed_attempts.eq(user_mfa::failed_attempts + 1))
+        .returning(user_mfa::failed_attempts)
+        .get_result(_conn)
+    {
+        Ok(res) => failed_attempts = res,
+        Err(e) => return ChatuzaError::Database(e),
+    }
+    if failed_attempts < MAX_MFA_ATTEMPTS {
+        return ChatuzaError::Unauthorized(format!(
+            "invalid second factor code, {} attempts left",
+            MAX_MFA_ATTEMPTS - failed_attempts
+        ));
+    }
+
+    let locked_until = Utc::now().naive_utc() + Duration::seconds(MFA_LOCKOUT_SECS);
+    if let Err(e) = diesel::update(user_mfa::table.filter(user_mfa::user_id.eq(_user_id)))
+        .set((
+            user_mfa::failed_attempts.eq(0),
+            user_mfa::locked_until.eq(locked_until),
+        ))
+        .execute(_conn)
+    {
+        return ChatuzaError::Database(e);
+    }
+    ChatuzaError::TooManyRequests(format!(
+        "too many wrong second factor codes, try again in {} minutes",
+        MFA_LOCKOUT_SECS / 60
+    ))
+}
+
+// takes a totp code, or a recovery code once 2fa is enabled. the failed attempts have to stick
+// so this must not run inside a transaction that gets rolled back on the error
+fn verify_second_factor(
+    _conn: &mut PgConnection,
+    mfa: &QUserMfa,
+    code: &str,
+) -> Result<(), ChatuzaError> {
+    let now = Utc::now().naive_utc();
+    if let Some(locked_until) = mfa.locked_until {
+        if locked_until > now {
+            return Err(ChatuzaError::TooManyRequests(
+                "too many wrong second factor codes, try again later".to_owned(),
+            ));
+        }
+    }
+
+    let code = code.trim();
+    if let Some(step) = match_totp_step(mfa, code) {
+        // the step is claimed conditionally so two requests can't spend the same code
+        match diesel::update(
+            user_mfa::table.filter(
+                user_mfa::user_id.eq(mfa.user_id).and(
+                    user_mfa::last_used_step
+                        .is_null()
+                        .or(user_mfa::last_used_step.lt(step)),
+                ),
+            ),
+        )
+        .set((
+            user_mfa::last_used_step.eq(step),
+            user_mfa::failed_attempts.eq(0),
+            user_mfa::locked_until.eq(None::<NaiveDateTime>),
+        ))
+        .execute(_conn)
+        {
+            Ok(1) => return Ok(()),
+            Ok(_) => return Err(record_failed_mfa_attempt(_conn, mfa.user_id)),
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+    }
+
+    if mfa.enabled_at.is_some() {
+        match diesel::update(
+            mfa_recovery_codes::table.filter(
+                mfa_recovery_codes::user_id
+                    .eq(mfa.user_id)
+                    .and(
+                        mfa_recovery_codes::code_hash
+                            .eq(hash_token_id(&normalize_recovery_code(code))),
+                    )
+                    .and(mfa_recovery_codes::used_at.is_null()),
+            ),
+        )
+        .set(mfa_recovery_codes::used_at.eq(now))
+        .execute(_conn)
+        {
+            Ok(1) => return clear_failed_mfa_attempts(_conn, mfa.user_id),
+            Ok(_) => {}
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+    }
+    Err(record_failed_mfa_attempt(_conn, mfa.user_id))
+}
+
+fn mark_session_mfa_verified(
+    _conn: &mut PgConnection,
+    _session_id: i32,
+) -> Result<(), ChatuzaError> {
+    match diesel::update(sessions::table.filter(sessions::session_id.eq(_session_id)))
+        .set(sessions::mfa_verified_at.eq(Utc::now().naive_utc()))
+        .execute(_conn)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// generates the secret, 2fa stays off until a code from it is confirmed. asking again before
+// confirming replaces the pending secret
+pub fn start_mfa_enrollment(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<MfaEnrollmentResponse, ChatuzaError> {
+    let user = get_user_with_user_id(_conn, _user_id)?;
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        // locked so a concurrent confirmation can't get its secret swapped
+        match user_mfa::table
+            .filter(user_mfa::user_id.eq(_user_id))
+            .select(QUserMfa::as_select())
+            .for_update()
+            .first(_conn)
+            .optional()
+        {
+            Ok(Some(res)) if res.enabled_at.is_some() => {
+                return Err(ChatuzaError::AlreadyExists(format!(
+                    "two-factor authentication is already enabled for user id {}",
+                    _user_id
+                )))
+            }
+            Ok(_) => {}
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        let mut totp_secret = vec![0u8; TOTP_SECRET_LEN];
+        OsRng.fill_bytes(&mut totp_secret);
+        if let Err(e) = diesel::insert_into(user_mfa::table)
+            .values(&UserMfa {
+                user_id: _user_id,
+                totp_secret: totp_secret.clone(),
+            })
+            .on_conflict(user_mfa::user_id)
+            .do_update()
+            .set((
+                user_mfa::totp_secret.eq(&totp_secret),
+                user_mfa::created_at.eq(Utc::now().naive_utc()),
+                user_mfa::last_used_step.eq(None::<i64>),
+            ))
+            .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+
+        Ok(MfaEnrollmentResponse {
+            secret: BASE32_NOPAD.encode(&totp_secret),
+            provisioning_uri: build_provisioning_uri(&user.username, &totp_secret),
+        })
+    })
+}
+
+// turns 2fa on with the first code of the app, the session that confirmed counts as verified
+pub fn confirm_mfa_enrollment(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    _session_id: i32,
+    code: &str,
+) -> Result<MfaRecoveryCodesResponse, ChatuzaError> {
+    let mfa = match get_user_mfa(_conn, _user_id)? {
+        Some(res) if res.enabled_at.is_none() => res,
+        Some(_) => {
+            return Err(ChatuzaError::AlreadyExists(format!(
+                "two-factor authentication is already enabled for user id {}",
+                _user_id
+            )))
+        }
+        None => {
+            return Err(ChatuzaError::NotFound(
+                "pending two-factor enrollment of user id",
+                _user_id.to_string(),
+            ))
+        }
+    };
+    verify_second_factor(_conn, &mfa, code)?;
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        if let Err(e) = diesel::update(
+            user_mfa::table.filter(
+                user_mfa::user_id
+                    .eq(_user_id)
+                    .and(user_mfa::enabled_at.is_null()),
+            ),
+        )
+        .set(user_mfa::enabled_at.eq(Utc::now().naive_utc()))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        let recovery_codes = replace_recovery_codes(_conn, _user_id)?;
+        mark_session_mfa_verified(_conn, _session_id)?;
+        Ok(MfaRecoveryCodesResponse { recovery_codes })
+    })
+}
+
+// step up for the current session, see `ensure_fresh_mfa`
+pub fn verify_session_mfa(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    _session_id: i32,
+    code: &str,
+) -> Result<bool, ChatuzaError> {
+    let mfa = get_enabled_user_mfa(_conn, _user_id)?;
+    verify_second_factor(_conn, &mfa, code)?;
+    mark_session_mfa_verified(_conn, _session_id)?;
+    Ok(true)
+}
+
+pub fn regenerate_recovery_codes(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<MfaRecoveryCodesResponse, ChatuzaError> {
+    get_enabled_user_mfa(_conn, _user_id)?;
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let recovery_codes = replace_recovery_codes(_conn, _user_id)?;
+        Ok(MfaRecoveryCodesResponse { recovery_codes })
+    })
+}
+
+// needs a current code, a stolen access token alone can't turn 2fa off
+pub fn disable_mfa(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    code: &str,
+) -> Result<bool, ChatuzaError> {
+    let mfa = get_enabled_user_mfa(_conn, _user_id)?;
+    verify_second_factor(_conn, &mfa, code)?;
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        if let Err(e) = diesel::delete(
+            mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(_user_id)),
+        )
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        if let Err(e) = diesel::delete(
+            mfa_login_challenges::table.filter(mfa_login_challenges::user_id.eq(_user_id)),
+        )
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        match diesel::delete(user_mfa::table.filter(user_mfa::user_id.eq(_user_id))).execute(_conn)
+        {
+            Ok(_) => Ok(true),
+            Err(e) => Err(ChatuzaError::Database(e)),
+        }
+    })
+}
+
+// -- login challenge -- //
+
+// what /login answers with after a right password when 2fa is on
+pub fn start_mfa_login_challenge(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<LoginResponse, ChatuzaError> {
+    let challenge_token = new_token_id();
+    let expires_at = Utc::now().naive_utc() + Duration::seconds(MFA_CHALLENGE_TTL_SECS);
+
+    match diesel::insert_into(mfa_login_challenges::table)
+        .values(&MfaLoginChallenges {
+            user_id: _user_id,
+            challenge_hash: hash_token_id(&challenge_token),
+            expires_at,
+        })
+        .execute(_conn)
+    {
+        Ok(_) => Ok(LoginResponse {
+            mfa_required: true,
+            tokens: None,
+            mfa_challenge_token: Some(challenge_token),
+            mfa_challenge_expires_at: Some(expires_at.and_utc().timestamp()),
+        }),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// a wrong code keeps the challenge usable until it expires, the lockout caps the guesses
+pub fn complete_mfa_login(
+    _conn: &mut PgConnection,
+    challenge_token: &str,
+    code: &str,
+) -> Result<AuthTokensResponse, ChatuzaError> {
+    let invalid_challenge =
+        || ChatuzaError::Unauthorized("invalid or expired two-factor challenge".to_owned());
+    let now = Utc::now().naive_utc();
+
+    let challenge: QMfaLoginChallenges;
+    match mfa_login_challenges::table
+        .filter(
+            mfa_login_challenges::challenge_hash
+                .eq(hash_token_id(challenge_token.trim()))
+                .and(mfa_login_challenges::consumed_at.is_null())
+                .and(mfa_login_challenges::expires_at.gt(now)),
+        )
+        .select(QMfaLoginChallenges::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => challenge = res,
+        Ok(None) => return Err(invalid_challenge()),
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+    let mfa = match get_user_mfa(_conn, challenge.user_id)? {
+        Some(res) if res.enabled_at.is_some() => res,
+        _ => return Err(invalid_challenge()),
+    };
+    verify_second_factor(_conn, &mfa, code)?;
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        // consumed conditionally, the same challenge can't open two sessions
+        match diesel::update(
+            mfa_login_challenges::table.filter(
+                mfa_login_challenges::challenge_id
+                    .eq(challenge.challenge_id)
+                    .and(mfa_login_challenges::consumed_at.is_null()),
+            ),
+        )
+        .set(mfa_login_challenges::consumed_at.eq(now))
+        .execute(_conn)
+        {
+            Ok(1) => {}
+            Ok(_) => return Err(invalid_challenge()),
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+        create_session(_conn, challenge.user_id, true)
+    })
+}
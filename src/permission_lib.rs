@@ -0,0 +1,116 @@
+use crate::db_models::{ChatRoomParticipants, ParticipantRole};
+use crate::errors::ChatuzaError;
+use crate::is_group_chat;
+use crate::schema::chat_room_participants;
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+// everything a participant may try to do on a group chat room
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupAction {
+    EditInfo,
+    DeleteRoom,
+    AddMembers,
+    RemoveMembers,
+    ChangeRoles,
+    SendMessages,
+}
+
+impl GroupAction {
+    // completes the "not allowed to .." message of `ChatuzaError::PermissionDenied`
+    fn describe(&self, _chat_room_id: i32) -> String {
+        match *self {
+            GroupAction::EditInfo => format!("edit the info of chat room id {}", _chat_room_id),
+            GroupAction::DeleteRoom => format!("delete the chat room id {}", _chat_room_id),
+            GroupAction::AddMembers => format!("add members to the chat room id {}", _chat_room_id),
+            GroupAction::RemoveMembers => {
+                format!("remove members from the chat room id {}", _chat_room_id)
+            }
+            GroupAction::ChangeRoles => {
+                format!("change the roles of chat room id {}", _chat_room_id)
+            }
+            GroupAction::SendMessages => format!("post in chat room id {}", _chat_room_id),
+        }
+    }
+}
+
+// the permission matrix, acting on another participant also needs a higher rank than theirs
+//
+//                owner  admin  moderator  member  read_only
+// EditInfo         x      x
+// DeleteRoom       x
+// AddMembers       x      x        x
+// RemoveMembers    x      x        x
+// ChangeRoles      x      x
+// SendMessages     x      x        x         x
+pub fn role_can(role: ParticipantRole, action: GroupAction) -> bool {
+    match action {
+        GroupAction::DeleteRoom => role == ParticipantRole::Owner,
+        GroupAction::EditInfo | GroupAction::ChangeRoles => {
+            role.rank() >= ParticipantRole::Admin.rank()
+        }
+        GroupAction::AddMembers | GroupAction::RemoveMembers => {
+            role.rank() >= ParticipantRole::Moderator.rank()
+        }
+        GroupAction::SendMessages => role.rank() >= ParticipantRole::Member.rank(),
+    }
+}
+
+pub fn get_participant_role(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    _user_id: i32,
+) -> Result<ParticipantRole, ChatuzaError> {
+    let participant_rows: Vec<ChatRoomParticipants> = chat_room_participants::table
+        .filter(
+            chat_room_participants::chat_room_id
+                .eq(_chat_room_id)
+                .and(chat_room_participants::user_id.eq(_user_id)),
+        )
+        .select(ChatRoomParticipants::as_select())
+        .load(_conn)
+        .unwrap_or(vec![]);
+
+    if participant_rows.len() != 1 {
+        return Err(ChatuzaError::NotFound(
+            "participant",
+            format!("user id {} in chat room id {}", _user_id, _chat_room_id),
+        ));
+    }
+    Ok(participant_rows[0].role)
+}
+
+// returns the role of the user so the callers can compare it with the one they act on
+pub fn ensure_group_permission(
+    _conn: &mut PgConnection,
+    _chat_room_id: i32,
+    _user_id: i32,
+    action: GroupAction,
+) -> Result<ParticipantRole, ChatuzaError> {
+    if !is_group_chat(_conn, _chat_room_id) {
+        return Err(ChatuzaError::NotFound(
+            "group chat room id",
+            _chat_room_id.to_string(),
+        ));
+    }
+
+    let role;
+    match get_participant_role(_conn, _chat_room_id, _user_id) {
+        Ok(res) => role = res,
+        Err(ChatuzaError::NotFound(..)) => {
+            return Err(ChatuzaError::PermissionDenied(
+                action.describe(_chat_room_id),
+            ))
+        }
+        Err(e) => return Err(e),
+    }
+
+    if role_can(role, action) {
+        Ok(role)
+    } else {
+        Err(ChatuzaError::PermissionDenied(
+            action.describe(_chat_room_id),
+        ))
+    }
+}
@@ -0,0 +1,310 @@
+use crate::api_models::PublicUserProfile;
+use crate::contact_lib::has_blocked;
+use crate::db_models::{
+    ProfileVisibility, QUserPrivacySettings, QUsers, RoomKind, UserPrivacySettings, UserProfiles,
+};
+use crate::errors::ChatuzaError;
+use crate::schema::{chat_rooms, user_privacy_settings, user_profiles, users};
+use crate::{get_user_with_email, get_user_with_user_id, get_user_with_username, is_valid_user};
+use chrono::Utc;
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// -- Privacy settings SETTER functions -- //
+
+// the row is created on the first update, until then the defaults apply
+pub fn update_user_privacy_settings(
+    _conn: &mut PgConnection,
+    new_settings: &UserPrivacySettings,
+) -> Result<QUserPrivacySettings, ChatuzaError> {
+    if !is_valid_user(_conn, new_settings.user_id) {
+        return Err(ChatuzaError::NotFound(
+            "user id",
+            new_settings.user_id.to_string(),
+        ));
+    }
+
+    match diesel::insert_into(user_privacy_settings::table)
+        .values(new_settings)
+        .on_conflict(user_privacy_settings::user_id)
+        .do_update()
+        .set((
+            user_privacy_settings::discoverable.eq(new_settings.discoverable),
+            user_privacy_settings::email_visibility.eq(new_settings.email_visibility),
+            user_privacy_settings::phone_visibility.eq(new_settings.phone_visibility),
+            user_privacy_settings::bio_visibility.eq(new_settings.bio_visibility),
+            user_privacy_settings::avatar_visibility.eq(new_settings.avatar_visibility),
+            user_privacy_settings::updated_at.eq(Utc::now().naive_utc()),
+        ))
+        .returning(QUserPrivacySettings::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- Privacy settings GETTER functions -- //
+
+// what the users who never touched their settings get, must match the column defaults
+fn default_privacy_settings(_user_id: i32) -> QUserPrivacySettings {
+    QUserPrivacySettings {
+        user_id: _user_id,
+        discoverable: true,
+        updated_at: Utc::now().naive_utc(),
+        email_visibility: ProfileVisibility::Nobody,
+        phone_visibility: ProfileVisibility::Nobody,
+        bio_visibility: ProfileVisibility::Everyone,
+        avatar_visibility: ProfileVisibility::Everyone,
+    }
+}
+
+pub fn get_user_privacy_settings(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<QUserPrivacySettings, ChatuzaError> {
+    match user_privacy_settings::table
+        .filter(user_privacy_settings::user_id.eq(_user_id))
+        .select(QUserPrivacySettings::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => {
+            if !is_valid_user(_conn, _user_id) {
+                return Err(ChatuzaError::NotFound("user id", _user_id.to_string()));
+            }
+            Ok(default_privacy_settings(_user_id))
+        }
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the contacts of a user are the users sharing a p2p chat room with them
+pub fn get_contact_user_ids(_conn: &mut PgConnection, _user_id: i32) -> HashSet<i32> {
+    let pairs: Vec<(Option<i32>, Option<i32>)> = chat_rooms::table
+        .filter(chat_rooms::room_kind.eq(RoomKind::Direct))
+        .filter(
+            chat_rooms::direct_user_low
+                .eq(_user_id)
+                .or(chat_rooms::direct_user_high.eq(_user_id)),
+        )
+        .select((chat_rooms::direct_user_low, chat_rooms::direct_user_high))
+        .load(_conn)
+        .unwrap_or(vec![]);
+
+    pairs
+        .into_iter()
+        .filter_map(|(low, high)| if low == Some(_user_id) { high } else { low })
+        .collect()
+}
+
+// the owner always sees their own fields, anonymous viewers only get the public ones
+pub fn is_field_visible(
+    visibility: ProfileVisibility,
+    owner_user_id: i32,
+    viewer_user_id: Option<i32>,
+    is_contact: bool,
+) -> bool {
+    if viewer_user_id == Some(owner_user_id) {
+        return true;
+    }
+    match visibility {
+        ProfileVisibility::Everyone => true,
+        ProfileVisibility::Contacts => viewer_user_id.is_some() && is_contact,
+        ProfileVisibility::Nobody => false,
+    }
+}
+
+pub fn build_public_profile(
+    user: &QUsers,
+    profile: Option<&UserProfiles>,
+    settings: &QUserPrivacySettings,
+    viewer_user_id: Option<i32>,
+    is_contact: bool,
+) -> PublicUserProfile {
+    let visible =
+        |visibility| is_field_visible(visibility, user.user_id, viewer_user_id, is_contact);
+
+    PublicUserProfile {
+        user_id: user.user_id,
+        username: user.username.clone(),
+        email: if visible(settings.email_visibility) {
+            Some(user.email.clone())
+        } else {
+            None
+        },
+        phone_number: if visible(settings.phone_visibility) {
+            Some(user.phone_number.clone())
+        } else {
+            None
+        },
+        bio: match profile {
+            Some(res) if visible(settings.bio_visibility) => res.bio.clone(),
+            _ => None,
+        },
+        profile_picture: match profile {
+            Some(res) if visible(settings.avatar_visibility) => res.profile_picture.clone(),
+            _ => None,
+        },
+    }
+}
+
+fn get_user_profile_row(_conn: &mut PgConnection, _user_id: i32) -> Option<UserProfiles> {
+    user_profiles::table
+        .filter(user_profiles::user_id.eq(_user_id))
+        .select(UserProfiles::as_select())
+        .first(_conn)
+        .optional()
+        .unwrap_or(None)
+}
+
+fn get_public_profile(
+    _conn: &mut PgConnection,
+    user: &QUsers,
+    viewer_user_id: Option<i32>,
+) -> Result<PublicUserProfile, ChatuzaError> {
+    let settings = get_user_privacy_settings(_conn, user.user_id)?;
+    let profile = get_user_profile_row(_conn, user.user_id);
+    let is_contact = match viewer_user_id {
+        Some(viewer_id) => get_contact_user_ids(_conn, user.user_id).contains(&viewer_id),
+        None => false,
+    };
+    Ok(build_public_profile(
+        user,
+        profile.as_ref(),
+        &settings,
+        viewer_user_id,
+        is_contact,
+    ))
+}
+
+// a user who blocked the viewer is answered like a missing one
+fn ensure_not_blocked_by(
+    _conn: &mut PgConnection,
+    user: &QUsers,
+    viewer_user_id: Option<i32>,
+    key: (&'static str, String),
+) -> Result<(), ChatuzaError> {
+    if let Some(viewer_id) = viewer_user_id {
+        if has_blocked(_conn, user.user_id, viewer_id) {
+            return Err(ChatuzaError::NotFound(key.0, key.1));
+        }
+    }
+    Ok(())
+}
+
+pub fn get_public_profile_with_username(
+    _conn: &mut PgConnection,
+    _username: &str,
+    viewer_user_id: Option<i32>,
+) -> Result<PublicUserProfile, ChatuzaError> {
+    let user = get_user_with_username(_conn, _username)?;
+    ensure_not_blocked_by(
+        _conn,
+        &user,
+        viewer_user_id,
+        ("username", _username.to_string()),
+    )?;
+    get_public_profile(_conn, &user, viewer_user_id)
+}
+
+pub fn get_public_profile_with_user_id(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    viewer_user_id: Option<i32>,
+) -> Result<PublicUserProfile, ChatuzaError> {
+    let user = get_user_with_user_id(_conn, _user_id)?;
+    ensure_not_blocked_by(
+        _conn,
+        &user,
+        viewer_user_id,
+        ("user id", _user_id.to_string()),
+    )?;
+    get_public_profile(_conn, &user, viewer_user_id)
+}
+
+// looking up an email only works when the email is visible to the viewer,
+// otherwise it would tell who is behind a hidden address
+pub fn get_public_profile_with_email(
+    _conn: &mut PgConnection,
+    _email: &str,
+    viewer_user_id: Option<i32>,
+) -> Result<PublicUserProfile, ChatuzaError> {
+    let user = get_user_with_email(_conn, _email)?;
+    ensure_not_blocked_by(_conn, &user, viewer_user_id, ("email", _email.to_string()))?;
+
+    let public_profile = get_public_profile(_conn, &user, viewer_user_id)?;
+    if public_profile.email.is_none() {
+        return Err(ChatuzaError::NotFound("email", _email.to_string()));
+    }
+    Ok(public_profile)
+}
+
+// the profiles of many users at once, in the order of the ids, the missing ones are skipped
+pub fn get_public_profiles(
+    _conn: &mut PgConnection,
+    user_ids: &[i32],
+    viewer_user_id: Option<i32>,
+) -> Result<Vec<PublicUserProfile>, ChatuzaError> {
+    if user_ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let user_rows: Vec<QUsers>;
+    match users::table
+        .filter(users::user_id.eq_any(user_ids))
+        .select(QUsers::as_select())
+        .load(_conn)
+    {
+        Ok(res) => user_rows = res,
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+    let mut profiles: HashMap<i32, UserProfiles> = HashMap::new();
+    match user_profiles::table
+        .filter(user_profiles::user_id.eq_any(user_ids))
+        .select(UserProfiles::as_select())
+        .load(_conn)
+    {
+        Ok(res) => profiles.extend(res.into_iter().map(|res| (res.user_id, res))),
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+    let mut settings: HashMap<i32, QUserPrivacySettings> = HashMap::new();
+    match user_privacy_settings::table
+        .filter(user_privacy_settings::user_id.eq_any(user_ids))
+        .select(QUserPrivacySettings::as_select())
+        .load(_conn)
+    {
+        Ok(res) => settings.extend(res.into_iter().map(|res| (res.user_id, res))),
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+    for _user_id in user_ids {
+        settings
+            .entry(*_user_id)
+            .or_insert_with(|| default_privacy_settings(*_user_id));
+    }
+    let contact_ids = match viewer_user_id {
+        Some(viewer_id) => get_contact_user_ids(_conn, viewer_id),
+        None => HashSet::new(),
+    };
+
+    let mut users_by_id: HashMap<i32, QUsers> = user_rows
+        .into_iter()
+        .map(|res| (res.user_id, res))
+        .collect();
+    Ok(user_ids
+        .iter()
+        .filter_map(|_user_id| {
+            let user = users_by_id.remove(_user_id)?;
+            Some(build_public_profile(
+                &user,
+                profiles.get(_user_id),
+                &settings[_user_id],
+                viewer_user_id,
+                contact_ids.contains(_user_id),
+            ))
+        })
+        .collect())
+}
@@ -0,0 +1,675 @@
+use crate::auth_lib::peek_access_token_user_id;
+use crate::db_models::{RateLimitBuckets, RateLimitQuotas};
+use crate::db_pool::PgPool;
+use crate::errors::ChatuzaError;
+use crate::schema::{rate_limit_buckets, rate_limit_quotas};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use log::warn;
+use rocket::config::{ConfigError, Value};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Method, Status};
+use rocket::{Config, Data, Request, Response, State};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+// where the fairing sends the rejected requests, no route is mounted there so no handler runs
+// and `on_response` writes the 429 over whatever came back
+const RATE_LIMITED_PATH: &str = "/rate-limited";
+// the in-memory buckets that refilled completely are dropped past this many keys
+const MAX_MEMORY_BUCKETS: usize = 100_000;
+
+// what a bucket is counted per, the user falls back to the ip for anonymous requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    Ip,
+    User,
+}
+
+// `capacity` requests per `per_secs`, refilled continuously, a burst can use the whole capacity
+#[derive(Debug, Clone)]
+pub struct BucketPolicy {
+    // an exact path like "/api/login", or a prefix ending with '*', "*" alone matches everything
+    pub route: String,
+    pub scope: LimitScope,
+    pub capacity: u32,
+    pub per_secs: u64,
+}
+
+impl BucketPolicy {
+    pub fn matches(&self, path: &str) -> bool {
+        match self.route.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == self.route,
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity as f64 / self.per_secs as f64
+    }
+}
+
+// uses per utc day, checked by the handlers themselves since the wallet is in the form body
+#[derive(Debug, Clone)]
+pub struct DailyQuotaPolicy {
+    pub route: String,
+    pub per_user: Option<u32>,
+    pub per_wallet: Option<u32>,
+}
+
+// read from the `rate_limit_backend`, `rate_limits` and `daily_quotas` extras of Rocket.toml
+#[derive(Debug, Clone)]
+pub struct RateLimitSettings {
+    // "memory" or "postgres", the latter shares the limits between several servers
+    pub backend: String,
+    // counts the ip buckets per X-Real-IP instead of the peer address. only for a server behind
+    // a reverse proxy that sets it, anyone can send the header
+    pub trust_real_ip_header: bool,
+    pub buckets: Vec<BucketPolicy>,
+    pub daily_quotas: Vec<DailyQuotaPolicy>,
+}
+
+fn invalid_entry(key: &str, value: &Value, reason: &str) -> ChatuzaError {
+    ChatuzaError::Internal(format!("invalid {} entry {} : {}", key, value, reason))
+}
+
+fn config_entries<'a>(config: &'a Config, key: &str) -> Result<&'a [Value], ChatuzaError> {
+    match config.get_slice(key) {
+        Ok(res) => Ok(res.as_slice()),
+        Err(ConfigError::Missing(_)) => Ok(&[]),
+        Err(e) => Err(ChatuzaError::Internal(format!(
+            "invalid {} setting due to \n {}",
+            key, e
+        ))),
+    }
+}
+
+fn entry_route(key: &str, value: &Value) -> Result<String, ChatuzaError> {
+    match value.get("route").and_then(|res| res.as_str()) {
+        Some(res) if !res.is_empty() => Ok(res.to_owned()),
+        _ => Err(invalid_entry(key, value, "the route is missing")),
+    }
+}
+
+// a missing key is None, anything but a positive integer is an error
+fn entry_count(key: &str, value: &Value, field: &str) -> Result<Option<u64>, ChatuzaError> {
+    match value.get(field) {
+        None => Ok(None),
+        Some(res) => match res.as_integer() {
+            Some(count) if count > 0 => Ok(Some(count as u64)),
+            _ => Err(invalid_entry(
+                key,
+                value,
+                &format!("{} must be a positive integer", field),
+            )),
+        },
+    }
+}
+
+impl RateLimitSettings {
+    // unlike the other settings a typo here is refused at startup, a silently missing limit
+    // is worse than a server that doesn't boot
+    pub fn from_rocket_config(config: &Config) -> Result<Self, ChatuzaError> {
+        let backend = config
+            .get_str("rate_limit_backend")
+            .unwrap_or("memory")
+            .to_owned();
+        let trust_real_ip_header = match config.get_bool("rate_limit_trust_real_ip_header") {
+            Ok(res) => res,
+            Err(ConfigError::Missing(_)) => false,
+            Err(e) => {
+                return Err(ChatuzaError::Internal(format!(
+                    "invalid rate_limit_trust_real_ip_header setting due to \n {}",
+                    e
+                )))
+            }
+        };
+
+        let mut buckets = vec![];
+        for value in config_entries(config, "rate_limits")? {
+            let scope = match value.get("key").and_then(|res| res.as_str()) {
+                None | Some("ip") => LimitScope::Ip,
+                Some("user") => LimitScope::User,
+                Some(_) => {
+                    return Err(invalid_entry(
+                        "rate_limits",
+                        value,
+                        "key must be \"ip\" or \"user\"",
+                    ))
+                }
+            };
+            let capacity = entry_count("rate_limits", value, "capacity")?;
+            let per_secs = entry_count("rate_limits", value, "per_secs")?;
+            match (capacity, per_secs) {
+                (Some(capacity), Some(per_secs)) if capacity <= u32::MAX as u64 => {
+                    buckets.push(BucketPolicy {
+                        route: entry_route("rate_limits", value)?,
+                        scope,
+                        capacity: capacity as u32,
+                        per_secs,
+                    })
+                }
+                _ => {
+                    return Err(invalid_entry(
+                        "rate_limits",
+                        value,
+                        "capacity and per_secs are required",
+                    ))
+                }
+            }
+        }
+
+        let mut daily_quotas = vec![];
+        for value in config_entries(config, "daily_quotas")? {
+            let per_user = entry_count("daily_quotas", value, "per_user")?;
+            let per_wallet = entry_count("daily_quotas", value, "per_wallet")?;
+            if per_user.is_none() && per_wallet.is_none() {
+                return Err(invalid_entry(
+                    "daily_quotas",
+                    value,
+                    "per_user or per_wallet is required",
+                ));
+            }
+            daily_quotas.push(DailyQuotaPolicy {
+                route: entry_route("daily_quotas", value)?,
+                per_user: per_user.map(|res| res.min(u32::MAX as u64) as u32),
+                per_wallet: per_wallet.map(|res| res.min(u32::MAX as u64) as u32),
+            });
+        }
+
+        Ok(RateLimitSettings {
+            backend,
+            trust_real_ip_header,
+            buckets,
+            daily_quotas,
+        })
+    }
+}
+
+// -- token buckets -- //
+
+// the tokens after the refill since `updated_at`, capped at the capacity
+fn refill(
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    now: NaiveDateTime,
+    policy: &BucketPolicy,
+) -> f64 {
+    let elapsed_secs = (now - updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+    (tokens + elapsed_secs * policy.refill_per_sec()).min(policy.capacity as f64)
+}
+
+// takes a token, or tells how many seconds until there is one
+fn take_from_bucket(tokens: f64, policy: &BucketPolicy) -> (f64, Option<u64>) {
+    if tokens >= 1.0 {
+        return (tokens - 1.0, None);
+    }
+    let retry_after_secs = ((1.0 - tokens) / policy.refill_per_sec()).ceil().max(1.0);
+    (tokens, Some(retry_after_secs as u64))
+}
+
+fn seconds_until_next_day(now: NaiveDateTime) -> u64 {
+    let next_day = (now.date() + Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap(); // panic impossible
+    (next_day - now).num_seconds().max(1) as u64
+}
+
+// where the buckets and the daily counters live
+pub trait RateLimitStore: Send + Sync {
+    // Some(seconds until the next token) when the bucket is empty
+    fn take_token(
+        &self,
+        bucket_key: &str,
+        policy: &BucketPolicy,
+    ) -> Result<Option<u64>, ChatuzaError>;
+    // counts one use against every (key, limit) of the day, or against none of them when one
+    // is already used up, returns whether it was counted
+    fn take_daily_quotas(
+        &self,
+        quotas: &[(String, u32)],
+        day: NaiveDate,
+    ) -> Result<bool, ChatuzaError>;
+    // takes back one use of the day from every key, for a use that didn't go through
+    fn give_back_daily_quotas(
+        &self,
+        quota_keys: &[String],
+        day: NaiveDate,
+    ) -> Result<(), ChatuzaError>;
+}
+
+struct MemoryBucket {
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    // from then on the bucket is full again and can be forgotten
+    full_at: NaiveDateTime,
+}
+
+// per process, the limits reset when the server restarts
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, MemoryBucket>>,
+    quotas: Mutex<HashMap<String, (NaiveDate, u32)>>,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn take_token(
+        &self,
+        bucket_key: &str,
+        policy: &BucketPolicy,
+    ) -> Result<Option<u64>, ChatuzaError> {
+        let now = Utc::now().naive_utc();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            buckets.retain(|_, res| res.full_at > now);
+        }
+
+        let tokens = match buckets.get(bucket_key) {
+            Some(res) => refill(res.tokens, res.updated_at, now, policy),
+            None => policy.capacity as f64,
+        };
+        let (tokens, retry_after_secs) = take_from_bucket(tokens, policy);
+        let missing_millis = (policy.capacity as f64 - tokens) / policy.refill_per_sec() * 1000.0;
+        buckets.insert(
+            bucket_key.to_owned(),
+            MemoryBucket {
+                tokens,
+                updated_at: now,
+                full_at: now + Duration::milliseconds(missing_millis.ceil() as i64),
+            },
+        );
+        Ok(retry_after_secs)
+    }
+
+    fn take_daily_quotas(
+        &self,
+        quotas: &[(String, u32)],
+        day: NaiveDate,
+    ) -> Result<bool, ChatuzaError> {
+        let mut counters = self.quotas.lock().unwrap();
+        // yesterday's counters are dead weight
+        counters.retain(|_, (counter_day, _)| *counter_day >= day);
+
+        let used_up = quotas
+            .iter()
+            .any(|(quota_key, limit)| match counters.get(quota_key) {
+                Some((_, used)) => used >= limit,
+                None => false,
+            });
+        if used_up {
+            return Ok(false);
+        }
+        for (quota_key, _) in quotas {
+            counters.entry(quota_key.clone()).or_insert((day, 0)).1 += 1;
+        }
+        Ok(true)
+    }
+
+    fn give_back_daily_quotas(
+        &self,
+        quota_keys: &[String],
+        day: NaiveDate,
+    ) -> Result<(), ChatuzaError> {
+        let mut counters = self.quotas.lock().unwrap();
+        for quota_key in quota_keys {
+            if let Some((counter_day, used)) = counters.get_mut(quota_key) {
+                if *counter_day == day && *used > 0 {
+                    *used -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// shared between the servers using the same database, the rows are locked while updated
+pub struct PgRateLimitStore {
+    pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgRateLimitStore { pool }
+    }
+}
+
+impl RateLimitStore for PgRateLimitStore {
+    fn take_token(
+        &self,
+        bucket_key: &str,
+        policy: &BucketPolicy,
+    ) -> Result<Option<u64>, ChatuzaError> {
+        let mut conn = match self.pool.get() {
+            Ok(res) => res,
+            Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+        };
+
+        conn.transaction::<_, ChatuzaError, _>(|_conn| {
+            let now = Utc::now().naive_utc();
+            let tokens = match rate_limit_buckets::table
+                .filter(rate_limit_buckets::bucket_key.eq(bucket_key))
+                .select(RateLimitBuckets::as_select())
+                .for_update()
+                .first(_conn)
+                .optional()
+            {
+                Ok(Some(res)) => refill(res.tokens, res.updated_at, now, policy),
+                Ok(None) => policy.capacity as f64,
+                Err(e) => return Err(ChatuzaError::Database(e)),
+            };
+            let (tokens, retry_after_secs) = take_from_bucket(tokens, policy);
+
+            match diesel::insert_into(rate_limit_buckets::table)
+                .values(&RateLimitBuckets {
+                    bucket_key: bucket_key.to_owned(),
+                    tokens,
+                    updated_at: now,
+                })
+                .on_conflict(rate_limit_buckets::bucket_key)
+                .do_update()
+                .set((
+                    rate_limit_buckets::tokens.eq(tokens),
+                    rate_limit_buckets::updated_at.eq(now),
+                ))
+                .execute(_conn)
+            {
+                Ok(_) => Ok(retry_after_secs),
+                Err(e) => Err(ChatuzaError::Database(e)),
+            }
+        })
+    }
+
+    fn take_daily_quotas(
+        &self,
+        quotas: &[(String, u32)],
+        day: NaiveDate,
+    ) -> Result<bool, ChatuzaError> {
+        let mut conn = match self.pool.get() {
+            Ok(res) => res,
+            Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+        };
+
+        conn.transaction::<_, ChatuzaError, _>(|_conn| {
+            for (quota_key, _) in quotas {
+                // the row has to exist to be locked
+                if let Err(e) = diesel::insert_into(rate_limit_quotas::table)
+                    .values(&RateLimitQuotas {
+                        quota_key: quota_key.clone(),
+                        quota_day: day,
+                        used: 0,
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(_conn)
+                {
+                    return Err(ChatuzaError::Database(e));
+                }
+            }
+
+            let quota_keys: Vec<String> = quotas
+                .iter()
+                .map(|(quota_key, _)| quota_key.clone())
+                .collect();
+            let rows: Vec<RateLimitQuotas>;
+            match rate_limit_quotas::table
+                .filter(rate_limit_quotas::quota_key.eq_any(quota_keys.clone()))
+                .filter(rate_limit_quotas::quota_day.eq(day))
+                .select(RateLimitQuotas::as_select())
+                .for_update()
+                .load(_conn)
+            {
+                Ok(res) => rows = res,
+                Err(e) => return Err(ChatuzaError::Database(e)),
+            }
+            let used_up = quotas.iter().any(|(quota_key, limit)| {
+                rows.iter()
+                    .any(|res| &res.quota_key == quota_key && res.used as i64 >= *limit as i64)
+            });
+            if used_up {
+                return Ok(false);
+            }
+
+            match diesel::update(
+                rate_limit_quotas::table
+                    .filter(rate_limit_quotas::quota_key.eq_any(quota_keys))
+                    .filter(rate_limit_quotas::quota_day.eq(day)),
+            )
+            .set(rate_limit_quotas::used.eq(rate_limit_quotas::used + 1))
+            .execute(_conn)
+            {
+                Ok(_) => Ok(true),
+                Err(e) => Err(ChatuzaError::Database(e)),
+            }
+        })
+    }
+
+    fn give_back_daily_quotas(
+        &self,
+        quota_keys: &[String],
+        day: NaiveDate,
+    ) -> Result<(), ChatuzaError> {
+        let mut conn = match self.pool.get() {
+            Ok(res) => res,
+            Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+        };
+
+        match diesel::update(
+            rate_limit_quotas::table
+                .filter(rate_limit_quotas::quota_key.eq_any(quota_keys))
+                .filter(rate_limit_quotas::quota_day.eq(day))
+                .filter(rate_limit_quotas::used.gt(0)),
+        )
+        .set(rate_limit_quotas::used.eq(rate_limit_quotas::used - 1))
+        .execute(&mut conn)
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ChatuzaError::Database(e)),
+        }
+    }
+}
+
+// -- limiter -- //
+
+// managed by rocket, used by `RateLimitFairing` and by the handlers with daily quotas
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    store: Box<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings, store: Box<dyn RateLimitStore>) -> Self {
+        RateLimiter { settings, store }
+    }
+
+    // takes a token from every bucket the path matches, Some(longest wait) if one was empty
+    pub fn check_request(
+        &self,
+        path: &str,
+        client_ip: Option<IpAddr>,
+        _user_id: Option<i32>,
+    ) -> Result<Option<u64>, ChatuzaError> {
+        let ip_key = match client_ip {
+            Some(res) => format!("ip:{}", res),
+            None => "ip:unknown".to_owned(),
+        };
+
+        let mut retry_after_secs: Option<u64> = None;
+        for policy in self.settings.buckets.iter().filter(|res| res.matches(path)) {
+            let scope_key = match (policy.scope, _user_id) {
+                (LimitScope::User, Some(res)) => format!("user:{}", res),
+                _ => ip_key.clone(),
+            };
+            // a prefix policy is one bucket for all the paths it covers
+            let bucket_key = format!("{}|{}", policy.route, scope_key);
+            if let Some(res) = self.store.take_token(&bucket_key, policy)? {
+                retry_after_secs = Some(retry_after_secs.unwrap_or(0).max(res));
+            }
+        }
+        Ok(retry_after_secs)
+    }
+
+    // the (key, limit) of every daily quota of `route` for the user and the wallet
+    fn daily_quotas(&self, route: &str, _user_id: i32, wallet_address: &str) -> Vec<(String, u32)> {
+        let policy = match self
+            .settings
+            .daily_quotas
+            .iter()
+            .find(|res| res.route == route)
+        {
+            Some(res) => res,
+            None => return vec![],
+        };
+
+        let mut quotas = vec![];
+        if let Some(limit) = policy.per_user {
+            quotas.push((format!("{}|user:{}", route, _user_id), limit));
+        }
+        if let Some(limit) = policy.per_wallet {
+            quotas.push((format!("{}|wallet:{}", route, wallet_address.trim()), limit));
+        }
+        quotas
+    }
+
+    // counts one use of `route` for the user and the wallet, the routes without a
+    // `daily_quotas` entry are never refused
+    pub fn consume_daily_quota(
+        &self,
+        route: &str,
+        _user_id: i32,
+        wallet_address: &str,
+    ) -> Result<(), ChatuzaError> {
+        let quotas = self.daily_quotas(route, _user_id, wallet_address);
+        if quotas.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now().naive_utc();
+        if self.store.take_daily_quotas(&quotas, now.date())? {
+            return Ok(());
+        }
+        Err(ChatuzaError::RateLimited(
+            format!(
+                "the daily quota of {} is used up for this user or wallet",
+                route
+            ),
+            seconds_until_next_day(now),
+        ))
+    }
+
+    // gives back the use `consume_daily_quota` counted when the operation failed after all
+    pub fn refund_daily_quota(&self, route: &str, _user_id: i32, wallet_address: &str) {
+        let quota_keys: Vec<String> = self
+            .daily_quotas(route, _user_id, wallet_address)
+            .into_iter()
+            .map(|(quota_key, _)| quota_key)
+            .collect();
+        if quota_keys.is_empty() {
+            return;
+        }
+        // the use stays counted if this fails, the quota resets the next day anyway
+        if let Err(e) = self
+            .store
+            .give_back_daily_quotas(&quota_keys, Utc::now().naive_utc().date())
+        {
+            warn!(
+                "couldn't refund the daily quota of {} due to \n {}",
+                route, e
+            );
+        }
+    }
+}
+
+pub fn init_rate_limiter(
+    settings: &RateLimitSettings,
+    pool: &PgPool,
+) -> Result<RateLimiter, ChatuzaError> {
+    let store: Box<dyn RateLimitStore> = match settings.backend.as_str() {
+        "memory" => Box::new(MemoryRateLimitStore::new()),
+        "postgres" => Box::new(PgRateLimitStore::new(pool.clone())),
+        other => {
+            return Err(ChatuzaError::Internal(format!(
+                "unknown rate limit backend {}",
+                other
+            )))
+        }
+    };
+    Ok(RateLimiter::new(settings.clone(), store))
+}
+
+// -- fairing -- //
+
+// set by `on_request` for the rejected requests, holds the Retry-After seconds
+struct RateLimitRejection(Option<u64>);
+
+// a fairing can't answer a request by itself in rocket 0.4, so the rejected ones are rerouted
+// to a path without a handler and the response gets replaced
+pub struct RateLimitFairing;
+
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let retry_after_secs = {
+            let limiter = match request.guard::<State<RateLimiter>>() {
+                rocket::Outcome::Success(res) => res,
+                _ => return,
+            };
+            let _user_id = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|res| res.strip_prefix("Bearer "))
+                .and_then(|res| peek_access_token_user_id(res.trim()));
+
+            // `client_ip` prefers the X-Real-IP header over the peer address
+            let client_ip = if limiter.settings.trust_real_ip_header {
+                request.client_ip()
+            } else {
+                request.remote().map(|res| res.ip())
+            };
+
+            match limiter.check_request(request.uri().path(), client_ip, _user_id) {
+                Ok(res) => res,
+                Err(e) => {
+                    // a broken limiter store shouldn't take the whole api down with it
+                    warn!("rate limit check failed due to \n {}", e);
+                    None
+                }
+            }
+        };
+
+        if let Some(res) = retry_after_secs {
+            request.local_cache(|| RateLimitRejection(Some(res)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap()); // panic impossible
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let retry_after_secs = match request.local_cache(|| RateLimitRejection(None)) {
+            RateLimitRejection(Some(res)) => *res,
+            RateLimitRejection(None) => return,
+        };
+
+        let envelope =
+            ChatuzaError::RateLimited("too many requests".to_owned(), retry_after_secs).envelope();
+        response.set_status(Status::TooManyRequests);
+        response.set_header(ContentType::JSON);
+        response.set_raw_header("Retry-After", retry_after_secs.to_string());
+        response.set_sized_body(Cursor::new(
+            serde_json::to_string(&envelope).unwrap_or_default(),
+        ));
+    }
+}
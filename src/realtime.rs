@@ -0,0 +1,593 @@
+use crate::auth_lib::{access_token_expires_at, authenticate_access_token};
+use crate::contact_lib::get_blocked_user_ids;
+use crate::db_models::{ParticipantRole, QChatRooms, QMessages, QRoomTokenTransfers};
+use crate::db_pool::PgPool;
+use crate::{
+    get_user_group_chat_rooms_by_user_id, get_user_p2p_chat_rooms_by_user_id, PgConnection,
+};
+use chrono::Utc;
+use log::error;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+// every event is pushed as `{ "event": "<name>", "data": { .. } }`
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum RoomEvent<'a> {
+    MessageCreated(&'a QMessages),
+    MessageEdited(&'a QMessages),
+    MessageDeleted(&'a QMessages),
+    RoomCreated(&'a QChatRooms),
+    RoomUpdated(&'a QChatRooms),
+    RoomDeleted {
+        chat_room_id: i32,
+    },
+    ParticipantAdded {
+        chat_room_id: i32,
+        user_id: i32,
+    },
+    ParticipantRemoved {
+        chat_room_id: i32,
+        user_id: i32,
+    },
+    ParticipantRoleChanged {
+        chat_room_id: i32,
+        user_id: i32,
+        role: ParticipantRole,
+    },
+    // a confirmed spl token transfer between two participants
+    TokenTransferred(&'a QRoomTokenTransfers),
+}
+
+// anything a serialized event can be pushed into, returns false once the receiver is gone
+pub trait EventSink: Send {
+    fn deliver(&self, payload: &str) -> bool;
+    // called when the hub drops the connection because its session ended
+    fn close(&self) {}
+}
+
+impl EventSink for ws::Sender {
+    fn deliver(&self, payload: &str) -> bool {
+        self.send(payload).is_ok()
+    }
+
+    fn close(&self) {
+        let _ = self.close_with_reason(ws::CloseCode::Policy, "session ended");
+    }
+}
+
+// lets an in-process client subscribe to the hub without a socket
+impl EventSink for mpsc::Sender<String> {
+    fn deliver(&self, payload: &str) -> bool {
+        self.send(payload.to_owned()).is_ok()
+    }
+}
+
+struct Connection {
+    user_id: i32,
+    session_id: i32,
+    // unix timestamp the access token of the handshake expired at, the connection is dropped then
+    expires_at: i64,
+    sink: Box<dyn EventSink>,
+}
+
+#[derive(Default)]
+struct HubState {
+    next_connection_id: u64,
+    connections: HashMap<u64, Connection>,
+    user_connections: HashMap<i32, HashSet<u64>>,
+    room_subscribers: HashMap<i32, HashSet<u64>>,
+    // user id -> the user ids they blocked, their messages aren't delivered to them
+    blocked_senders: HashMap<i32, HashSet<i32>>,
+}
+
+#[derive(Clone, Default)]
+pub struct RealtimeHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+impl RealtimeHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(
+        &self,
+        _user_id: i32,
+        _session_id: i32,
+        expires_at: i64,
+        chat_room_ids: Vec<i32>,
+        sink: Box<dyn EventSink>,
+    ) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_connection_id += 1;
+        let connection_id = state.next_connection_id;
+
+        state.connections.insert(
+            connection_id,
+            Connection {
+                user_id: _user_id,
+                session_id: _session_id,
+                expires_at,
+                sink,
+            },
+        );
+        state
+            .user_connections
+            .entry(_user_id)
+            .or_default()
+            .insert(connection_id);
+        for _chat_room_id in chat_room_ids {
+            state
+                .room_subscribers
+                .entry(_chat_room_id)
+                .or_default()
+                .insert(connection_id);
+        }
+        connection_id
+    }
+
+    pub fn disconnect(&self, connection_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        Self::remove_connection(&mut state, connection_id);
+    }
+
+    // closes the connections opened with the session, e.g. on logout
+    pub fn disconnect_session(&self, _session_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        let connection_ids: Vec<u64> = state
+            .connections
+            .iter()
+            .filter(|(_, res)| res.session_id == _session_id)
+            .map(|(connection_id, _)| *connection_id)
+            .collect();
+        Self::close_connections(&mut state, connection_ids);
+    }
+
+    // closes every connection of the user, e.g. once all their sessions are revoked
+    pub fn disconnect_user(&self, _user_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        let connection_ids: Vec<u64> = match state.user_connections.get(&_user_id) {
+            Some(res) => res.iter().copied().collect(),
+            None => return,
+        };
+        Self::close_connections(&mut state, connection_ids);
+    }
+
+    pub fn subscribe_user(&self, _user_id: i32, _chat_room_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        let connection_ids: Vec<u64> = match state.user_connections.get(&_user_id) {
+            Some(res) => res.iter().copied().collect(),
+            None => return,
+        };
+        state
+            .room_subscribers
+            .entry(_chat_room_id)
+            .or_default()
+            .extend(connection_ids);
+    }
+
+    pub fn unsubscribe_user(&self, _user_id: i32, _chat_room_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        let connection_ids: Vec<u64> = match state.user_connections.get(&_user_id) {
+            Some(res) => res.iter().copied().collect(),
+            None => return,
+        };
+        if let Some(subscribers) = state.room_subscribers.get_mut(&_chat_room_id) {
+            for connection_id in connection_ids {
+                subscribers.remove(&connection_id);
+            }
+        }
+    }
+
+    pub fn set_blocked_users(&self, _user_id: i32, blocked_user_ids: Vec<i32>) {
+        let mut state = self.state.lock().unwrap();
+        if blocked_user_ids.is_empty() {
+            state.blocked_senders.remove(&_user_id);
+        } else {
+            state
+                .blocked_senders
+                .insert(_user_id, blocked_user_ids.into_iter().collect());
+        }
+    }
+
+    pub fn block_user(&self, _blocker_id: i32, _blocked_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .blocked_senders
+            .entry(_blocker_id)
+            .or_default()
+            .insert(_blocked_id);
+    }
+
+    pub fn unblock_user(&self, _blocker_id: i32, _blocked_id: i32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(blocked) = state.blocked_senders.get_mut(&_blocker_id) {
+            blocked.remove(&_blocked_id);
+            if blocked.is_empty() {
+                state.blocked_senders.remove(&_blocker_id);
+            }
+        }
+    }
+
+    // subscribes the connected participants of a freshly created room and announces it to them
+    pub fn open_room(&self, chat_room: &QChatRooms, participant_ids: Vec<i32>) {
+        for participant_id in participant_ids {
+            self.subscribe_user(participant_id, chat_room.chat_room_id);
+        }
+        self.publish(chat_room.chat_room_id, &RoomEvent::RoomCreated(chat_room));
+    }
+
+    // fans the event out to every connection subscribed to the room, membership events
+    // update the subscriptions so joining users get the event and leaving users don't get later ones
+    pub fn publish(&self, _chat_room_id: i32, event: &RoomEvent) {
+        if let RoomEvent::ParticipantAdded { user_id, .. } = event {
+            self.subscribe_user(*user_id, _chat_room_id);
+        }
+
+        let payload = match serde_json::to_string(event) {
+            Ok(res) => res,
+            Err(_) => return,
+        };
+        let sender_id = match event {
+            RoomEvent::MessageCreated(message)
+            | RoomEvent::MessageEdited(message)
+            | RoomEvent::MessageDeleted(message) => message.sender_id,
+            RoomEvent::TokenTransferred(transfer) => Some(transfer.sender_id),
+            _ => None,
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            let subscribers: Vec<u64> = match state.room_subscribers.get(&_chat_room_id) {
+                Some(res) => res.iter().copied().collect(),
+                None => vec![],
+            };
+            let now = Utc::now().timestamp();
+            let mut dead_connections = vec![];
+            let mut expired_connections = vec![];
+            for connection_id in subscribers {
+                if let Some(connection) = state.connections.get(&connection_id) {
+                    if connection.expires_at <= now {
+                        expired_connections.push(connection_id);
+                        continue;
+                    }
+                    let blocked = match (sender_id, state.blocked_senders.get(&connection.user_id))
+                    {
+                        (Some(sender), Some(blocked_ids)) => blocked_ids.contains(&sender),
+                        _ => false,
+                    };
+                    if blocked {
+                        continue;
+                    }
+                    if !connection.sink.deliver(&payload) {
+                        dead_connections.push(connection_id);
+                    }
+                }
+            }
+            for connection_id in dead_connections {
+                Self::remove_connection(&mut state, connection_id);
+            }
+            Self::close_connections(&mut state, expired_connections);
+        }
+
+        match event {
+            RoomEvent::ParticipantRemoved { user_id, .. } => {
+                self.unsubscribe_user(*user_id, _chat_room_id)
+            }
+            RoomEvent::RoomDeleted { .. } => {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .room_subscribers
+                    .remove(&_chat_room_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn close_connections(state: &mut HubState, connection_ids: Vec<u64>) {
+        for connection_id in connection_ids {
+            if let Some(connection) = state.connections.get(&connection_id) {
+                connection.sink.close();
+            }
+            Self::remove_connection(state, connection_id);
+        }
+    }
+
+    fn remove_connection(state: &mut HubState, connection_id: u64) {
+        if let Some(connection) = state.connections.remove(&connection_id) {
+            if let Some(user_connections) = state.user_connections.get_mut(&connection.user_id) {
+                user_connections.remove(&connection_id);
+                if user_connections.is_empty() {
+                    state.user_connections.remove(&connection.user_id);
+                }
+            }
+        }
+        state.room_subscribers.retain(|_, subscribers| {
+            subscribers.remove(&connection_id);
+            !subscribers.is_empty()
+        });
+    }
+}
+
+pub fn get_user_chat_room_ids(_conn: &mut PgConnection, _user_id: i32) -> Vec<i32> {
+    let mut chat_room_ids: Vec<i32> = get_user_p2p_chat_rooms_by_user_id(_conn, _user_id)
+        .unwrap_or(vec![])
+        .iter()
+        .map(|res| res.chat_room_id)
+        .collect();
+    chat_room_ids.extend(
+        get_user_group_chat_rooms_by_user_id(_conn, _user_id)
+            .unwrap_or(vec![])
+            .iter()
+            .map(|res| res.chat_room_id),
+    );
+    chat_room_ids
+}
+
+// -- WebSocket gateway -- //
+
+// clients connect to `ws://<ws_address>/?access_token=<access token>`
+fn access_token_from_resource(resource: &str) -> Option<String> {
+    let (_, query) = resource.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|res| res.strip_prefix("access_token="))
+        .map(|res| res.to_owned())
+}
+
+struct GatewayHandler {
+    out: ws::Sender,
+    hub: RealtimeHub,
+    pool: PgPool,
+    connection_id: Option<u64>,
+}
+
+impl ws::Handler for GatewayHandler {
+    fn on_open(&mut self, shake: ws::Handshake) -> ws::Result<()> {
+        let access_token = match access_token_from_resource(shake.request.resource()) {
+            Some(res) => res,
+            None => {
+                return self
+                    .out
+                    .close_with_reason(ws::CloseCode::Policy, "missing access token")
+            }
+        };
+
+        let mut conn = match self.pool.get() {
+            Ok(res) => res,
+            Err(_) => {
+                return self
+                    .out
+                    .close_with_reason(ws::CloseCode::Again, "database unavailable")
+            }
+        };
+        match authenticate_access_token(&mut conn, &access_token) {
+            Ok((user, session)) => {
+                // the token just passed the same check, so it has an expiry
+                let expires_at = access_token_expires_at(&access_token).unwrap_or(0);
+                let chat_room_ids = get_user_chat_room_ids(&mut conn, user.user_id);
+                self.hub
+                    .set_blocked_users(user.user_id, get_blocked_user_ids(&mut conn, user.user_id));
+                self.connection_id = Some(self.hub.connect(
+                    user.user_id,
+                    session.session_id,
+                    expires_at,
+                    chat_room_ids,
+                    Box::new(self.out.clone()),
+                ));
+                Ok(())
+            }
+            Err(e) => self
+                .out
+                .close_with_reason(ws::CloseCode::Policy, format!("{}", e)),
+        }
+    }
+
+    // the gateway only pushes, everything a client wants to change goes through the http api
+    fn on_message(&mut self, _msg: ws::Message) -> ws::Result<()> {
+        Ok(())
+    }
+
+    fn on_close(&mut self, _code: ws::CloseCode, _reason: &str) {
+        if let Some(connection_id) = self.connection_id.take() {
+            self.hub.disconnect(connection_id);
+        }
+    }
+}
+
+pub fn spawn_websocket_gateway(
+    address: String,
+    hub: RealtimeHub,
+    pool: PgPool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = ws::listen(address.as_str(), |out| GatewayHandler {
+            out,
+            hub: hub.clone(),
+            pool: pool.clone(),
+            connection_id: None,
+        }) {
+            error!("websocket gateway on {} stopped due to \n {}", address, e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+
+    fn connect_session(
+        hub: &RealtimeHub,
+        _user_id: i32,
+        _session_id: i32,
+        expires_at: i64,
+        chat_room_ids: Vec<i32>,
+    ) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        hub.connect(
+            _user_id,
+            _session_id,
+            expires_at,
+            chat_room_ids,
+            Box::new(sender),
+        );
+        receiver
+    }
+
+    // one session per user, with a token good for the next hour
+    fn connect_client(
+        hub: &RealtimeHub,
+        _user_id: i32,
+        chat_room_ids: Vec<i32>,
+    ) -> Receiver<String> {
+        connect_session(
+            hub,
+            _user_id,
+            _user_id,
+            Utc::now().timestamp() + 3600,
+            chat_room_ids,
+        )
+    }
+
+    fn is_closed(receiver: &Receiver<String>) -> bool {
+        matches!(receiver.try_recv(), Err(mpsc::TryRecvError::Disconnected))
+    }
+
+    fn received_events(receiver: &Receiver<String>) -> Vec<serde_json::Value> {
+        receiver
+            .try_iter()
+            .map(|res| serde_json::from_str(&res).unwrap())
+            .collect()
+    }
+
+    fn message_from(_chat_room_id: i32, _sender_id: i32) -> QMessages {
+        QMessages {
+            message_id: 1,
+            chat_room_id: _chat_room_id,
+            sender_id: Some(_sender_id),
+            payload: b"hi".to_vec(),
+            created_at: Utc::now().naive_utc(),
+            parent_message_id: None,
+            edited_at: None,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn publish_reaches_only_the_subscribers_of_the_room() {
+        let hub = RealtimeHub::new();
+        let first = connect_client(&hub, 1, vec![10]);
+        let second = connect_client(&hub, 2, vec![10, 20]);
+        let outsider = connect_client(&hub, 3, vec![20]);
+
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 1)));
+
+        let events = received_events(&first);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "message_created");
+        assert_eq!(events[0]["data"]["chat_room_id"], 10);
+        assert_eq!(received_events(&second).len(), 1);
+        assert!(received_events(&outsider).is_empty());
+    }
+
+    #[test]
+    fn membership_events_update_the_subscriptions() {
+        let hub = RealtimeHub::new();
+        let member = connect_client(&hub, 1, vec![10]);
+        let joining = connect_client(&hub, 2, vec![]);
+
+        // the joining user gets the event announcing them and the later ones
+        hub.publish(
+            10,
+            &RoomEvent::ParticipantAdded {
+                chat_room_id: 10,
+                user_id: 2,
+            },
+        );
+        assert_eq!(received_events(&joining).len(), 1);
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 1)));
+        assert_eq!(received_events(&joining).len(), 1);
+
+        // the leaving user gets the event announcing it but nothing after
+        hub.publish(
+            10,
+            &RoomEvent::ParticipantRemoved {
+                chat_room_id: 10,
+                user_id: 2,
+            },
+        );
+        assert_eq!(received_events(&joining)[0]["event"], "participant_removed");
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 1)));
+        assert!(received_events(&joining).is_empty());
+
+        assert_eq!(received_events(&member).len(), 4);
+    }
+
+    #[test]
+    fn messages_of_blocked_senders_are_not_delivered() {
+        let hub = RealtimeHub::new();
+        let blocker = connect_client(&hub, 1, vec![10]);
+        let other = connect_client(&hub, 3, vec![10]);
+        hub.block_user(1, 2);
+
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+        assert!(received_events(&blocker).is_empty());
+        assert_eq!(received_events(&other).len(), 1);
+
+        hub.unblock_user(1, 2);
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+        assert_eq!(received_events(&blocker).len(), 1);
+    }
+
+    #[test]
+    fn closed_clients_are_dropped() {
+        let hub = RealtimeHub::new();
+        let gone = connect_client(&hub, 1, vec![10]);
+        let staying = connect_client(&hub, 2, vec![10]);
+        drop(gone);
+
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+        assert_eq!(received_events(&staying).len(), 1);
+
+        let state = hub.state.lock().unwrap();
+        assert!(!state.user_connections.contains_key(&1));
+        assert_eq!(state.connections.len(), 1);
+    }
+
+    #[test]
+    fn ended_sessions_stop_receiving_events() {
+        let hub = RealtimeHub::new();
+        let later = Utc::now().timestamp() + 3600;
+        let logged_out = connect_session(&hub, 1, 11, later, vec![10]);
+        let other_session = connect_session(&hub, 1, 12, later, vec![10]);
+        let other_user = connect_client(&hub, 2, vec![10]);
+
+        hub.disconnect_session(11);
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+        assert!(is_closed(&logged_out));
+        assert_eq!(received_events(&other_session).len(), 1);
+
+        // every session revoked at once, like a password reset does
+        hub.disconnect_user(1);
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+        assert!(is_closed(&other_session));
+        assert_eq!(received_events(&other_user).len(), 2);
+    }
+
+    #[test]
+    fn expired_tokens_stop_receiving_events() {
+        let hub = RealtimeHub::new();
+        let expired = connect_session(&hub, 1, 11, Utc::now().timestamp() - 1, vec![10]);
+        let live = connect_client(&hub, 2, vec![10]);
+
+        hub.publish(10, &RoomEvent::MessageCreated(&message_from(10, 2)));
+
+        assert!(is_closed(&expired));
+        assert_eq!(received_events(&live).len(), 1);
+        assert!(!hub.state.lock().unwrap().user_connections.contains_key(&1));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::auth_lib::{hash_password, hash_token_id, new_token_id, revoke_all_user_sessions};
+use crate::db_models::{PasswordResetTokens, QPasswordResetTokens, QUsers, VerificationChannel};
+use crate::delivery::{MessageSender, OutgoingMessage};
+use crate::errors::ChatuzaError;
+use crate::schema::{password_reset_tokens, users};
+use crate::verification_lib::normalize_phone_number;
+use crate::{get_user_with_email, get_user_with_username};
+use chrono::{Duration, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+pub const PASSWORD_RESET_TTL_SECS: i64 = 30 * 60;
+pub const PASSWORD_RESET_COOLDOWN_SECS: i64 = 60;
+pub const MAX_PASSWORD_RESETS_PER_HOUR: i64 = 3;
+
+// the identifier can be a username, an email or a phone number
+fn find_user_for_recovery(_conn: &mut PgConnection, identifier: &str) -> Option<QUsers> {
+    let identifier = identifier.trim();
+    if identifier.contains('@') {
+        return get_user_with_email(_conn, identifier).ok();
+    }
+    if identifier.starts_with('+') || identifier.starts_with("00") {
+        let phone_number = normalize_phone_number(identifier).ok()?;
+        return users::table
+            .filter(users::phone_number.eq(phone_number))
+            .select(QUsers::as_select())
+            .first(_conn)
+            .optional()
+            .unwrap_or(None);
+    }
+    get_user_with_username(_conn, identifier).ok()
+}
+
+// -- Password reset SETTER functions -- //
+
+// the answer is the same whether the account exists or not, whether the per account limit
+// was hit or not, so the endpoint can't be used to probe for accounts
+pub fn request_password_reset(
+    _conn: &mut PgConnection,
+    sender: &dyn MessageSender,
+    identifier: &str,
+    channel: VerificationChannel,
+) -> Result<(), ChatuzaError> {
+    let user = match find_user_for_recovery(_conn, identifier) {
+        Some(res) => res,
+        None => return Ok(()),
+    };
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let now = Utc::now().naive_utc();
+        let recent_requests: Vec<QPasswordResetTokens>;
+        match password_reset_tokens::table
+            .filter(
+                password_reset_tokens::user_id
+                    .eq(user.user_id)
+                    .and(password_reset_tokens::created_at.gt(now - Duration::hours(1))),
+            )
+            .order(password_reset_tokens::reset_token_id.desc())
+            .select(QPasswordResetTokens::as_select())
+            .load(_conn)
+        {
+            Ok(res) => recent_requests = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+        if recent_requests.len() as i64 >= MAX_PASSWORD_RESETS_PER_HOUR {
+            return Ok(());
+        }
+        if let Some(last_request) = recent_requests.first() {
+            if last_request.created_at > now - Duration::seconds(PASSWORD_RESET_COOLDOWN_SECS) {
+                return Ok(());
+            }
+        }
+
+        // a new token replaces the outstanding ones
+        if let Err(e) = diesel::update(
+            password_reset_tokens::table.filter(
+                password_reset_tokens::user_id
+                    .eq(user.user_id)
+                    .and(password_reset_tokens::used_at.is_null()),
+            ),
+        )
+        .set(password_reset_tokens::used_at.eq(now))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+
+        let reset_token = new_token_id();
+        if let Err(e) = diesel::insert_into(password_reset_tokens::table)
+            .values(&PasswordResetTokens {
+                user_id: user.user_id,
+                token_hash: hash_token_id(&reset_token),
+                channel,
+                expires_at: now + Duration::seconds(PASSWORD_RESET_TTL_SECS),
+            })
+            .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+
+        sender.send(&OutgoingMessage {
+            channel,
+            recipient: match channel {
+                VerificationChannel::Email => user.email.clone(),
+                VerificationChannel::Phone => user.phone_number.clone(),
+            },
+            subject: "Reset your Chatuza password".to_owned(),
+            body: format!(
+                "Use this token to reset the password of {}: {} \nIt expires in {} minutes, \
+                 ignore this message if you didn't ask for it.",
+                user.username,
+                reset_token,
+                PASSWORD_RESET_TTL_SECS / 60
+            ),
+        })
+    })
+}
+
+// sets the new password, burns the token and signs the user out everywhere, returns the user id
+// so the caller can close the user's open connections too
+pub fn reset_password(
+    _conn: &mut PgConnection,
+    reset_token: &str,
+    new_password: &str,
+) -> Result<i32, ChatuzaError> {
+    if new_password.is_empty() {
+        return Err(ChatuzaError::Validation(
+            "the new password can't be empty".to_owned(),
+        ));
+    }
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let now = Utc::now().naive_utc();
+        // claiming the token in the same statement that checks it keeps it single use under races
+        let claimed_token: QPasswordResetTokens;
+        match diesel::update(
+            password_reset_tokens::table.filter(
+                password_reset_tokens::token_hash
+                    .eq(hash_token_id(reset_token.trim()))
+                    .and(password_reset_tokens::used_at.is_null())
+                    .and(password_reset_tokens::expires_at.gt(now)),
+            ),
+        )
+        .set(password_reset_tokens::used_at.eq(now))
+        .returning(QPasswordResetTokens::as_returning())
+        .get_result(_conn)
+        .optional()
+        {
+            Ok(Some(res)) => claimed_token = res,
+            Ok(None) => {
+                return Err(ChatuzaError::Unauthorized(
+                    "invalid or expired password reset token".to_owned(),
+                ))
+            }
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        if let Err(e) =
+            diesel::update(users::table.filter(users::user_id.eq(claimed_token.user_id)))
+                .set(users::password.eq(hash_password(new_password)?))
+                .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        revoke_all_user_sessions(_conn, claimed_token.user_id)?;
+        Ok(claimed_token.user_id)
+    })
+}
@@ -0,0 +1,314 @@
+use crate::api_models::{RoomTokenTransferIN, RoomTokenTransferResponse};
+use crate::contact_lib::is_blocked_between;
+use crate::db_models::{QRoomTokenTransfers, RoomTokenTransfers, TokenTransferStatus};
+use crate::errors::ChatuzaError;
+use crate::is_user_in_chat_room;
+use crate::permission_lib::{get_participant_role, role_can, GroupAction};
+use crate::schema::room_token_transfers;
+use crate::wallet_config::SolanaContext;
+use crate::wallet_lib::{
+    ensure_token_program, get_mint_decimals, get_user_wallet_pubkey, parse_pubkey,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{NaiveDateTime, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+use solana_sdk::hash::Hash;
+use solana_sdk::message::Message;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use spl_token_2022::instruction::transfer_checked;
+use spl_token_2022::state::Account as TokenAccount;
+use std::str::FromStr;
+
+pub const MAX_ROOM_TOKEN_TRANSFERS_PAGE_SIZE: i64 = 100;
+
+// -- Room token transfers SETTER functions -- //
+
+// the unsigned transaction moving `amount_in` (in the mint's base units) from the sender's wallet
+// to the recipient's. the sender pays the fees and the recipient's token account if it's missing,
+// nothing is sent from here: the sender's wallet signs and submits it
+pub fn build_room_token_transfer(
+    _conn: &mut PgConnection,
+    solana: &SolanaContext,
+    _sender_id: i32,
+    transfer_info: &RoomTokenTransferIN,
+) -> Result<RoomTokenTransferResponse, ChatuzaError> {
+    let _chat_room_id = transfer_info.chat_room_id_in;
+    let _recipient_id = transfer_info.recipient_user_id_in;
+    let _amount = transfer_info.amount_in;
+    if _sender_id == _recipient_id {
+        return Err(ChatuzaError::Validation(
+            "tokens can't be sent to yourself".to_owned(),
+        ));
+    }
+    if _amount == 0 || _amount > i64::MAX as u64 {
+        return Err(ChatuzaError::Validation(format!(
+            "the amount must be between 1 and {}",
+            i64::MAX
+        )));
+    }
+
+    // both have to be in the room, and the sender allowed to post in it since the transfer
+    // shows up in the conversation
+    if !is_user_in_chat_room(_conn, _chat_room_id, _sender_id) {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "send tokens in chat room id {}",
+            _chat_room_id
+        )));
+    }
+    if !is_user_in_chat_room(_conn, _chat_room_id, _recipient_id) {
+        return Err(ChatuzaError::NotFound(
+            "participant",
+            format!(
+                "user id {} in chat room id {}",
+                _recipient_id, _chat_room_id
+            ),
+        ));
+    }
+    match get_participant_role(_conn, _chat_room_id, _sender_id) {
+        Ok(res) if role_can(res, GroupAction::SendMessages) => {}
+        Ok(_) => {
+            return Err(ChatuzaError::PermissionDenied(format!(
+                "send tokens in chat room id {}",
+                _chat_room_id
+            )))
+        }
+        Err(e) => return Err(e),
+    }
+    if is_blocked_between(_conn, _sender_id, _recipient_id) {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "send tokens to user id {}",
+            _recipient_id
+        )));
+    }
+
+    let token_mint_address =
+        parse_pubkey("token mint address", &transfer_info.token_mint_address_in)?;
+    let token_program_id = parse_pubkey("token program id", &transfer_info.token_program_id_in)?;
+    ensure_token_program(&token_program_id)?;
+    let sender_wallet = get_user_wallet_pubkey(_conn, _sender_id)?;
+    let recipient_wallet = get_user_wallet_pubkey(_conn, _recipient_id)?;
+    let decimals = get_mint_decimals(solana, &token_mint_address, &token_program_id)?;
+
+    // checked here so the wallet isn't handed a transaction bound to fail
+    let source = get_associated_token_address_with_program_id(
+        &sender_wallet,
+        &token_mint_address,
+        &token_program_id,
+    );
+    let balance = match solana.chain.get_account(&source) {
+        Ok(Some(res)) => match StateWithExtensions::<TokenAccount>::unpack(&res.data) {
+            Ok(state) => state.base.amount,
+            Err(_) => 0,
+        },
+        Ok(None) => 0,
+        Err(e) => return Err(ChatuzaError::from(e)),
+    };
+    if balance < _amount {
+        return Err(ChatuzaError::Validation(format!(
+            "the wallet {} holds {} of the {} to send",
+            sender_wallet, balance, _amount
+        )));
+    }
+
+    let destination = get_associated_token_address_with_program_id(
+        &recipient_wallet,
+        &token_mint_address,
+        &token_program_id,
+    );
+    let transfer = match transfer_checked(
+        &token_program_id,
+        &source,
+        &token_mint_address,
+        &destination,
+        &sender_wallet,
+        &[],
+        _amount,
+        decimals,
+    ) {
+        Ok(res) => res,
+        Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+    };
+    let lbh: Hash;
+    match solana.chain.get_latest_blockhash() {
+        Ok(res) => lbh = res,
+        Err(e) => return Err(ChatuzaError::from(e)),
+    }
+    let transaction = Transaction::new_unsigned(Message::new_with_blockhash(
+        &[
+            create_associated_token_account_idempotent(
+                &sender_wallet,
+                &recipient_wallet,
+                &token_mint_address,
+                &token_program_id,
+            ),
+            transfer,
+        ],
+        Some(&sender_wallet),
+        &lbh,
+    ));
+    let serialized_transaction = match bincode::serialize(&transaction) {
+        Ok(res) => res,
+        Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+    };
+
+    match diesel::insert_into(room_token_transfers::table)
+        .values(&RoomTokenTransfers {
+            chat_room_id: _chat_room_id,
+            sender_id: _sender_id,
+            recipient_id: _recipient_id,
+            sender_wallet_address: sender_wallet.to_string(),
+            recipient_wallet_address: recipient_wallet.to_string(),
+            token_mint_address: token_mint_address.to_string(),
+            token_program_id: token_program_id.to_string(),
+            amount: _amount as i64,
+            decimals: decimals as i16,
+            transaction_message: transaction.message_data(),
+        })
+        .returning(QRoomTokenTransfers::as_returning())
+        .get_result(_conn)
+    {
+        Ok(res) => Ok(RoomTokenTransferResponse {
+            transfer: res,
+            transaction: STANDARD.encode(serialized_transaction),
+        }),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// takes the signature the sender got from submitting the transaction. it has to sign the message
+// built for this transfer, so it can't be borrowed from another one, and the cluster decides
+// between confirmed and failed. the transfer stays pending while the cluster doesn't know it yet
+pub fn confirm_room_token_transfer(
+    _conn: &mut PgConnection,
+    solana: &SolanaContext,
+    _user_id: i32,
+    _transfer_id: i32,
+    _signature: &str,
+) -> Result<QRoomTokenTransfers, ChatuzaError> {
+    let transfer = get_room_token_transfer_by_id(_conn, _transfer_id)?;
+    if transfer.sender_id != _user_id {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "confirm the token transfer id {}",
+            _transfer_id
+        )));
+    }
+    if transfer.status != TokenTransferStatus::Pending {
+        return Err(ChatuzaError::AlreadyExists(format!(
+            "token transfer id {} is already {}",
+            _transfer_id,
+            transfer.status.as_str()
+        )));
+    }
+
+    let signature = match Signature::from_str(_signature.trim()) {
+        Ok(res) => res,
+        Err(_) => {
+            return Err(ChatuzaError::Validation(format!(
+                "{} isn't a valid transaction signature",
+                _signature
+            )))
+        }
+    };
+    let sender_wallet = parse_pubkey("wallet address", &transfer.sender_wallet_address)?;
+    if !signature.verify(sender_wallet.as_ref(), &transfer.transaction_message) {
+        return Err(ChatuzaError::Validation(format!(
+            "the signature isn't the one of token transfer id {}",
+            _transfer_id
+        )));
+    }
+
+    let status = match solana.chain.get_signature_status(&signature) {
+        Ok(Some(Ok(()))) => TokenTransferStatus::Confirmed,
+        Ok(Some(Err(_))) => TokenTransferStatus::Failed,
+        Ok(None) => return Ok(transfer),
+        Err(e) => return Err(ChatuzaError::from(e)),
+    };
+    let confirmed_at: Option<NaiveDateTime> = match status {
+        TokenTransferStatus::Confirmed => Some(Utc::now().naive_utc()),
+        _ => None,
+    };
+
+    // the pending filter lets only one confirmation through
+    match diesel::update(
+        room_token_transfers::table
+            .filter(room_token_transfers::transfer_id.eq(_transfer_id))
+            .filter(room_token_transfers::status.eq(TokenTransferStatus::Pending)),
+    )
+    .set((
+        room_token_transfers::status.eq(status),
+        room_token_transfers::signature.eq(signature.to_string()),
+        room_token_transfers::confirmed_at.eq(confirmed_at),
+    ))
+    .returning(QRoomTokenTransfers::as_returning())
+    .get_result(_conn)
+    .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(ChatuzaError::AlreadyExists(format!(
+            "token transfer id {} is already confirmed",
+            _transfer_id
+        ))),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// -- Room token transfers GETTER functions -- //
+
+pub fn get_room_token_transfer_by_id(
+    _conn: &mut PgConnection,
+    _transfer_id: i32,
+) -> Result<QRoomTokenTransfers, ChatuzaError> {
+    match room_token_transfers::table
+        .filter(room_token_transfers::transfer_id.eq(_transfer_id))
+        .select(QRoomTokenTransfers::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(ChatuzaError::NotFound(
+            "token transfer id",
+            _transfer_id.to_string(),
+        )),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// the confirmed transfers of the room, newest first
+pub fn get_room_token_transfers(
+    _conn: &mut PgConnection,
+    reader_user_id: i32,
+    _chat_room_id: i32,
+    before_transfer_id: Option<i32>,
+    _limit: i64,
+) -> Result<Vec<QRoomTokenTransfers>, ChatuzaError> {
+    if !is_user_in_chat_room(_conn, _chat_room_id, reader_user_id) {
+        return Err(ChatuzaError::PermissionDenied(format!(
+            "read the history of chat room id {}",
+            _chat_room_id
+        )));
+    }
+
+    let mut query = room_token_transfers::table
+        .filter(room_token_transfers::chat_room_id.eq(_chat_room_id))
+        .filter(room_token_transfers::status.eq(TokenTransferStatus::Confirmed))
+        .select(QRoomTokenTransfers::as_select())
+        .order(room_token_transfers::transfer_id.desc())
+        .limit(_limit.clamp(1, MAX_ROOM_TOKEN_TRANSFERS_PAGE_SIZE))
+        .into_boxed();
+
+    if let Some(before_id) = before_transfer_id {
+        query = query.filter(room_token_transfers::transfer_id.lt(before_id));
+    }
+
+    match query.load(_conn) {
+        Ok(res) => Ok(res),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
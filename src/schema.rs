@@ -1,15 +1,61 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "contact_request_status"))]
+    pub struct ContactRequestStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "funding_status"))]
+    pub struct FundingStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "join_request_status"))]
+    pub struct JoinRequestStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "owner_leave_policy"))]
+    pub struct OwnerLeavePolicy;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "participant_role"))]
+    pub struct ParticipantRole;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "profile_visibility"))]
+    pub struct ProfileVisibility;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "room_kind"))]
+    pub struct RoomKind;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "token_transfer_status"))]
+    pub struct TokenTransferStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "verification_channel"))]
+    pub struct VerificationChannel;
+}
+
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ParticipantRole;
+
     chat_room_participants (participant_id) {
         participant_id -> Int4,
         chat_room_id -> Int4,
         user_id -> Int4,
-        is_admin -> Bool,
+        role -> ParticipantRole,
+        joined_at -> Timestamp,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::RoomKind;
+    use super::sql_types::OwnerLeavePolicy;
+
     chat_rooms (chat_room_id) {
         chat_room_id -> Int4,
         #[max_length = 255]
@@ -17,6 +63,178 @@ diesel::table! {
         #[max_length = 255]
         room_description -> Varchar,
         chat_room_pubkey -> Bytea,
+        room_kind -> RoomKind,
+        direct_user_low -> Nullable<Int4>,
+        direct_user_high -> Nullable<Int4>,
+        owner_leave_policy -> OwnerLeavePolicy,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ContactRequestStatus;
+
+    contact_requests (contact_request_id) {
+        contact_request_id -> Int4,
+        requester_id -> Int4,
+        addressee_id -> Int4,
+        chat_room_pubkey -> Bytea,
+        status -> ContactRequestStatus,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        responded_at -> Nullable<Timestamp>,
+        chat_room_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    group_audit_log (audit_id) {
+        audit_id -> Int4,
+        chat_room_id -> Int4,
+        actor_id -> Nullable<Int4>,
+        #[max_length = 64]
+        action -> Varchar,
+        target_user_id -> Nullable<Int4>,
+        invite_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    group_invites (invite_id) {
+        invite_id -> Int4,
+        chat_room_id -> Int4,
+        created_by -> Nullable<Int4>,
+        token_hash -> Bytea,
+        expires_at -> Timestamp,
+        max_uses -> Nullable<Int4>,
+        use_count -> Int4,
+        requires_approval -> Bool,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JoinRequestStatus;
+
+    join_requests (join_request_id) {
+        join_request_id -> Int4,
+        chat_room_id -> Int4,
+        user_id -> Int4,
+        invite_id -> Nullable<Int4>,
+        status -> JoinRequestStatus,
+        created_at -> Timestamp,
+        decided_by -> Nullable<Int4>,
+        decided_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    messages (message_id) {
+        message_id -> Int4,
+        chat_room_id -> Int4,
+        sender_id -> Nullable<Int4>,
+        payload -> Bytea,
+        created_at -> Timestamp,
+        parent_message_id -> Nullable<Int4>,
+        edited_at -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mfa_login_challenges (challenge_id) {
+        challenge_id -> Int4,
+        user_id -> Int4,
+        challenge_hash -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        consumed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (recovery_code_id) {
+        recovery_code_id -> Int4,
+        user_id -> Int4,
+        code_hash -> Bytea,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::VerificationChannel;
+
+    password_reset_tokens (reset_token_id) {
+        reset_token_id -> Int4,
+        user_id -> Int4,
+        token_hash -> Bytea,
+        channel -> VerificationChannel,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    rate_limit_buckets (bucket_key) {
+        #[max_length = 255]
+        bucket_key -> Varchar,
+        tokens -> Float8,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rate_limit_quotas (quota_key, quota_day) {
+        #[max_length = 255]
+        quota_key -> Varchar,
+        quota_day -> Date,
+        used -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TokenTransferStatus;
+
+    room_token_transfers (transfer_id) {
+        transfer_id -> Int4,
+        chat_room_id -> Int4,
+        sender_id -> Int4,
+        recipient_id -> Int4,
+        #[max_length = 44]
+        sender_wallet_address -> Varchar,
+        #[max_length = 44]
+        recipient_wallet_address -> Varchar,
+        #[max_length = 44]
+        token_mint_address -> Varchar,
+        #[max_length = 44]
+        token_program_id -> Varchar,
+        amount -> Int8,
+        decimals -> Int2,
+        transaction_message -> Bytea,
+        status -> TokenTransferStatus,
+        #[max_length = 88]
+        signature -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        confirmed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    sessions (session_id) {
+        session_id -> Int4,
+        user_id -> Int4,
+        refresh_token_hash -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        mfa_verified_at -> Nullable<Timestamp>,
     }
 }
 
@@ -29,6 +247,71 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::FundingStatus;
+
+    token_account_fundings (funding_id) {
+        funding_id -> Int4,
+        user_id -> Int4,
+        #[max_length = 44]
+        wallet_address -> Varchar,
+        #[max_length = 44]
+        token_mint_address -> Varchar,
+        #[max_length = 44]
+        token_program_id -> Varchar,
+        #[max_length = 44]
+        token_account_address -> Varchar,
+        token_account_created -> Bool,
+        #[max_length = 88]
+        token_account_signature -> Nullable<Varchar>,
+        funding_status -> FundingStatus,
+        #[max_length = 88]
+        funding_signature -> Nullable<Varchar>,
+        #[max_length = 44]
+        funding_blockhash -> Nullable<Varchar>,
+        locked_until -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_blocks (block_id) {
+        block_id -> Int4,
+        blocker_id -> Int4,
+        blocked_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_mfa (user_id) {
+        user_id -> Int4,
+        totp_secret -> Bytea,
+        enabled_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        last_used_step -> Nullable<Int8>,
+        failed_attempts -> Int4,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ProfileVisibility;
+
+    user_privacy_settings (user_id) {
+        user_id -> Int4,
+        discoverable -> Bool,
+        updated_at -> Timestamp,
+        email_visibility -> ProfileVisibility,
+        phone_visibility -> ProfileVisibility,
+        bio_visibility -> ProfileVisibility,
+        avatar_visibility -> ProfileVisibility,
+    }
+}
+
 diesel::table! {
     user_profiles (user_profile_id) {
         user_profile_id -> Int4,
@@ -49,19 +332,72 @@ diesel::table! {
         email -> Varchar,
         #[max_length = 255]
         password -> Varchar,
-        #[max_length = 15]
+        #[max_length = 16]
         phone_number -> Varchar,
+        email_verified_at -> Nullable<Timestamp>,
+        phone_verified_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::VerificationChannel;
+
+    verification_codes (verification_id) {
+        verification_id -> Int4,
+        user_id -> Int4,
+        channel -> VerificationChannel,
+        #[max_length = 255]
+        target -> Varchar,
+        code_hash -> Bytea,
+        attempts -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        consumed_at -> Nullable<Timestamp>,
     }
 }
 
 diesel::joinable!(chat_room_participants -> chat_rooms (chat_room_id));
 diesel::joinable!(chat_room_participants -> users (user_id));
+diesel::joinable!(contact_requests -> chat_rooms (chat_room_id));
+diesel::joinable!(group_invites -> chat_rooms (chat_room_id));
+diesel::joinable!(group_invites -> users (created_by));
+diesel::joinable!(join_requests -> chat_rooms (chat_room_id));
+diesel::joinable!(join_requests -> group_invites (invite_id));
+diesel::joinable!(messages -> chat_rooms (chat_room_id));
+diesel::joinable!(messages -> users (sender_id));
+diesel::joinable!(mfa_login_challenges -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(room_token_transfers -> chat_rooms (chat_room_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(token_account_fundings -> users (user_id));
+diesel::joinable!(user_mfa -> users (user_id));
+diesel::joinable!(user_privacy_settings -> users (user_id));
 diesel::joinable!(user_profiles -> users (user_id));
+diesel::joinable!(verification_codes -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     chat_room_participants,
     chat_rooms,
+    contact_requests,
+    group_audit_log,
+    group_invites,
+    join_requests,
+    messages,
+    mfa_login_challenges,
+    mfa_recovery_codes,
+    password_reset_tokens,
+    rate_limit_buckets,
+    rate_limit_quotas,
+    room_token_transfers,
+    sessions,
     solana_wallets,
+    token_account_fundings,
+    user_blocks,
+    user_mfa,
+    user_privacy_settings,
     user_profiles,
     users,
+    verification_codes,
 );
@@ -0,0 +1,121 @@
+use crate::api_models::{Page, PageDirection, PageRequest, PublicUserProfile};
+use crate::db_models::ProfileVisibility;
+use crate::errors::ChatuzaError;
+use crate::privacy_lib::get_public_profiles;
+use crate::schema::{user_blocks, user_privacy_settings, user_profiles, users};
+pub use diesel;
+use diesel::dsl::not;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+use diesel::sql_types::{Nullable, Text};
+
+pub const MIN_SEARCH_QUERY_LEN: usize = 2;
+pub const MAX_SEARCH_QUERY_LEN: usize = 64;
+// pg_trgm scores go from 0 to 1, below this the matches are mostly noise
+pub const MIN_SEARCH_SIMILARITY: f32 = 0.3;
+
+// the pg_trgm functions, the extension is created by the migrations
+diesel::sql_function!(fn similarity(x: Text, y: Text) -> Float4);
+diesel::sql_function!(fn word_similarity(x: Text, y: Text) -> Float4);
+diesel::sql_function!(fn coalesce(x: Nullable<Text>, y: Text) -> Text);
+
+// the like wildcards typed by the user are matched literally
+fn escape_like_pattern(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// username prefix matches come first, then the fuzzy matches on username and bio by score.
+// the ranking doesn't give a stable id to resume from so the cursor is the offset of the page
+pub fn search_users(
+    _conn: &mut PgConnection,
+    searcher_user_id: i32,
+    _query: &str,
+    page: &PageRequest,
+) -> Result<Page<PublicUserProfile>, ChatuzaError> {
+    let search_query = _query.trim().to_lowercase();
+    if search_query.chars().count() < MIN_SEARCH_QUERY_LEN
+        || search_query.chars().count() > MAX_SEARCH_QUERY_LEN
+    {
+        return Err(ChatuzaError::Validation(format!(
+            "search queries must be {} to {} characters long",
+            MIN_SEARCH_QUERY_LEN, MAX_SEARCH_QUERY_LEN
+        )));
+    }
+    if page.direction == PageDirection::Backward {
+        return Err(ChatuzaError::Validation(
+            "the user search can only be paged forward".to_owned(),
+        ));
+    }
+    let offset = page.cursor.unwrap_or(0).max(0);
+    let prefix_pattern = format!("{}%", escape_like_pattern(&search_query));
+
+    let user_ids: Vec<i32>;
+    match users::table
+        .left_join(user_profiles::table)
+        .filter(users::user_id.ne(searcher_user_id))
+        .filter(
+            users::username
+                .ilike(prefix_pattern.clone())
+                .or(similarity(users::username, search_query.clone()).gt(MIN_SEARCH_SIMILARITY))
+                .or(word_similarity(
+                    search_query.clone(),
+                    coalesce(user_profiles::bio, "".to_owned()),
+                )
+                .gt(MIN_SEARCH_SIMILARITY)
+                // a bio that isn't public must not make its owner findable
+                .and(not(users::user_id.eq_any(
+                    user_privacy_settings::table
+                        .filter(
+                            user_privacy_settings::bio_visibility.ne(ProfileVisibility::Everyone),
+                        )
+                        .select(user_privacy_settings::user_id),
+                )))),
+        )
+        // the users who opted out of the search
+        .filter(not(users::user_id.eq_any(
+            user_privacy_settings::table
+                .filter(user_privacy_settings::discoverable.eq(false))
+                .select(user_privacy_settings::user_id),
+        )))
+        // the blocks work both ways
+        .filter(not(users::user_id.eq_any(
+            user_blocks::table
+                .filter(user_blocks::blocker_id.eq(searcher_user_id))
+                .select(user_blocks::blocked_id),
+        )))
+        .filter(not(users::user_id.eq_any(
+            user_blocks::table
+                .filter(user_blocks::blocked_id.eq(searcher_user_id))
+                .select(user_blocks::blocker_id),
+        )))
+        .order((
+            users::username.ilike(prefix_pattern).desc(),
+            (similarity(users::username, search_query.clone())
+                + word_similarity(
+                    search_query.clone(),
+                    coalesce(user_profiles::bio, "".to_owned()),
+                ))
+            .desc(),
+            users::user_id.asc(),
+        ))
+        .select(users::user_id)
+        .offset(offset as i64)
+        .limit(page.limit + 1)
+        .load(_conn)
+    {
+        Ok(res) => user_ids = res,
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+
+    let mut result = Page::from_rows(user_ids, page, |_| 0);
+    if result.next_cursor.is_some() {
+        result.next_cursor = Some(offset + page.limit as i32);
+    }
+    Ok(Page {
+        items: get_public_profiles(_conn, &result.items, Some(searcher_user_id))?,
+        next_cursor: result.next_cursor,
+    })
+}
@@ -0,0 +1,275 @@
+use crate::api_models::VerificationStartedResponse;
+use crate::auth_lib::hash_token_id;
+use crate::db_models::{QUsers, QVerificationCodes, VerificationChannel, VerificationCodes};
+use crate::delivery::{MessageSender, OutgoingMessage};
+use crate::errors::ChatuzaError;
+use crate::get_user_with_user_id;
+use crate::schema::{users, verification_codes};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+pub use diesel;
+pub use diesel::pg::PgConnection;
+pub use diesel::prelude::*;
+
+pub const VERIFICATION_CODE_TTL_SECS: i64 = 15 * 60;
+// wrong guesses allowed on a single code before a new one has to be requested
+pub const MAX_VERIFICATION_ATTEMPTS: i32 = 5;
+pub const VERIFICATION_RESEND_COOLDOWN_SECS: i64 = 60;
+pub const MAX_VERIFICATION_CODES_PER_HOUR: i64 = 5;
+
+// -- phone numbers -- //
+
+// turns "+1 (415) 555-0100" or "0044 20 7946 0958" into "+14155550100" / "+442079460958",
+// numbers without the country code can't be told apart so they are rejected
+pub fn normalize_phone_number(raw_phone_number: &str) -> Result<String, ChatuzaError> {
+    let invalid = || {
+        ChatuzaError::Validation(format!(
+            "{} isn't an international phone number (e.g. +14155550100)",
+            raw_phone_number
+        ))
+    };
+
+    let trimmed: String = raw_phone_number
+        .chars()
+        .filter(|res| !matches!(res, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+    let digits = if let Some(res) = trimmed.strip_prefix('+') {
+        res
+    } else if let Some(res) = trimmed.strip_prefix("00") {
+        res
+    } else {
+        return Err(invalid());
+    };
+
+    // e.164: up to 15 digits and country codes never start with 0
+    if digits.len() < 8
+        || digits.len() > 15
+        || digits.starts_with('0')
+        || !digits.chars().all(|res| res.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    Ok(format!("+{}", digits))
+}
+
+// -- Verification SETTER functions -- //
+
+// six digits, drawn without the modulo bias
+fn new_verification_code() -> String {
+    loop {
+        let candidate = OsRng.next_u32();
+        if candidate < u32::MAX - (u32::MAX % 1_000_000) {
+            return format!("{:06}", candidate % 1_000_000);
+        }
+    }
+}
+
+fn verification_target(user: &QUsers, channel: VerificationChannel) -> String {
+    match channel {
+        VerificationChannel::Email => user.email.clone(),
+        VerificationChannel::Phone => user.phone_number.clone(),
+    }
+}
+
+// sends a fresh code to the current email or phone number of the user, the codes sent before
+// stop working. the insert is rolled back when the sender doesn't take the message
+pub fn start_verification(
+    _conn: &mut PgConnection,
+    sender: &dyn MessageSender,
+    _user_id: i32,
+    channel: VerificationChannel,
+) -> Result<VerificationStartedResponse, ChatuzaError> {
+    let user = get_user_with_user_id(_conn, _user_id)?;
+    let already_verified = match channel {
+        VerificationChannel::Email => user.email_verified_at.is_some(),
+        VerificationChannel::Phone => user.phone_verified_at.is_some(),
+    };
+    if already_verified {
+        return Err(ChatuzaError::AlreadyExists(format!(
+            "the {} of user id {} is already verified",
+            channel.as_str(),
+            _user_id
+        )));
+    }
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        let now = Utc::now().naive_utc();
+        let recent_codes: Vec<QVerificationCodes>;
+        match verification_codes::table
+            .filter(
+                verification_codes::user_id
+                    .eq(_user_id)
+                    .and(verification_codes::channel.eq(channel))
+                    .and(verification_codes::created_at.gt(now - Duration::hours(1))),
+            )
+            .order(verification_codes::verification_id.desc())
+            .select(QVerificationCodes::as_select())
+            .load(_conn)
+        {
+            Ok(res) => recent_codes = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+        if recent_codes.len() as i64 >= MAX_VERIFICATION_CODES_PER_HOUR {
+            return Err(ChatuzaError::TooManyRequests(format!(
+                "too many {} verification codes requested, try again later",
+                channel.as_str()
+            )));
+        }
+        if let Some(last_code) = recent_codes.first() {
+            if last_code.created_at > now - Duration::seconds(VERIFICATION_RESEND_COOLDOWN_SECS) {
+                return Err(ChatuzaError::TooManyRequests(format!(
+                    "a {} verification code was just sent, wait {} seconds before asking again",
+                    channel.as_str(),
+                    VERIFICATION_RESEND_COOLDOWN_SECS
+                )));
+            }
+        }
+
+        // only the latest code can be used
+        if let Err(e) = diesel::update(
+            verification_codes::table.filter(
+                verification_codes::user_id
+                    .eq(_user_id)
+                    .and(verification_codes::channel.eq(channel))
+                    .and(verification_codes::consumed_at.is_null()),
+            ),
+        )
+        .set(verification_codes::consumed_at.eq(now))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+
+        let code = new_verification_code();
+        let target = verification_target(&user, channel);
+        let new_code;
+        match diesel::insert_into(verification_codes::table)
+            .values(&VerificationCodes {
+                user_id: _user_id,
+                channel,
+                target: target.clone(),
+                code_hash: hash_token_id(&code),
+                expires_at: now + Duration::seconds(VERIFICATION_CODE_TTL_SECS),
+            })
+            .returning(QVerificationCodes::as_returning())
+            .get_result(_conn)
+        {
+            Ok(res) => new_code = res,
+            Err(e) => return Err(ChatuzaError::Database(e)),
+        }
+
+        sender.send(&OutgoingMessage {
+            channel,
+            recipient: target,
+            subject: "Your Chatuza verification code".to_owned(),
+            body: format!(
+                "Your Chatuza verification code is {}. It expires in {} minutes.",
+                code,
+                VERIFICATION_CODE_TTL_SECS / 60
+            ),
+        })?;
+
+        Ok(VerificationStartedResponse {
+            channel,
+            expires_at: new_code.expires_at,
+            attempts_left: MAX_VERIFICATION_ATTEMPTS,
+        })
+    })
+}
+
+// marks the email or phone number as verified, a wrong code burns one attempt of the code
+pub fn confirm_verification(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+    channel: VerificationChannel,
+    submitted_code: &str,
+) -> Result<QUsers, ChatuzaError> {
+    let user = get_user_with_user_id(_conn, _user_id)?;
+
+    let latest_code: QVerificationCodes;
+    match verification_codes::table
+        .filter(
+            verification_codes::user_id
+                .eq(_user_id)
+                .and(verification_codes::channel.eq(channel))
+                .and(verification_codes::consumed_at.is_null()),
+        )
+        .order(verification_codes::verification_id.desc())
+        .select(QVerificationCodes::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(res)) => latest_code = res,
+        Ok(None) => {
+            return Err(ChatuzaError::NotFound(
+                "pending verification code for",
+                channel.as_str().to_owned(),
+            ))
+        }
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+
+    let now = Utc::now().naive_utc();
+    if latest_code.expires_at <= now {
+        return Err(ChatuzaError::Validation(
+            "the verification code expired, request a new one".to_owned(),
+        ));
+    }
+    if latest_code.attempts >= MAX_VERIFICATION_ATTEMPTS {
+        return Err(ChatuzaError::TooManyRequests(
+            "too many wrong verification codes, request a new one".to_owned(),
+        ));
+    }
+    // the address changed after the code got sent
+    if latest_code.target != verification_target(&user, channel) {
+        return Err(ChatuzaError::Validation(format!(
+            "the {} changed since the code was sent, request a new one",
+            channel.as_str()
+        )));
+    }
+
+    if hash_token_id(submitted_code.trim()) != latest_code.code_hash {
+        // the attempt counts even though the request fails, so it isn't run in a transaction
+        if let Err(e) = diesel::update(
+            verification_codes::table
+                .filter(verification_codes::verification_id.eq(latest_code.verification_id)),
+        )
+        .set(verification_codes::attempts.eq(verification_codes::attempts + 1))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+        return Err(ChatuzaError::Validation(format!(
+            "wrong verification code, {} attempts left",
+            (MAX_VERIFICATION_ATTEMPTS - latest_code.attempts - 1).max(0)
+        )));
+    }
+
+    _conn.transaction::<_, ChatuzaError, _>(|_conn| {
+        if let Err(e) = diesel::update(
+            verification_codes::table
+                .filter(verification_codes::verification_id.eq(latest_code.verification_id)),
+        )
+        .set(verification_codes::consumed_at.eq(now))
+        .execute(_conn)
+        {
+            return Err(ChatuzaError::Database(e));
+        }
+
+        let user_filter = users::table.filter(users::user_id.eq(_user_id));
+        let updated = match channel {
+            VerificationChannel::Email => diesel::update(user_filter)
+                .set(users::email_verified_at.eq(now))
+                .returning(QUsers::as_returning())
+                .get_result(_conn),
+            VerificationChannel::Phone => diesel::update(user_filter)
+                .set(users::phone_verified_at.eq(now))
+                .returning(QUsers::as_returning())
+                .get_result(_conn),
+        };
+        match updated {
+            Ok(res) => Ok(res),
+            Err(e) => Err(ChatuzaError::Database(e)),
+        }
+    })
+}
@@ -0,0 +1,346 @@
+use crate::chain_client::ChainClient;
+use crate::errors::ChatuzaError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use dotenvy::dotenv;
+use rocket::Config;
+use serde::Deserialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::signer::{EncodableKey, Signer, SignerError};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolanaCluster {
+    Localnet,
+    Devnet,
+    Mainnet,
+}
+
+impl SolanaCluster {
+    pub fn default_rpc_url(&self) -> &'static str {
+        match self {
+            SolanaCluster::Localnet => "http://127.0.0.1:8899",
+            SolanaCluster::Devnet => "https://api.devnet.solana.com",
+            SolanaCluster::Mainnet => "https://api.mainnet-beta.solana.com",
+        }
+    }
+}
+
+impl FromStr for SolanaCluster {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "localnet" => Ok(SolanaCluster::Localnet),
+            "devnet" => Ok(SolanaCluster::Devnet),
+            "mainnet" | "mainnet-beta" => Ok(SolanaCluster::Mainnet),
+            other => Err(format!(
+                "{} isn't a solana cluster (localnet, devnet or mainnet)",
+                other
+            )),
+        }
+    }
+}
+
+// where the keypair paying for the activations and the token accounts comes from
+#[derive(Debug, Clone)]
+pub enum TreasurySource {
+    // a keypair json file as written by `solana-keygen`
+    File(PathBuf),
+    // the name of an env var holding the same json byte array
+    Env(String),
+    // the key stays in a signing service, see `RemoteSigner`
+    ExternalSigner { url: String, pubkey: Pubkey },
+}
+
+// read from Rocket.toml, every `solana_*` key can be overridden by the env var of the same name
+// in upper case (e.g. SOLANA_RPC_URL). anything missing or invalid stops the server at startup
+#[derive(Debug, Clone)]
+pub struct WalletConfig {
+    pub cluster: SolanaCluster,
+    pub rpc_url: String,
+    pub commitment: CommitmentConfig,
+    pub treasury_source: TreasurySource,
+    // lamports sent by /fund-wallet to activate a wallet
+    pub activation_lamports: u64,
+    // tokens, in the mint's base units, sent to every token account made by /create-token-account
+    pub token_account_funding_amount: u64,
+    // where those tokens come from, the treasury's associated token account of the mint if None.
+    // when set only token accounts of its mint can be funded
+    pub treasury_token_account: Option<Pubkey>,
+}
+
+fn invalid_setting(key: &str, reason: String) -> ChatuzaError {
+    ChatuzaError::Internal(format!("invalid {} setting: {}", key, reason))
+}
+
+// the env var wins over Rocket.toml
+fn string_setting(config: &Config, key: &str) -> Option<String> {
+    match env::var(key.to_uppercase()) {
+        Ok(res) if !res.is_empty() => Some(res),
+        _ => config.get_str(key).ok().map(|res| res.to_owned()),
+    }
+}
+
+fn required_setting(config: &Config, key: &str) -> Result<String, ChatuzaError> {
+    match string_setting(config, key) {
+        Some(res) => Ok(res),
+        None => Err(invalid_setting(key, "it's missing".to_owned())),
+    }
+}
+
+fn amount_setting(config: &Config, key: &str, default: u64) -> Result<u64, ChatuzaError> {
+    let amount = match env::var(key.to_uppercase()) {
+        Ok(res) if !res.is_empty() => res.parse::<i64>().ok(),
+        _ => match config.get_int(key) {
+            Ok(res) => Some(res),
+            Err(rocket::config::ConfigError::Missing(_)) => return Ok(default),
+            Err(_) => None,
+        },
+    };
+    match amount {
+        Some(res) if res > 0 => Ok(res as u64),
+        _ => Err(invalid_setting(
+            key,
+            "it must be a positive integer".to_owned(),
+        )),
+    }
+}
+
+impl WalletConfig {
+    pub fn from_rocket_config(config: &Config) -> Result<Self, ChatuzaError> {
+        dotenv().ok();
+
+        let cluster = match SolanaCluster::from_str(&required_setting(config, "solana_cluster")?) {
+            Ok(res) => res,
+            Err(e) => return Err(invalid_setting("solana_cluster", e)),
+        };
+        let rpc_url = string_setting(config, "solana_rpc_url")
+            .unwrap_or(cluster.default_rpc_url().to_owned());
+        if !rpc_url.starts_with("http://") && !rpc_url.starts_with("https://") {
+            return Err(invalid_setting(
+                "solana_rpc_url",
+                format!("{} isn't an http(s) url", rpc_url),
+            ));
+        }
+        let commitment = match string_setting(config, "solana_commitment") {
+            None => CommitmentConfig::confirmed(),
+            Some(res) => match res.as_str() {
+                "processed" => CommitmentConfig::processed(),
+                "confirmed" => CommitmentConfig::confirmed(),
+                "finalized" => CommitmentConfig::finalized(),
+                other => {
+                    return Err(invalid_setting(
+                        "solana_commitment",
+                        format!("{} isn't processed, confirmed or finalized", other),
+                    ))
+                }
+            },
+        };
+
+        let treasury_source = match required_setting(config, "solana_treasury_source")?.as_str() {
+            "file" => TreasurySource::File(PathBuf::from(required_setting(
+                config,
+                "solana_treasury_keypair_path",
+            )?)),
+            "env" => TreasurySource::Env(
+                string_setting(config, "solana_treasury_keypair_env")
+                    .unwrap_or("SOLANA_TREASURY_KEYPAIR".to_owned()),
+            ),
+            "external" => {
+                let pubkey = required_setting(config, "solana_treasury_pubkey")?;
+                TreasurySource::ExternalSigner {
+                    url: required_setting(config, "solana_external_signer_url")?,
+                    pubkey: match Pubkey::from_str(&pubkey) {
+                        Ok(res) => res,
+                        Err(_) => {
+                            return Err(invalid_setting(
+                                "solana_treasury_pubkey",
+                                format!("{} isn't a valid public key", pubkey),
+                            ))
+                        }
+                    },
+                }
+            }
+            other => {
+                return Err(invalid_setting(
+                    "solana_treasury_source",
+                    format!("{} isn't file, env or external", other),
+                ))
+            }
+        };
+
+        let treasury_token_account = match string_setting(config, "solana_treasury_token_account") {
+            None => None,
+            Some(res) => match Pubkey::from_str(&res) {
+                Ok(res) => Some(res),
+                Err(_) => {
+                    return Err(invalid_setting(
+                        "solana_treasury_token_account",
+                        format!("{} isn't a valid public key", res),
+                    ))
+                }
+            },
+        };
+
+        Ok(WalletConfig {
+            cluster,
+            rpc_url,
+            commitment,
+            treasury_source,
+            activation_lamports: amount_setting(config, "solana_activation_lamports", 100_000_000)?,
+            token_account_funding_amount: amount_setting(
+                config,
+                "solana_token_account_funding_amount",
+                1_000_000,
+            )?,
+            treasury_token_account,
+        })
+    }
+}
+
+// -- external signer -- //
+
+// posts `{ "pubkey", "message" (base64) }` to the signing service and expects
+// `{ "signature" (base58) }` back, with the SOLANA_EXTERNAL_SIGNER_TOKEN env var as bearer token
+pub struct RemoteSigner {
+    url: String,
+    pubkey: Pubkey,
+    api_token: String,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, pubkey: Pubkey) -> Self {
+        dotenv().ok();
+        RemoteSigner {
+            url: url.to_owned(),
+            pubkey,
+            api_token: env::var("SOLANA_EXTERNAL_SIGNER_TOKEN").unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RemoteSignature {
+    signature: String,
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let response: RemoteSignature = match ureq::post(&self.url)
+            .timeout(Duration::from_secs(10))
+            .set("Authorization", &format!("Bearer {}", self.api_token))
+            .send_json(serde_json::json!({
+                "pubkey": self.pubkey.to_string(),
+                "message": STANDARD.encode(message),
+            }))
+            .map_err(|e| e.to_string())
+            .and_then(|res| res.into_json().map_err(|e| e.to_string()))
+        {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(SignerError::Custom(format!(
+                    "external signer failed: {}",
+                    e
+                )))
+            }
+        };
+
+        match Signature::from_str(&response.signature) {
+            Ok(res) if res.verify(self.pubkey.as_ref(), message) => Ok(res),
+            _ => Err(SignerError::Custom(
+                "the external signer returned an invalid signature".to_owned(),
+            )),
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+// -- context -- //
+
+// what the wallet functions run with, managed by rocket
+pub struct SolanaContext {
+    pub config: WalletConfig,
+    pub treasury: Box<dyn Signer + Send + Sync>,
+    pub chain: Box<dyn ChainClient>,
+}
+
+impl SolanaContext {
+    // e.g. with a `MockChainClient` and a throwaway keypair
+    pub fn new(
+        config: WalletConfig,
+        treasury: Box<dyn Signer + Send + Sync>,
+        chain: Box<dyn ChainClient>,
+    ) -> Self {
+        SolanaContext {
+            config,
+            treasury,
+            chain,
+        }
+    }
+
+    pub fn treasury_pubkey(&self) -> Pubkey {
+        self.treasury.pubkey()
+    }
+}
+
+fn load_treasury(source: &TreasurySource) -> Result<Box<dyn Signer + Send + Sync>, ChatuzaError> {
+    match source {
+        TreasurySource::File(path) => match Keypair::read_from_file(path) {
+            Ok(res) => Ok(Box::new(res)),
+            Err(e) => Err(ChatuzaError::Internal(format!(
+                "couldn't read the treasury keypair {} due to \n {}",
+                path.display(),
+                e
+            ))),
+        },
+        TreasurySource::Env(var_name) => {
+            let bytes: Vec<u8> = match env::var(var_name)
+                .ok()
+                .and_then(|res| serde_json::from_str(&res).ok())
+            {
+                Some(res) => res,
+                None => {
+                    return Err(ChatuzaError::Internal(format!(
+                        "{} must hold the treasury keypair as a json byte array",
+                        var_name
+                    )))
+                }
+            };
+            match Keypair::from_bytes(&bytes) {
+                Ok(res) => Ok(Box::new(res)),
+                Err(e) => Err(ChatuzaError::Internal(format!(
+                    "invalid treasury keypair in {} due to \n {}",
+                    var_name, e
+                ))),
+            }
+        }
+        TreasurySource::ExternalSigner { url, pubkey } => {
+            Ok(Box::new(RemoteSigner::new(url, *pubkey)))
+        }
+    }
+}
+
+pub fn init_solana_context(config: &WalletConfig) -> Result<SolanaContext, ChatuzaError> {
+    Ok(SolanaContext::new(
+        config.clone(),
+        load_treasury(&config.treasury_source)?,
+        Box::new(RpcClient::new_with_commitment(
+            config.rpc_url.clone(),
+            config.commitment,
+        )),
+    ))
+}
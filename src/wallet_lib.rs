@@ -1,43 +1,60 @@
 use crate::api_models::{CreateTokenAccount, CreateTokenAccountResponse};
-use crate::db_models::{QSolanaWallet, SolanaWallet};
+use crate::db_models::{
+    FundingStatus, QSolanaWallet, QTokenAccountFundings, SolanaWallet, TokenAccountFundings,
+};
+use crate::errors::ChatuzaError;
 use crate::schema::solana_wallets;
 use crate::schema::solana_wallets::dsl::*;
+use crate::schema::token_account_fundings;
+use crate::wallet_config::SolanaContext;
 use crate::{get_user_with_username, is_valid_user};
+use chrono::{NaiveDateTime, Utc};
 pub use diesel;
 pub use diesel::pg::PgConnection;
 pub use diesel::prelude::*;
 pub use diesel::result::Error;
 pub use dotenvy::dotenv;
-use solana_client::rpc_client::RpcClient;
+use log::warn;
 use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::{Keypair, Signature};
-use solana_sdk::signer::{EncodableKey, Signer};
-use solana_sdk::system_instruction::transfer;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::signature::Signature;
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use spl_associated_token_account::get_associated_token_address_with_program_id;
-use spl_associated_token_account::instruction::create_associated_token_account;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use spl_token_2022::instruction::transfer_checked;
+use spl_token_2022::state::{Account as TokenAccount, Mint};
 pub use std::env;
 use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+// sends signed on a blockhash that turned out unknown, with a backoff doubling from
+// SEND_BACKOFF_MILLIS in between
+const SEND_ATTEMPTS: u32 = 4;
+const SEND_BACKOFF_MILLIS: u64 = 500;
+// longer than the slowest confirmation, a crashed request doesn't hold the row for good
+const FUNDING_LOCK_SECS: i64 = 120;
 
 pub fn initialize_new_solana_wallet(
     _conn: &mut PgConnection,
     _new_wallet_info: &SolanaWallet,
-) -> Result<QSolanaWallet, Box<dyn std::error::Error>> {
+) -> Result<QSolanaWallet, ChatuzaError> {
     // Checking if user already has a wallet
     if get_user_solana_wallet(_conn, _new_wallet_info.user_id).is_ok() {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
+        return Err(ChatuzaError::AlreadyExists(
             "User already has a wallet".to_owned(),
-        )));
+        ));
     }
 
     // Checking if user ID is valid
     if !is_valid_user(_conn, _new_wallet_info.user_id) {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("user id {} not found !", _new_wallet_info.user_id),
-        )));
+        return Err(ChatuzaError::NotFound(
+            "user id",
+            _new_wallet_info.user_id.to_string(),
+        ));
     }
 
     match diesel::insert_into(solana_wallets::table)
@@ -46,56 +63,31 @@ pub fn initialize_new_solana_wallet(
         .get_result(_conn)
     {
         Ok(res) => Ok(res),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Couldn't initialize a Solana wallet for user ID {} \n
-                        Error: {:?}",
-                    &_new_wallet_info.user_id, e
-                )
-                .as_str(),
-            )))
-        }
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
 pub fn delete_solana_wallet(
     _conn: &mut PgConnection,
     _username: &String,
-) -> Result<bool, Box<dyn std::error::Error>> {
+) -> Result<bool, ChatuzaError> {
     let _user_id_removable: i32;
     match get_user_with_username(_conn, _username) {
         Ok(res) => _user_id_removable = res.user_id,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("{:?}", e),
-            )))
-        }
+        Err(e) => return Err(e),
     }
     match diesel::delete(solana_wallets.filter(solana_wallets::user_id.eq(_user_id_removable)))
         .execute(_conn)
     {
         Ok(_) => Ok(true),
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Couldn't delete the Solana wallet for user ID {} \n
-                        Error: {:?}",
-                    &_user_id_removable, e
-                )
-                .as_str(),
-            )))
-        }
+        Err(e) => Err(ChatuzaError::Database(e)),
     }
 }
 
 pub fn get_user_solana_wallet(
     _conn: &mut PgConnection,
     _user_id: i32,
-) -> Result<QSolanaWallet, Box<dyn std::error::Error>> {
+) -> Result<QSolanaWallet, ChatuzaError> {
     let wallets: Vec<QSolanaWallet> = solana_wallets
         .filter(solana_wallets::user_id.eq(_user_id))
         .select(QSolanaWallet::as_select())
@@ -105,97 +97,825 @@ pub fn get_user_solana_wallet(
     if wallets.len() == 1 {
         Ok(wallets.into_iter().next().unwrap()) // panic impossible
     } else {
-        Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("user id {} doesn't have a solana wallet ", _user_id),
-        )))
+        Err(ChatuzaError::NotFound(
+            "solana wallet of user id",
+            _user_id.to_string(),
+        ))
+    }
+}
+
+pub fn parse_pubkey(field: &str, value: &str) -> Result<Pubkey, ChatuzaError> {
+    match Pubkey::from_str(value.trim()) {
+        Ok(res) => Ok(res),
+        Err(_) => Err(ChatuzaError::Validation(format!(
+            "{} {} isn't a valid solana public key",
+            field, value
+        ))),
+    }
+}
+
+// the address of the wallet the user added, stored as its base58 text
+pub fn get_user_wallet_pubkey(
+    _conn: &mut PgConnection,
+    _user_id: i32,
+) -> Result<Pubkey, ChatuzaError> {
+    match get_user_solana_wallet(_conn, _user_id) {
+        Ok(res) => parse_pubkey(
+            "wallet address",
+            String::from_utf8_lossy(res.wallet_addr.as_slice()).as_ref(),
+        ),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn ensure_token_program(token_program_id: &Pubkey) -> Result<(), ChatuzaError> {
+    if *token_program_id != spl_token::id() && *token_program_id != spl_token_2022::id() {
+        return Err(ChatuzaError::Validation(format!(
+            "token program id {} is neither the token nor the token-2022 program",
+            token_program_id
+        )));
+    }
+    Ok(())
+}
+
+// the unpacking works for the mints of both token programs
+pub fn get_mint_decimals(
+    solana: &SolanaContext,
+    token_mint_address: &Pubkey,
+    token_program_id: &Pubkey,
+) -> Result<u8, ChatuzaError> {
+    let mint_account = match solana.chain.get_account(token_mint_address) {
+        Ok(Some(res)) => res,
+        Ok(None) => {
+            return Err(ChatuzaError::NotFound(
+                "token mint",
+                token_mint_address.to_string(),
+            ))
+        }
+        Err(e) => return Err(ChatuzaError::from(e)),
+    };
+    match StateWithExtensions::<Mint>::unpack(&mint_account.data) {
+        Ok(res) if mint_account.owner == *token_program_id => Ok(res.base.decimals),
+        _ => Err(ChatuzaError::Validation(format!(
+            "{} isn't a mint of the token program {}",
+            token_mint_address, token_program_id
+        ))),
+    }
+}
+
+// signs with a freshly fetched blockhash and sends. a BlockhashNotFound means the transaction
+// never landed, so it's signed again on a new blockhash after a backoff. `record` runs before
+// every send, e.g. to write the signature down first
+fn send_with_blockhash_retry<F>(
+    solana: &SolanaContext,
+    instructions: &[Instruction],
+    mut record: F,
+) -> Result<Signature, ChatuzaError>
+where
+    F: FnMut(&Transaction) -> Result<(), ChatuzaError>,
+{
+    let pk = solana.treasury_pubkey();
+    let mut attempt: u32 = 0;
+    loop {
+        let lbh: Hash;
+        match solana.chain.get_latest_blockhash() {
+            Ok(res) => lbh = res,
+            Err(e) => return Err(ChatuzaError::from(e)),
+        }
+        let mut transaction = Transaction::new_with_payer(instructions, Some(&pk));
+        if let Err(e) = transaction.try_sign(&[solana.treasury.as_ref() as &dyn Signer], lbh) {
+            return Err(ChatuzaError::Internal(format!(
+                "couldn't sign with the treasury due to \n {}",
+                e
+            )));
+        }
+        record(&transaction)?;
+
+        match solana.chain.send_and_confirm_transaction(&transaction) {
+            Ok(res) => return Ok(res),
+            Err(e) => match e.get_transaction_error() {
+                // a resend of something that landed
+                Some(TransactionError::AlreadyProcessed) => return Ok(transaction.signatures[0]),
+                Some(TransactionError::BlockhashNotFound) if attempt + 1 < SEND_ATTEMPTS => {
+                    thread::sleep(Duration::from_millis(SEND_BACKOFF_MILLIS << attempt));
+                    attempt += 1;
+                }
+                _ => return Err(ChatuzaError::from(e)),
+            },
+        }
+    }
+}
+
+// -- token account funding functions -- //
+
+// the row of the token account, locked for this request. a second request for the same account
+// gets a conflict until the first one is done
+// `charge_quota` only runs when there's no funding row yet, retrying a funding that conflicted
+// on the lock or failed on the cluster doesn't count again
+fn lock_token_account_funding(
+    _conn: &mut PgConnection,
+    _new_funding: &TokenAccountFundings,
+    charge_quota: impl FnOnce() -> Result<(), ChatuzaError>,
+) -> Result<QTokenAccountFundings, ChatuzaError> {
+    match token_account_fundings::table
+        .filter(token_account_fundings::wallet_address.eq(&_new_funding.wallet_address))
+        .filter(token_account_fundings::token_mint_address.eq(&_new_funding.token_mint_address))
+        .filter(token_account_fundings::token_program_id.eq(&_new_funding.token_program_id))
+        .select(QTokenAccountFundings::as_select())
+        .first(_conn)
+        .optional()
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            charge_quota()?;
+            if let Err(e) = diesel::insert_into(token_account_fundings::table)
+                .values(_new_funding)
+                .on_conflict_do_nothing()
+                .execute(_conn)
+            {
+                return Err(ChatuzaError::Database(e));
+            }
+        }
+        Err(e) => return Err(ChatuzaError::Database(e)),
+    }
+
+    let now = Utc::now().naive_utc();
+    match diesel::update(
+        token_account_fundings::table
+            .filter(token_account_fundings::wallet_address.eq(&_new_funding.wallet_address))
+            .filter(token_account_fundings::token_mint_address.eq(&_new_funding.token_mint_address))
+            .filter(token_account_fundings::token_program_id.eq(&_new_funding.token_program_id))
+            .filter(
+                token_account_fundings::locked_until
+                    .is_null()
+                    .or(token_account_fundings::locked_until.lt(now)),
+            ),
+    )
+    .set(
+        token_account_fundings::locked_until.eq(now + chrono::Duration::seconds(FUNDING_LOCK_SECS)),
+    )
+    .returning(QTokenAccountFundings::as_returning())
+    .get_result(_conn)
+    .optional()
+    {
+        Ok(Some(res)) => Ok(res),
+        Ok(None) => Err(ChatuzaError::AlreadyExists(format!(
+            "the token account {} is already being set up, try again shortly",
+            _new_funding.token_account_address
+        ))),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+fn unlock_token_account_funding(_conn: &mut PgConnection, _funding_id: i32) {
+    // the lock runs out by itself if this fails
+    if let Err(e) = diesel::update(
+        token_account_fundings::table.filter(token_account_fundings::funding_id.eq(_funding_id)),
+    )
+    .set(token_account_fundings::locked_until.eq(None::<NaiveDateTime>))
+    .execute(_conn)
+    {
+        warn!(
+            "couldn't unlock token account funding {} due to \n {}",
+            _funding_id, e
+        );
+    }
+}
+
+fn set_funding_status(
+    _conn: &mut PgConnection,
+    _funding_id: i32,
+    _status: FundingStatus,
+    _signature: Option<&Transaction>,
+) -> Result<(), ChatuzaError> {
+    let query = diesel::update(
+        token_account_fundings::table.filter(token_account_fundings::funding_id.eq(_funding_id)),
+    );
+    let res = match _signature {
+        Some(transaction) => query
+            .set((
+                token_account_fundings::funding_status.eq(_status),
+                token_account_fundings::funding_signature.eq(transaction.signatures[0].to_string()),
+                token_account_fundings::funding_blockhash
+                    .eq(transaction.message.recent_blockhash.to_string()),
+                token_account_fundings::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(_conn),
+        None => query
+            .set((
+                token_account_fundings::funding_status.eq(_status),
+                token_account_fundings::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(_conn),
+    };
+    match res {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ChatuzaError::Database(e)),
+    }
+}
+
+// where a submitted funding transaction is at: confirmed once the cluster has it, unfunded (so
+// it can be sent again) when it failed or its blockhash expired without it landing, otherwise
+// still submitted and left alone
+fn resolve_submitted_funding(
+    solana: &SolanaContext,
+    funding: &QTokenAccountFundings,
+) -> Result<FundingStatus, ChatuzaError> {
+    let (signature, blockhash) = match (&funding.funding_signature, &funding.funding_blockhash) {
+        (Some(sig), Some(bh)) => match (Signature::from_str(sig), Hash::from_str(bh)) {
+            (Ok(sig), Ok(bh)) => (sig, bh),
+            _ => {
+                return Err(ChatuzaError::Internal(format!(
+                    "token account funding {} has an unreadable signature or blockhash",
+                    funding.funding_id
+                )))
+            }
+        },
+        _ => return Ok(FundingStatus::Unfunded),
+    };
+
+    match solana.chain.get_signature_status(&signature)? {
+        Some(Ok(())) => return Ok(FundingStatus::Confirmed),
+        Some(Err(_)) => return Ok(FundingStatus::Unfunded),
+        None => {}
+    }
+    if solana.chain.is_blockhash_valid(&blockhash)? {
+        return Ok(FundingStatus::Submitted);
+    }
+    // it could have landed right before the blockhash expired
+    match solana.chain.get_signature_status(&signature)? {
+        Some(Ok(())) => Ok(FundingStatus::Confirmed),
+        _ => Ok(FundingStatus::Unfunded),
+    }
+}
+
+// where the funding transfer takes the tokens from and the decimals it's checked against
+struct TokenFundingSource {
+    token_account: Pubkey,
+    decimals: u8,
+}
+
+// checked before anything is sent, so an empty or misconfigured treasury doesn't leave token
+// accounts made but never funded
+fn preflight_token_funding(
+    solana: &SolanaContext,
+    token_mint_address: &Pubkey,
+    token_program_id: &Pubkey,
+) -> Result<TokenFundingSource, ChatuzaError> {
+    let pk = solana.treasury_pubkey();
+    let amount = solana.config.token_account_funding_amount;
+    let source = match solana.config.treasury_token_account {
+        Some(res) => res,
+        None => {
+            get_associated_token_address_with_program_id(&pk, token_mint_address, token_program_id)
+        }
+    };
+
+    let decimals = get_mint_decimals(solana, token_mint_address, token_program_id)?;
+
+    let source_account = match solana.chain.get_account(&source) {
+        Ok(Some(res)) => res,
+        Ok(None) => {
+            return Err(ChatuzaError::Internal(format!(
+                "the treasury token account {} doesn't exist",
+                source
+            )))
+        }
+        Err(e) => return Err(ChatuzaError::from(e)),
+    };
+    let source_state = match StateWithExtensions::<TokenAccount>::unpack(&source_account.data) {
+        Ok(res) if source_account.owner == *token_program_id => res.base,
+        _ => {
+            return Err(ChatuzaError::Internal(format!(
+                "the treasury token account {} isn't an account of the token program {}",
+                source, token_program_id
+            )))
+        }
+    };
+    if source_state.mint != *token_mint_address {
+        // a configured treasury token account only funds its own mint
+        return Err(ChatuzaError::Validation(format!(
+            "the treasury only funds token accounts of mint {}",
+            source_state.mint
+        )));
+    }
+    if source_state.owner != pk || source_state.is_frozen() {
+        return Err(ChatuzaError::Internal(format!(
+            "the treasury can't transfer out of the token account {}",
+            source
+        )));
+    }
+    if source_state.amount < amount {
+        return Err(ChatuzaError::Internal(format!(
+            "the treasury token account {} holds {} of the {} needed",
+            source, source_state.amount, amount
+        )));
     }
+
+    Ok(TokenFundingSource {
+        token_account: source,
+        decimals,
+    })
 }
 
+// makes the associated token account of the wallet if it doesn't exist yet and funds it once.
+// safe to call again after any failure: the account is made with the idempotent instruction and
+// the funding signature is stored before it's sent, so a retry looks it up instead of paying twice
 pub fn create_token_account(
+    _conn: &mut PgConnection,
+    solana: &SolanaContext,
+    _user_id: i32,
     token_account_info: &CreateTokenAccount,
-) -> Result<CreateTokenAccountResponse, Box<dyn std::error::Error>> {
-    let kp =
-        Keypair::read_from_file("/home/javad/Desktop/chatuza_all/chatuza_db/sec.json").unwrap();
-    let pk = kp.try_pubkey().unwrap();
-    let rpc = RpcClient::new("https://api.devnet.solana.com".to_string());
-
-    match rpc.send_and_confirm_transaction(&Transaction::new_signed_with_payer(
-        &[create_associated_token_account(
-            &pk,
-            &Pubkey::from_str(token_account_info.wallet_address.as_str()).unwrap(),
-            &Pubkey::from_str(token_account_info.token_mint_address.as_str()).unwrap(),
-            &Pubkey::from_str(token_account_info.token_program_id.as_str()).unwrap(),
-        )],
-        Some(&pk),
-        &[&kp],
-        Hash::from_str(token_account_info.lbh.as_str()).unwrap(),
-    )) {
-        Ok(sig) => {
-            // funding the account
-            match rpc.send_and_confirm_transaction(&Transaction::new_signed_with_payer(
-                &[transfer(
-                    &pk,
-                    &get_associated_token_address_with_program_id(
-                        &Pubkey::from_str(token_account_info.wallet_address.as_str()).unwrap(),
-                        &Pubkey::from_str(token_account_info.token_mint_address.as_str()).unwrap(),
-                        &Pubkey::from_str(token_account_info.token_program_id.as_str()).unwrap(),
-                    ),
-                    1_000_000,
-                )],
-                Some(&pk),
-                &[&kp],
-                Hash::from_str(token_account_info.lbh.as_str()).unwrap(),
-            )) {
-                Ok(sig2) => Ok(CreateTokenAccountResponse {
-                    signatures: vec![sig.to_string(), sig2.to_string()],
-                }),
-                Err(e2) => Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    format!("{}", e2),
-                ))),
+    charge_quota: impl FnOnce() -> Result<(), ChatuzaError>,
+) -> Result<CreateTokenAccountResponse, ChatuzaError> {
+    let wallet_address = parse_pubkey("wallet address", &token_account_info.wallet_address)?;
+    let token_mint_address =
+        parse_pubkey("token mint address", &token_account_info.token_mint_address)?;
+    let token_program_id = parse_pubkey("token program id", &token_account_info.token_program_id)?;
+    ensure_token_program(&token_program_id)?;
+    let token_account_address = get_associated_token_address_with_program_id(
+        &wallet_address,
+        &token_mint_address,
+        &token_program_id,
+    );
+
+    let funding = lock_token_account_funding(
+        _conn,
+        &TokenAccountFundings {
+            user_id: _user_id,
+            wallet_address: wallet_address.to_string(),
+            token_mint_address: token_mint_address.to_string(),
+            token_program_id: token_program_id.to_string(),
+            token_account_address: token_account_address.to_string(),
+        },
+        charge_quota,
+    )?;
+    let funding_id = funding.funding_id;
+    let res = run_token_account_funding(
+        _conn,
+        solana,
+        funding,
+        &token_account_address,
+        &wallet_address,
+        &token_mint_address,
+        &token_program_id,
+    );
+    // whatever happened, the row already holds what got sent
+    unlock_token_account_funding(_conn, funding_id);
+    res
+}
+
+fn run_token_account_funding(
+    _conn: &mut PgConnection,
+    solana: &SolanaContext,
+    mut funding: QTokenAccountFundings,
+    token_account_address: &Pubkey,
+    wallet_address: &Pubkey,
+    token_mint_address: &Pubkey,
+    token_program_id: &Pubkey,
+) -> Result<CreateTokenAccountResponse, ChatuzaError> {
+    let pk = solana.treasury_pubkey();
+    let mut signatures: Vec<String> = vec![];
+    let mut funding_source: Option<TokenFundingSource> = None;
+    if funding.funding_status == FundingStatus::Unfunded {
+        funding_source = Some(preflight_token_funding(
+            solana,
+            token_mint_address,
+            token_program_id,
+        )?);
+    }
+
+    // the account
+    if funding.token_account_signature.is_none() {
+        match solana.chain.get_account(token_account_address) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let sig = send_with_blockhash_retry(
+                    solana,
+                    &[create_associated_token_account_idempotent(
+                        &pk,
+                        wallet_address,
+                        token_mint_address,
+                        token_program_id,
+                    )],
+                    |_| Ok(()),
+                )?;
+                if let Err(e) = diesel::update(
+                    token_account_fundings::table
+                        .filter(token_account_fundings::funding_id.eq(funding.funding_id)),
+                )
+                .set((
+                    token_account_fundings::token_account_created.eq(true),
+                    token_account_fundings::token_account_signature.eq(sig.to_string()),
+                    token_account_fundings::updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(_conn)
+                {
+                    return Err(ChatuzaError::Database(e));
+                }
+                funding.token_account_created = true;
+                funding.token_account_signature = Some(sig.to_string());
+                signatures.push(sig.to_string());
             }
+            Err(e) => return Err(ChatuzaError::from(e)),
         }
-        Err(e) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            format!("{}", e),
-        ))),
     }
+
+    // funding the account
+    if funding.funding_status == FundingStatus::Submitted {
+        let status = resolve_submitted_funding(solana, &funding)?;
+        if status != FundingStatus::Submitted {
+            set_funding_status(_conn, funding.funding_id, status, None)?;
+        }
+        funding.funding_status = status;
+    }
+    if funding.funding_status == FundingStatus::Unfunded {
+        // a submitted transfer that never landed wasn't checked yet
+        let funding_source = match funding_source {
+            Some(res) => res,
+            None => preflight_token_funding(solana, token_mint_address, token_program_id)?,
+        };
+        let transfer = match transfer_checked(
+            token_program_id,
+            &funding_source.token_account,
+            token_mint_address,
+            token_account_address,
+            &pk,
+            &[],
+            solana.config.token_account_funding_amount,
+            funding_source.decimals,
+        ) {
+            Ok(res) => res,
+            Err(e) => return Err(ChatuzaError::Internal(format!("{}", e))),
+        };
+        let funding_id = funding.funding_id;
+        let sig = send_with_blockhash_retry(solana, &[transfer], |transaction| {
+            set_funding_status(
+                _conn,
+                funding_id,
+                FundingStatus::Submitted,
+                Some(transaction),
+            )
+        })?;
+        set_funding_status(_conn, funding_id, FundingStatus::Confirmed, None)?;
+        funding.funding_status = FundingStatus::Confirmed;
+        funding.funding_signature = Some(sig.to_string());
+        signatures.push(sig.to_string());
+    }
+
+    Ok(CreateTokenAccountResponse {
+        token_account_address: funding.token_account_address,
+        token_account_created: funding.token_account_created,
+        token_account_signature: funding.token_account_signature,
+        funding_status: funding.funding_status,
+        funding_signature: funding.funding_signature,
+        signatures,
+    })
 }
 
 // we activate the the account of the user in exchange of some transferable spl token if not activated before
 pub fn activate_wallet_account_for_transfer(
+    solana: &SolanaContext,
     wallet_pubkey: String,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let kp =
-        Keypair::read_from_file("/home/javad/Desktop/chatuza_all/chatuza_db/sec.json").unwrap();
-    let pk: Pubkey = kp.try_pubkey().unwrap();
-    let rpc = RpcClient::new("https://api.devnet.solana.com".to_string());
-    let recipient_pk: Pubkey = Pubkey::from_str(wallet_pubkey.as_str()).unwrap(); // panic impossible
-    let lbh: Hash;
-    match rpc.get_latest_blockhash() {
-        Ok(_lbh) => lbh = _lbh,
-        Err(e) => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("couldn't fetch the latest block hash due to \n {}", e),
-            )))
-        }
-    }
-    match rpc.send_and_confirm_transaction(&Transaction::new_signed_with_payer(
+) -> Result<String, ChatuzaError> {
+    let pk: Pubkey = solana.treasury_pubkey();
+    let recipient_pk: Pubkey = parse_pubkey("wallet address", &wallet_pubkey)?;
+    match send_with_blockhash_retry(
+        solana,
         &[solana_sdk::system_instruction::transfer(
             &pk,
             &recipient_pk,
-            100_000_000,
+            solana.config.activation_lamports,
         )],
-        Some(&pk),
-        &[&kp],
-        lbh,
-    )) {
+        |_| Ok(()),
+    ) {
         Ok(sig) => Ok(sig.to_string()),
-        Err(e) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!(" couldn't fund the recipient due to \n {}", e),
-        ))),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::add_new_user;
+    use crate::chain_client::MockChainClient;
+    use crate::db_models::{UserProfiles, Users};
+    use crate::wallet_config::{SolanaCluster, TreasurySource, WalletConfig};
+    use solana_client::client_error::ClientErrorKind;
+    use solana_client::rpc_client::RpcClient;
+    use solana_sdk::account::Account;
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::program_option::COption;
+    use solana_sdk::program_pack::Pack;
+    use solana_sdk::signature::Keypair;
+    use spl_token_2022::state::AccountState;
+    use std::cell::Cell;
+
+    const FUNDING_AMOUNT: u64 = 100;
+
+    fn test_config(cluster: SolanaCluster) -> WalletConfig {
+        WalletConfig {
+            cluster,
+            rpc_url: cluster.default_rpc_url().to_owned(),
+            commitment: CommitmentConfig::confirmed(),
+            treasury_source: TreasurySource::Env("SOLANA_TREASURY_KEYPAIR".to_owned()),
+            activation_lamports: 1_000_000,
+            token_account_funding_amount: FUNDING_AMOUNT,
+            treasury_token_account: None,
+        }
+    }
+
+    // the context the functions get and a handle on the same mock chain
+    fn mock_context() -> (SolanaContext, MockChainClient) {
+        let chain = MockChainClient::new();
+        let solana = SolanaContext::new(
+            test_config(SolanaCluster::Localnet),
+            Box::new(Keypair::new()),
+            Box::new(chain.clone()),
+        );
+        (solana, chain)
+    }
+
+    fn packed<T: Pack>(state: T) -> Vec<u8> {
+        let mut data = vec![0; T::LEN];
+        T::pack(state, &mut data).unwrap();
+        data
+    }
+
+    fn token_program_account(data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    // a mint and a treasury token account holding enough of it to fund `accounts` token accounts
+    fn mock_mint(solana: &SolanaContext, chain: &MockChainClient, accounts: u64) -> Pubkey {
+        let token_mint_address = Pubkey::new_unique();
+        chain.set_account(
+            token_mint_address,
+            token_program_account(packed(Mint {
+                mint_authority: COption::None,
+                supply: FUNDING_AMOUNT * accounts,
+                decimals: 6,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            })),
+        );
+        chain.set_account(
+            get_associated_token_address_with_program_id(
+                &solana.treasury_pubkey(),
+                &token_mint_address,
+                &spl_token::id(),
+            ),
+            token_program_account(packed(TokenAccount {
+                mint: token_mint_address,
+                owner: solana.treasury_pubkey(),
+                amount: FUNDING_AMOUNT * accounts,
+                delegate: COption::None,
+                state: AccountState::Initialized,
+                is_native: COption::None,
+                delegated_amount: 0,
+                close_authority: COption::None,
+            })),
+        );
+        token_mint_address
+    }
+
+    // the funding rows need a user, it's rolled back with the test transaction
+    fn test_connection(_username: &str, _phone_number: &str) -> (PgConnection, i32) {
+        let mut conn = crate::establish_connection();
+        conn.begin_test_transaction().unwrap();
+        let _user_id = add_new_user(
+            &mut conn,
+            &Users {
+                username: _username.to_owned(),
+                email: format!("{}@example.com", _username),
+                password: "correct horse battery staple".to_owned(),
+                phone_number: _phone_number.to_owned(),
+            },
+            &mut UserProfiles {
+                user_id: 0,
+                bio: None,
+                profile_picture: None,
+            },
+        )
+        .unwrap()
+        .user_id;
+        (conn, _user_id)
+    }
+
+    fn token_account_info(
+        wallet_address: &Pubkey,
+        token_mint_address: &Pubkey,
+    ) -> CreateTokenAccount {
+        CreateTokenAccount {
+            wallet_address: wallet_address.to_string(),
+            token_mint_address: token_mint_address.to_string(),
+            token_program_id: spl_token::id().to_string(),
+            lbh: None,
+        }
+    }
+
+    fn blockhash_not_found() -> ClientErrorKind {
+        ClientErrorKind::TransactionError(TransactionError::BlockhashNotFound)
+    }
+
+    #[test]
+    fn activation_is_signed_again_after_an_unknown_blockhash() {
+        let (solana, chain) = mock_context();
+        chain.fail_next_send(blockhash_not_found());
+
+        let sig = activate_wallet_account_for_transfer(&solana, Pubkey::new_unique().to_string())
+            .unwrap();
+
+        let submitted = chain.submitted_transactions();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].signatures[0].to_string(), sig);
+    }
+
+    #[test]
+    fn activation_resent_on_the_same_blockhash_counts_as_sent() {
+        let (solana, chain) = mock_context();
+        let wallet_address = Pubkey::new_unique().to_string();
+
+        // the same blockhash gives the same signature, the mock answers the resend with AlreadyProcessed
+        let first = activate_wallet_account_for_transfer(&solana, wallet_address.clone()).unwrap();
+        let second = activate_wallet_account_for_transfer(&solana, wallet_address).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(chain.submitted_transactions().len(), 1);
+    }
+
+    #[test]
+    fn activation_gives_up_after_the_last_attempt() {
+        let (solana, chain) = mock_context();
+        for _ in 0..SEND_ATTEMPTS {
+            chain.fail_next_send(blockhash_not_found());
+        }
+
+        let res = activate_wallet_account_for_transfer(&solana, Pubkey::new_unique().to_string());
+
+        assert!(matches!(res, Err(ChatuzaError::Chain(_))));
+        assert!(chain.submitted_transactions().is_empty());
+    }
+
+    #[test]
+    fn create_token_account_retries_an_unknown_blockhash_and_funds_once() {
+        let (mut conn, _user_id) = test_connection("wallet_lib_retry", "+14155550151");
+        let (solana, chain) = mock_context();
+        let token_mint_address = mock_mint(&solana, &chain, 1);
+        let wallet_address = Pubkey::new_unique();
+        let charged = Cell::new(0);
+        // the creation of the account is refused once
+        chain.fail_next_send(blockhash_not_found());
+
+        let res = create_token_account(
+            &mut conn,
+            &solana,
+            _user_id,
+            &token_account_info(&wallet_address, &token_mint_address),
+            || {
+                charged.set(charged.get() + 1);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert!(res.token_account_created);
+        assert_eq!(res.funding_status, FundingStatus::Confirmed);
+        assert_eq!(res.signatures.len(), 2);
+        assert_eq!(chain.submitted_transactions().len(), 2);
+
+        // asking again sends nothing and isn't charged
+        let res = create_token_account(
+            &mut conn,
+            &solana,
+            _user_id,
+            &token_account_info(&wallet_address, &token_mint_address),
+            || {
+                charged.set(charged.get() + 1);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.funding_status, FundingStatus::Confirmed);
+        assert!(res.signatures.is_empty());
+        assert_eq!(chain.submitted_transactions().len(), 2);
+        assert_eq!(charged.get(), 1);
+    }
+
+    #[test]
+    fn create_token_account_takes_an_already_processed_funding_as_sent() {
+        let (mut conn, _user_id) = test_connection("wallet_lib_processed", "+14155550152");
+        let (solana, chain) = mock_context();
+        let token_mint_address = mock_mint(&solana, &chain, 1);
+        let wallet_address = Pubkey::new_unique();
+        // the account is already there, only the funding transfer is sent
+        chain.set_account(
+            get_associated_token_address_with_program_id(
+                &wallet_address,
+                &token_mint_address,
+                &spl_token::id(),
+            ),
+            token_program_account(vec![]),
+        );
+        chain.fail_next_send(ClientErrorKind::TransactionError(
+            TransactionError::AlreadyProcessed,
+        ));
+
+        let res = create_token_account(
+            &mut conn,
+            &solana,
+            _user_id,
+            &token_account_info(&wallet_address, &token_mint_address),
+            || Ok(()),
+        )
+        .unwrap();
+
+        assert!(!res.token_account_created);
+        assert_eq!(res.funding_status, FundingStatus::Confirmed);
+        assert_eq!(res.signatures.len(), 1);
+        assert_eq!(res.funding_signature, Some(res.signatures[0].clone()));
+        assert!(chain.submitted_transactions().is_empty());
+    }
+
+    #[test]
+    fn create_token_account_doesnt_fund_twice_after_a_retried_transfer() {
+        let (mut conn, _user_id) = test_connection("wallet_lib_fund_once", "+14155550153");
+        let (solana, chain) = mock_context();
+        let token_mint_address = mock_mint(&solana, &chain, 2);
+        let wallet_address = Pubkey::new_unique();
+        let token_account_address = get_associated_token_address_with_program_id(
+            &wallet_address,
+            &token_mint_address,
+            &spl_token::id(),
+        );
+        chain.set_account(token_account_address, token_program_account(vec![]));
+        // the funding transfer is refused once
+        chain.fail_next_send(blockhash_not_found());
+
+        let res = create_token_account(
+            &mut conn,
+            &solana,
+            _user_id,
+            &token_account_info(&wallet_address, &token_mint_address),
+            || Ok(()),
+        )
+        .unwrap();
+        assert_eq!(res.funding_status, FundingStatus::Confirmed);
+        assert_eq!(chain.submitted_transactions().len(), 1);
+
+        // a request that died between the send and writing the confirmation down
+        diesel::update(token_account_fundings::table.filter(
+            token_account_fundings::token_account_address.eq(token_account_address.to_string()),
+        ))
+        .set(token_account_fundings::funding_status.eq(FundingStatus::Submitted))
+        .execute(&mut conn)
+        .unwrap();
+
+        let res = create_token_account(
+            &mut conn,
+            &solana,
+            _user_id,
+            &token_account_info(&wallet_address, &token_mint_address),
+            || Ok(()),
+        )
+        .unwrap();
+
+        // the landed transfer is looked up instead of sent again
+        assert_eq!(res.funding_status, FundingStatus::Confirmed);
+        assert!(res.signatures.is_empty());
+        assert_eq!(chain.submitted_transactions().len(), 1);
+    }
+
+    // needs a `solana-test-validator` listening on the default localnet port,
+    // run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn activation_lands_on_localnet() {
+        let config = test_config(SolanaCluster::Localnet);
+        let rpc = RpcClient::new_with_commitment(config.rpc_url.clone(), config.commitment);
+        let treasury = Keypair::new();
+        let airdrop = rpc
+            .request_airdrop(&treasury.pubkey(), 1_000_000_000)
+            .unwrap();
+        while !rpc.confirm_transaction(&airdrop).unwrap() {
+            thread::sleep(Duration::from_millis(200));
+        }
+        let activation_lamports = config.activation_lamports;
+        let solana = SolanaContext::new(config, Box::new(treasury), Box::new(rpc));
+        let wallet_address = Pubkey::new_unique();
+
+        activate_wallet_account_for_transfer(&solana, wallet_address.to_string()).unwrap();
+
+        assert_eq!(
+            solana.chain.get_balance(&wallet_address).unwrap(),
+            activation_lamports
+        );
     }
 }